@@ -0,0 +1,113 @@
+//! Stop a generation that is still running.
+use anyhow::{Error, Result};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Cancels the generations it is passed to. Clones share the same state, so one can be kept to cancel
+/// a generation that another is running, e.g. from a Ctrl-C handler.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every generation using this token. Generations that start afterwards are cancelled right away.
+    pub fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            self.inner.notify.notify_waiters();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        // Waiters are registered as soon as they are created, so a cancel between the check and the wait isn't missed.
+        let notified = self.inner.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Run a request until it finishes or the token is cancelled, whichever comes first.
+    /// For providers that have no way to stop a job once it is sent, so the request is simply dropped.
+    pub(crate) async fn run_until_cancelled<T>(
+        &self,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => Err(Error::new(Cancelled)),
+            result = request => result,
+        }
+    }
+}
+
+/// The error returned by a generation that was cancelled. Check for it with `error.is::<Cancelled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The image generation was cancelled.")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The error for a generation that was cancelled after trying to stop its job on the provider.
+/// If the job couldn't be stopped, the error says so, but it is still a `Cancelled` error.
+pub(crate) fn cancelled_error(stopped: Result<()>) -> Error {
+    match stopped {
+        Ok(()) => Error::new(Cancelled),
+        Err(e) => Error::new(Cancelled).context(format!(
+            "The image generation was cancelled, but the provider may still be running it: {}",
+            e
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_until_cancelled() -> Result<()> {
+        let cancel = CancellationToken::new();
+        assert_eq!(cancel.run_until_cancelled(async { Ok(1) }).await?, 1);
+
+        cancel.clone().cancel();
+        assert!(cancel.is_cancelled());
+        let error = cancel
+            .run_until_cancelled(std::future::pending::<Result<()>>())
+            .await
+            .err()
+            .ok_or(Error::msg("The request wasn't cancelled."))?;
+        assert!(error.is::<Cancelled>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled_error() {
+        assert!(cancelled_error(Ok(())).is::<Cancelled>());
+        let error = cancelled_error(Err(Error::msg("connection refused")));
+        assert!(error.is::<Cancelled>());
+        assert!(error.to_string().contains("connection refused"));
+    }
+}
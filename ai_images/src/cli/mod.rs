@@ -1,5 +1,5 @@
 mod parser;
 mod provider;
 
-pub use parser::GenerationParameters;
+pub use parser::{Commands, EditArgs, GenerationParameters, ParameterSource, TomlArgs};
 pub use provider::{ImageProviders, Provider};
@@ -1,5 +1,8 @@
 use super::provider::Provider;
-use crate::ImageParams;
+use crate::{
+    ImageOperation, ImageParams, InitImage, InpaintParams, MaskRect, Upscale, UpscaleParams,
+};
+use anyhow::Result;
 use clap::{Args, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -14,9 +17,91 @@ pub struct Commands {
     /// The prompt to send to the image generation API.
     #[arg(short, long)]
     pub prompt: Option<String>,
+
+    /// Start from an existing image instead of generating a new one.
+    #[command(flatten)]
+    pub edit: EditArgs,
+}
+
+/// Restyle an existing image, or repaint part of it.
+#[derive(Args, Debug, Default, PartialEq)]
+pub struct EditArgs {
+    /// An existing image to restyle, or to repaint part of if a mask is given.
+    #[arg(long)]
+    pub init_image: Option<PathBuf>,
+
+    /// A mask image for inpainting. White areas of the init image are repainted and black areas are kept.
+    #[arg(long, requires = "init_image", conflicts_with = "mask_rect")]
+    pub mask: Option<PathBuf>,
+
+    /// A rectangle of the init image to repaint, given as `x,y,width,height` in pixels.
+    #[arg(long, requires = "init_image")]
+    pub mask_rect: Option<MaskRect>,
+
+    /// How much the init image is changed, from 0 (unchanged) to 1 (replaced entirely). Defaults to 0.75.
+    #[arg(long, requires = "init_image")]
+    pub denoising_strength: Option<f32>,
+
+    /// Enlarge the init image with an upscaler instead of changing it.
+    #[arg(long, requires = "init_image", conflicts_with_all = ["mask", "mask_rect", "denoising_strength"])]
+    pub upscale: bool,
+
+    /// The upscaler to enlarge the init image with. Defaults to `R-ESRGAN 4x+`.
+    #[arg(long, requires = "upscale")]
+    pub upscaler: Option<String>,
+
+    /// How many times larger to make the init image. Defaults to 2.
+    #[arg(long, requires = "upscale")]
+    pub upscale_factor: Option<f32>,
+}
+
+impl EditArgs {
+    /// The operation requested by the arguments: upscaling if requested, inpainting if a mask was given,
+    /// img2img if only an init image was given, and txt2img otherwise.
+    pub fn operation(&self) -> Result<ImageOperation> {
+        let Some(init_image) = &self.init_image else {
+            return Ok(ImageOperation::TextToImage);
+        };
+        if self.upscale {
+            let defaults = Upscale::default();
+            return Ok(ImageOperation::Upscale(UpscaleParams {
+                image: init_image.clone(),
+                upscale: Upscale {
+                    upscaler: self.upscaler.clone().unwrap_or(defaults.upscaler),
+                    scale: self.upscale_factor.unwrap_or(defaults.scale),
+                },
+            }));
+        }
+        let operation = match (&self.mask, self.mask_rect) {
+            (Some(mask), _) => {
+                ImageOperation::Inpaint(InpaintParams::with_mask(init_image.clone(), mask.clone()))
+            }
+            (None, Some(mask_rect)) => {
+                ImageOperation::Inpaint(InpaintParams::with_rect(init_image.clone(), mask_rect))
+            }
+            (None, None) => ImageOperation::ImageToImage(InitImage::new(init_image.clone())),
+        };
+        Ok(match (operation, self.denoising_strength) {
+            (ImageOperation::Inpaint(inpaint), Some(denoising_strength)) => {
+                ImageOperation::Inpaint(InpaintParams {
+                    denoising_strength,
+                    ..inpaint
+                })
+            }
+            (ImageOperation::ImageToImage(init_image), Some(denoising_strength)) => {
+                ImageOperation::ImageToImage(InitImage {
+                    denoising_strength,
+                    ..init_image
+                })
+            }
+            (operation, _) => operation,
+        })
+    }
 }
 
 /// Specify how to load image generation parameters.
+// Only parsed once per run, so the size difference between the variants doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum ParameterSource {
     /// Load parameters from a TOML file.
@@ -50,6 +135,7 @@ pub struct GenerationParameters {
 mod tests {
     use super::*;
     use anyhow::Result;
+    use clap::CommandFactory;
     use std::fs;
     use std::path::PathBuf;
     use toml::from_str;
@@ -82,4 +168,112 @@ mod tests {
         fs::remove_file(&toml_file)?;
         Ok(())
     }
+
+    #[test]
+    fn test_commands_are_valid() {
+        // Catches arguments of the flattened structs that share an id, which clap only reports when parsing.
+        Commands::command().debug_assert();
+    }
+
+    #[test]
+    fn test_args_with_provider_options() -> Result<()> {
+        let commands = Commands::try_parse_from([
+            "ai_images",
+            "args",
+            "-n",
+            "open-ai",
+            "--openai-model",
+            "gpt-image-1",
+            "--output-format",
+            "webp",
+            "--prompt",
+            "a cat",
+        ])?;
+        let ParameterSource::Args(args) = commands.parameter_source else {
+            unreachable!("the parameters are given as arguments");
+        };
+        assert_eq!(
+            args.provider.config.openai.model,
+            Some(crate::providers::OpenAiImageModel::GptImage1)
+        );
+        assert_eq!(
+            args.provider.config.openai.output_format,
+            Some(crate::providers::OpenAiImageFormat::Webp)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_args() -> Result<()> {
+        let commands = Commands::try_parse_from([
+            "ai_images",
+            "--init-image",
+            "portrait.png",
+            "--mask-rect",
+            "0,0,64,64",
+            "--denoising-strength",
+            "0.5",
+            "toml",
+            "--file",
+            "config.toml",
+        ])?;
+        match commands.edit.operation()? {
+            ImageOperation::Inpaint(inpaint) => {
+                assert_eq!(inpaint.image, PathBuf::from("portrait.png"));
+                assert_eq!(inpaint.mask_rect.map(|rect| rect.width), Some(64));
+                assert_eq!(inpaint.denoising_strength, 0.5);
+            }
+            operation => {
+                return Err(anyhow::Error::msg(format!(
+                    "Expected an inpaint operation, got {:?}",
+                    operation
+                )))
+            }
+        }
+
+        let commands = Commands::try_parse_from([
+            "ai_images",
+            "--init-image",
+            "sketch.png",
+            "toml",
+            "-f",
+            "a",
+        ])?;
+        assert!(matches!(
+            commands.edit.operation()?,
+            ImageOperation::ImageToImage(_)
+        ));
+
+        let commands = Commands::try_parse_from([
+            "ai_images",
+            "--init-image",
+            "portrait.png",
+            "--upscale",
+            "--upscale-factor",
+            "4",
+            "toml",
+            "-f",
+            "a",
+        ])?;
+        match commands.edit.operation()? {
+            ImageOperation::Upscale(upscale) => {
+                assert_eq!(upscale.image, PathBuf::from("portrait.png"));
+                assert_eq!(upscale.upscale.upscaler, "R-ESRGAN 4x+");
+                assert_eq!(upscale.upscale.scale, 4.0);
+            }
+            operation => {
+                return Err(anyhow::Error::msg(format!(
+                    "Expected an upscale operation, got {:?}",
+                    operation
+                )))
+            }
+        }
+
+        // A mask requires an init image.
+        assert!(
+            Commands::try_parse_from(["ai_images", "--mask", "mask.png", "toml", "-f", "a"])
+                .is_err()
+        );
+        Ok(())
+    }
 }
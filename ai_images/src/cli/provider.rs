@@ -1,5 +1,11 @@
+use crate::providers::{
+    ComfyUiInputs, OpenAiImageOptions, StabilityAiOptions, StableDiffusionMode,
+};
+use api_keys::ApiKeySource;
 use clap::{Args, ValueEnum};
+use rate_limiter::RateLimits;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Args, Deserialize, Serialize, Debug, PartialEq)]
 pub struct Provider {
@@ -10,13 +16,34 @@ pub struct Provider {
     /// The configuration options needed by some providers.
     #[clap(flatten)]
     pub config: ProviderConfig,
+
+    /// Limits on how many requests are sent to the provider at once and per minute.
+    /// Only configurable from a TOML file.
+    #[clap(skip)]
+    pub rate_limits: Option<RateLimits>,
 }
 
 impl Default for Provider {
     fn default() -> Self {
         Provider {
             name: ImageProviders::OpenAi,
-            config: ProviderConfig { url: None },
+            config: ProviderConfig {
+                url: None,
+                api_key: ApiKeySource::default(),
+                organization: None,
+                project: None,
+                deployment: None,
+                api_version: None,
+                openai: OpenAiImageOptions::default(),
+                stability: StabilityAiOptions::default(),
+                mode: None,
+                poll_interval: None,
+                task_timeout: None,
+                state_file: None,
+                workflow: None,
+                comfyui: ComfyUiInputs::default(),
+            },
+            rate_limits: None,
         }
     }
 }
@@ -25,6 +52,8 @@ impl Default for Provider {
 pub enum ImageProviders {
     OpenAi,
     StableDiffusion,
+    ComfyUi,
+    StabilityAi,
 }
 
 #[derive(Args, Deserialize, Serialize, Debug, PartialEq)]
@@ -32,4 +61,65 @@ pub struct ProviderConfig {
     /// The URL of the provider's API.
     #[clap(long)]
     pub url: Option<String>,
+
+    /// Where to load the API key from. Defaults to the provider's usual environment variable.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub api_key: ApiKeySource,
+
+    /// The organization to send OpenAI requests as. Only used by OpenAI.
+    #[clap(long = "openai-organization")]
+    pub organization: Option<String>,
+
+    /// The project to send OpenAI requests as. Only used by OpenAI.
+    #[clap(long = "openai-project")]
+    pub project: Option<String>,
+
+    /// The Azure OpenAI deployment to generate images with, with `url` set to the resource's endpoint.
+    /// Only used by OpenAI.
+    #[clap(long = "openai-deployment")]
+    pub deployment: Option<String>,
+
+    /// The Azure OpenAI API version. Defaults to `2024-10-21`. Only used by OpenAI with a `deployment`.
+    #[clap(long = "openai-api-version")]
+    pub api_version: Option<String>,
+
+    /// The model and image options to use with OpenAI. Only used by OpenAI.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub openai: OpenAiImageOptions,
+
+    /// The endpoint and output format to use with Stability AI. Only used by Stability AI.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub stability: StabilityAiOptions,
+
+    /// How requests are sent to Stable Diffusion: directly, through the agent-scheduler queue, or `auto` to use the queue
+    /// if it's installed. Only used by Stable Diffusion.
+    #[clap(long = "sd-mode", value_enum)]
+    pub mode: Option<StableDiffusionMode>,
+
+    /// How often a queued Stable Diffusion task or a ComfyUI workflow is checked on, in seconds. Defaults to 1.
+    /// Only used by Stable Diffusion and ComfyUI.
+    #[clap(long = "sd-poll-interval", alias = "comfyui-poll-interval")]
+    pub poll_interval: Option<f32>,
+
+    /// How long to wait for a queued Stable Diffusion task or a ComfyUI workflow, in seconds. Defaults to 300.
+    /// Only used by Stable Diffusion and ComfyUI.
+    #[clap(long = "sd-task-timeout", alias = "comfyui-task-timeout")]
+    pub task_timeout: Option<f32>,
+
+    /// Where queued Stable Diffusion tasks are saved until their images are collected.
+    /// Defaults to `.ai_images-tasks.json` in the output directory. Only used by Stable Diffusion.
+    #[clap(long = "sd-state-file")]
+    pub state_file: Option<PathBuf>,
+
+    /// The workflow to run, exported from ComfyUI with "Export (API)". Only used by ComfyUI.
+    #[clap(long)]
+    pub workflow: Option<PathBuf>,
+
+    /// Which node inputs of the workflow the image parameters are written to. Only used by ComfyUI.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub comfyui: ComfyUiInputs,
 }
@@ -0,0 +1,156 @@
+//! Convert generated images to the requested file format, and make thumbnails of them.
+use super::ImageData;
+use anyhow::{Error, Result};
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+/// The quality used for lossy formats if none is given.
+const DEFAULT_QUALITY: u8 = 85;
+
+/// The file formats generated images can be saved in.
+#[derive(ValueEnum, Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    /// Lossless WebP. Usually much smaller than PNG.
+    Webp,
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// The file extension for images in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+
+    /// The MIME type of images in this format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub(crate) fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::WebP => Some(OutputFormat::Webp),
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    /// Whether the quality setting changes the output.
+    fn is_lossy(&self) -> bool {
+        matches!(self, OutputFormat::Jpeg)
+    }
+}
+
+/// Detect the format of an encoded image from its contents, rather than trusting the provider.
+pub fn detect_format(bytes: &[u8]) -> Result<OutputFormat> {
+    let format = image::guess_format(bytes)
+        .map_err(|_| Error::msg("The provider returned data that is not a supported image."))?;
+    OutputFormat::from_image_format(format).ok_or(Error::msg(format!(
+        "The provider returned an image in an unsupported format: {:?}",
+        format
+    )))
+}
+
+/// Encode an image in the format. The quality, from 1 to 100, is only used by JPEG.
+pub fn encode(image: &DynamicImage, format: OutputFormat, quality: Option<u8>) -> Result<Vec<u8>> {
+    let quality = quality.unwrap_or(DEFAULT_QUALITY).clamp(1, 100);
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    match format {
+        // JPEG doesn't support an alpha channel.
+        OutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?,
+        // The WebP encoder only supports 8-bit images.
+        OutputFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_to(&mut bytes, format.image_format())?,
+        OutputFormat::Png => image.write_to(&mut bytes, format.image_format())?,
+    }
+    Ok(bytes.into_inner())
+}
+
+/// Convert the image to the format. Images already in a lossless target format are left as they are.
+pub fn convert(image: &mut ImageData, format: OutputFormat, quality: Option<u8>) -> Result<()> {
+    if image.format == format && (quality.is_none() || !format.is_lossy()) {
+        return Ok(());
+    }
+    image.bytes = encode(&image.decode()?, format, quality)?;
+    image.format = format;
+    Ok(())
+}
+
+/// Make a thumbnail of the image that fits in a `size` x `size` square, in the same format.
+pub fn thumbnail(image: &ImageData, size: u32, quality: Option<u8>) -> Result<Vec<u8>> {
+    encode(
+        &image.decode()?.thumbnail(size, size),
+        image.format,
+        quality,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn test_image() -> Result<ImageData> {
+        let image = RgbaImage::from_pixel(64, 32, image::Rgba([0, 255, 0, 255]));
+        ImageData::new(encode(
+            &DynamicImage::ImageRgba8(image),
+            OutputFormat::Png,
+            None,
+        )?)
+    }
+
+    #[test]
+    fn test_convert_formats() -> Result<()> {
+        for format in [OutputFormat::Webp, OutputFormat::Jpeg] {
+            let mut image = test_image()?;
+            convert(&mut image, format, Some(60))?;
+            assert_eq!(image.format, format);
+            assert_eq!(detect_format(&image.bytes)?, format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_same_format_is_untouched() -> Result<()> {
+        let mut image = test_image()?;
+        let before = image.clone();
+        convert(&mut image, OutputFormat::Png, Some(60))?;
+        assert_eq!(image, before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail() -> Result<()> {
+        let thumbnail = ImageData::new(thumbnail(&test_image()?, 16, None)?)?;
+        assert_eq!(thumbnail.format, OutputFormat::Png);
+        // The aspect ratio is kept.
+        assert_eq!(thumbnail.dimensions()?, (16, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_format_rejects_other_data() {
+        assert!(detect_format(b"{\"error\": \"content policy\"}").is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Save the generation parameters in a `parameters` PNG text chunk, in the same format as Automatic1111,
+//! so that any image can be traced back to the request that generated it.
+use super::{convert::OutputFormat, ImageData};
+use crate::{ImageParams, Prompt};
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const KEYWORD: &str = "parameters";
+
+/// The generation parameters saved with an image.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ImageMetadata {
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub steps: Option<u32>,
+    pub sampler_name: Option<String>,
+    /// The noise schedule, e.g. `Karras`.
+    pub scheduler: Option<String>,
+    pub cfg_scale: Option<f32>,
+    pub seed: Option<i64>,
+    pub width: u32,
+    pub height: u32,
+    pub model: Option<String>,
+    pub vae: Option<String>,
+    pub clip_skip: Option<u32>,
+    /// The image provider, e.g. `OpenAi`.
+    pub provider: Option<String>,
+    /// The prompt after the provider rewrote it. Only returned by DALL-E 3.
+    pub revised_prompt: Option<String>,
+}
+
+impl ImageMetadata {
+    /// Read the parameters saved in a PNG image, if it has any.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let bytes = std::fs::read(path)?;
+        Ok(read_text_chunk(&bytes, KEYWORD)?.map(|text| Self::from_parameters(&text)))
+    }
+
+    /// Save the parameters in a PNG image, replacing any that are already there.
+    /// Other formats can't hold a text chunk, so they are left unchanged.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if image::ImageFormat::from_path(path)? != image::ImageFormat::Png {
+            return Ok(());
+        }
+        let bytes = std::fs::read(path)?;
+        let bytes = write_text_chunk(&bytes, KEYWORD, &self.to_parameters())?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Save the parameters in an image before it is written, the same way as `write`.
+    pub fn embed(&self, image: &mut ImageData) -> Result<()> {
+        if image.format != OutputFormat::Png {
+            return Ok(());
+        }
+        image.bytes = write_text_chunk(&image.bytes, KEYWORD, &self.to_parameters())?;
+        Ok(())
+    }
+
+    /// Format the parameters like Automatic1111: the prompt, the negative prompt, then a line of settings.
+    pub fn to_parameters(&self) -> String {
+        let mut settings: Vec<(&str, String)> = Vec::new();
+        if let Some(steps) = self.steps {
+            settings.push(("Steps", steps.to_string()));
+        }
+        if let Some(sampler_name) = &self.sampler_name {
+            settings.push(("Sampler", sampler_name.clone()));
+        }
+        if let Some(scheduler) = &self.scheduler {
+            settings.push(("Schedule type", scheduler.clone()));
+        }
+        if let Some(cfg_scale) = self.cfg_scale {
+            settings.push(("CFG scale", cfg_scale.to_string()));
+        }
+        if let Some(seed) = self.seed {
+            settings.push(("Seed", seed.to_string()));
+        }
+        settings.push(("Size", format!("{}x{}", self.width, self.height)));
+        if let Some(model) = &self.model {
+            settings.push(("Model", model.clone()));
+        }
+        if let Some(vae) = &self.vae {
+            settings.push(("VAE", vae.clone()));
+        }
+        if let Some(clip_skip) = self.clip_skip {
+            settings.push(("Clip skip", clip_skip.to_string()));
+        }
+        if let Some(provider) = &self.provider {
+            settings.push(("Provider", provider.clone()));
+        }
+        if let Some(revised_prompt) = &self.revised_prompt {
+            settings.push(("Revised prompt", revised_prompt.clone()));
+        }
+        let settings = settings
+            .into_iter()
+            .map(|(key, value)| format!("{}: {}", key, quote(&value)))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let mut parameters = self.prompt.clone();
+        if let Some(negative_prompt) = &self.negative_prompt {
+            parameters.push_str(&format!("\nNegative prompt: {}", negative_prompt));
+        }
+        parameters.push('\n');
+        parameters.push_str(&settings);
+        parameters
+    }
+
+    /// Parse parameters written by this crate or by Automatic1111. Unknown settings are ignored.
+    pub fn from_parameters(parameters: &str) -> Self {
+        let mut lines: Vec<&str> = parameters.lines().collect();
+        let settings = match lines.last() {
+            Some(line) if line.starts_with("Steps: ") || line.contains("Size: ") => {
+                parse_settings(line)
+            }
+            _ => Vec::new(),
+        };
+        if !settings.is_empty() {
+            lines.pop();
+        }
+        let negative_start = lines
+            .iter()
+            .position(|line| line.starts_with("Negative prompt: "));
+        let (prompt, negative_prompt) = match negative_start {
+            Some(index) => (
+                lines[..index].join("\n"),
+                Some(lines[index..].join("\n")["Negative prompt: ".len()..].to_string()),
+            ),
+            None => (lines.join("\n"), None),
+        };
+
+        let mut metadata = ImageMetadata {
+            prompt,
+            negative_prompt,
+            ..Default::default()
+        };
+        for (key, value) in settings {
+            match key.as_str() {
+                "Steps" => metadata.steps = value.parse().ok(),
+                "Sampler" => metadata.sampler_name = Some(value),
+                "Schedule type" => metadata.scheduler = Some(value),
+                "CFG scale" => metadata.cfg_scale = value.parse().ok(),
+                "Seed" => metadata.seed = value.parse().ok(),
+                "Size" => {
+                    if let Some((width, height)) = value.split_once('x') {
+                        metadata.width = width.parse().unwrap_or_default();
+                        metadata.height = height.parse().unwrap_or_default();
+                    }
+                }
+                "Model" => metadata.model = Some(value),
+                "VAE" => metadata.vae = Some(value),
+                "Clip skip" => metadata.clip_skip = value.parse().ok(),
+                "Provider" => metadata.provider = Some(value),
+                "Revised prompt" => metadata.revised_prompt = Some(value),
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    /// The image parameters that reproduce the image. Settings that weren't saved keep their defaults.
+    pub fn to_image_params(&self) -> ImageParams {
+        let defaults = ImageParams::default();
+        ImageParams {
+            prompt: Prompt {
+                base: self.prompt.clone(),
+                negative: self.negative_prompt.clone(),
+                ..Default::default()
+            },
+            model: self.model.clone(),
+            width: self.width,
+            height: self.height,
+            steps: self.steps.unwrap_or(defaults.steps),
+            sampler_name: self.sampler_name.clone().unwrap_or(defaults.sampler_name),
+            scheduler: self.scheduler.clone(),
+            cfg_scale: self.cfg_scale.unwrap_or(defaults.cfg_scale),
+            clip_skip: self.clip_skip,
+            vae: self.vae.clone(),
+            seed: self.seed,
+            ..ImageParams::default()
+        }
+    }
+}
+
+/// Quote values that would otherwise break the settings line, the same way Automatic1111 does.
+fn quote(value: &str) -> String {
+    if value.contains([',', '\n', ':', '"']) {
+        serde_json::to_string(value).unwrap_or(value.to_string())
+    } else {
+        value.to_string()
+    }
+}
+
+/// Split a settings line like `Steps: 15, Sampler: UniPC, Revised prompt: "A castle, at night"` into its keys and values.
+fn parse_settings(line: &str) -> Vec<(String, String)> {
+    let mut settings = Vec::new();
+    let mut rest = line.trim();
+    while let Some((key, value)) = rest.split_once(": ") {
+        let key = key.trim().to_string();
+        let (value, remainder) = if value.starts_with('"') {
+            // Find the closing quote, skipping escaped characters.
+            let mut escaped = false;
+            let end = value
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| {
+                    let is_end = *c == '"' && !escaped;
+                    escaped = *c == '\\' && !escaped;
+                    is_end
+                })
+                .map(|(index, _)| index + 1)
+                .unwrap_or(value.len());
+            let quoted = &value[..end];
+            let unquoted = serde_json::from_str::<String>(quoted)
+                .unwrap_or(quoted.trim_matches('"').to_string());
+            (unquoted, &value[end..])
+        } else {
+            match value.split_once(", ") {
+                Some((value, remainder)) => (value.to_string(), remainder),
+                None => (value.to_string(), ""),
+            }
+        };
+        settings.push((key, value));
+        rest = remainder.trim_start_matches(',').trim_start();
+    }
+    settings
+}
+
+/// A chunk of a PNG file: its four-letter type and its data.
+struct Chunk<'a> {
+    kind: &'a [u8],
+    data: &'a [u8],
+}
+
+fn read_chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err(Error::msg("The image is not a PNG file."));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]) as usize;
+        let data_start = offset + 8;
+        // The data is followed by a 4-byte CRC.
+        let data_end = data_start + length;
+        if data_end + 4 > bytes.len() {
+            return Err(Error::msg("The PNG file is truncated."));
+        }
+        chunks.push(Chunk {
+            kind: &bytes[offset + 4..data_start],
+            data: &bytes[data_start..data_end],
+        });
+        offset = data_end + 4;
+    }
+    Ok(chunks)
+}
+
+fn push_chunk(output: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    output.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// The keyword and text of a `tEXt` or uncompressed `iTXt` chunk.
+fn text_chunk(chunk: &Chunk) -> Option<(String, String)> {
+    let separator = chunk.data.iter().position(|byte| *byte == 0)?;
+    let keyword = String::from_utf8_lossy(&chunk.data[..separator]).to_string();
+    let rest = &chunk.data[separator + 1..];
+    match chunk.kind {
+        // tEXt is Latin-1.
+        b"tEXt" => Some((keyword, rest.iter().map(|byte| *byte as char).collect())),
+        b"iTXt" => {
+            // Compression flag and method, then the null-terminated language tag and translated keyword.
+            let (compression, rest) = rest.split_first()?;
+            if *compression != 0 {
+                return None;
+            }
+            let rest = rest.get(1..)?;
+            let language_end = rest.iter().position(|byte| *byte == 0)?;
+            let rest = &rest[language_end + 1..];
+            let translated_end = rest.iter().position(|byte| *byte == 0)?;
+            let text = String::from_utf8_lossy(&rest[translated_end + 1..]).to_string();
+            Some((keyword, text))
+        }
+        _ => None,
+    }
+}
+
+/// Find the text of the chunk with the keyword.
+fn read_text_chunk(bytes: &[u8], keyword: &str) -> Result<Option<String>> {
+    Ok(read_chunks(bytes)?
+        .iter()
+        .filter_map(text_chunk)
+        .find(|(chunk_keyword, _)| chunk_keyword == keyword)
+        .map(|(_, text)| text))
+}
+
+/// Replace the text chunk with the keyword, or add it before the image data.
+/// Plain ASCII is written as `tEXt` and anything else as UTF-8 `iTXt`.
+fn write_text_chunk(bytes: &[u8], keyword: &str, text: &str) -> Result<Vec<u8>> {
+    let (kind, data): (&[u8], Vec<u8>) = if text.is_ascii() {
+        (
+            b"tEXt",
+            [keyword.as_bytes(), b"\0", text.as_bytes()].concat(),
+        )
+    } else {
+        (
+            b"iTXt",
+            [keyword.as_bytes(), b"\0\0\0\0\0", text.as_bytes()].concat(),
+        )
+    };
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut written = false;
+    for chunk in read_chunks(bytes)? {
+        if text_chunk(&chunk).is_some_and(|(chunk_keyword, _)| chunk_keyword == keyword) {
+            continue;
+        }
+        if !written && (chunk.kind == b"IDAT" || chunk.kind == b"IEND") {
+            push_chunk(&mut output, kind, &data);
+            written = true;
+        }
+        push_chunk(&mut output, chunk.kind, chunk.data);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    fn metadata() -> ImageMetadata {
+        ImageMetadata {
+            prompt: "A castle on a hill, at night".to_string(),
+            negative_prompt: Some("blurry".to_string()),
+            steps: Some(15),
+            sampler_name: Some("UniPC".to_string()),
+            scheduler: Some("Karras".to_string()),
+            cfg_scale: Some(6.5),
+            seed: Some(1234),
+            width: 32,
+            height: 16,
+            model: Some("sd_xl_base_1.0".to_string()),
+            vae: None,
+            clip_skip: Some(2),
+            provider: Some("StableDiffusion".to_string()),
+            revised_prompt: Some("A stone castle, lit by \"moonlight\"".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parameters_round_trip() {
+        let parameters = metadata().to_parameters();
+        assert_eq!(
+            parameters,
+            "A castle on a hill, at night\nNegative prompt: blurry\nSteps: 15, Sampler: UniPC, Schedule type: Karras, CFG scale: 6.5, Seed: 1234, Size: 32x16, Model: sd_xl_base_1.0, Clip skip: 2, Provider: StableDiffusion, Revised prompt: \"A stone castle, lit by \\\"moonlight\\\"\""
+        );
+        assert_eq!(ImageMetadata::from_parameters(&parameters), metadata());
+    }
+
+    #[test]
+    fn test_from_automatic1111_parameters() {
+        let parameters = "A cat\nwearing a hat\nNegative prompt: dog\nSteps: 20, Sampler: DPM++ 2M, Schedule type: Karras, CFG scale: 7.5, Seed: 42, Size: 512x768, Model hash: 31e35c80fc, Model: sd_xl_base_1.0, Version: v1.10.1";
+        let metadata = ImageMetadata::from_parameters(parameters);
+        assert_eq!(metadata.prompt, "A cat\nwearing a hat");
+        assert_eq!(metadata.negative_prompt, Some("dog".to_string()));
+        assert_eq!(metadata.sampler_name, Some("DPM++ 2M".to_string()));
+        assert_eq!(metadata.scheduler, Some("Karras".to_string()));
+        assert_eq!(metadata.cfg_scale, Some(7.5));
+        assert_eq!((metadata.width, metadata.height), (512, 768));
+        assert_eq!(metadata.model, Some("sd_xl_base_1.0".to_string()));
+    }
+
+    #[test]
+    fn test_write_and_read_png() -> Result<()> {
+        let path = std::env::temp_dir().join("test_write_and_read_png.png");
+        RgbaImage::from_pixel(32, 16, image::Rgba([0, 0, 255, 255])).save(&path)?;
+        metadata().write(&path)?;
+        // Writing again replaces the chunk instead of adding another one.
+        let unicode = ImageMetadata {
+            prompt: "Ein Schloss über dem Tal".to_string(),
+            ..metadata()
+        };
+        unicode.write(&path)?;
+        assert_eq!(ImageMetadata::read(&path)?, Some(unicode.clone()));
+        let bytes = std::fs::read(&path)?;
+        assert_eq!(
+            read_chunks(&bytes)?
+                .iter()
+                .filter(|chunk| text_chunk(chunk).is_some())
+                .count(),
+            1
+        );
+        // The image itself is untouched.
+        assert_eq!(image::open(&path)?.dimensions(), (32, 16));
+        assert_eq!(
+            ImageParams::from_image(&path)?.prompt.base,
+            "Ein Schloss über dem Tal"
+        );
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -1,16 +1,168 @@
+pub mod convert;
+pub mod metadata;
+pub mod resize;
+pub mod storage;
 pub mod string;
 
 use anyhow::Result;
-use std::path::Path;
+use convert::{detect_format, OutputFormat};
+use image::DynamicImage;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 
 /// A base64-encoded image.
 pub struct Base64Image {
     pub image: String,
+    /// The seed the image was generated with, if the provider reports one.
+    pub seed: Option<i64>,
 }
 
 impl Base64Image {
-    pub fn to_file(&self, path: &Path) -> Result<()> {
-        string::base64_to_png(&self.image, path)?;
-        Ok(())
+    /// Decode the image, checking that it is an image in a supported format.
+    pub fn decode(&self) -> Result<ImageData> {
+        let (bytes, format) = string::base64_to_bytes(&self.image)?;
+        Ok(ImageData {
+            bytes,
+            format,
+            seed: self.seed,
+            revised_prompt: None,
+            model: None,
+            task_id: None,
+        })
+    }
+
+    /// Save the image to `{path}.{extension}`, where the extension matches the format of the decoded data.
+    /// Returns the path the image was saved to.
+    pub fn to_file(&self, path: &Path) -> Result<PathBuf> {
+        let image = self.decode()?;
+        let path = path.with_extension(image.format.extension());
+        std::fs::write(&path, image.bytes)?;
+        Ok(path)
+    }
+}
+
+/// An image returned by a provider, before it is saved. Providers only generate images,
+/// and `storage` names, converts, and writes them, so every provider's images are saved the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageData {
+    /// The encoded image.
+    pub bytes: Vec<u8>,
+    /// The format of `bytes`, detected from the data rather than trusting the provider.
+    pub format: OutputFormat,
+    /// The seed the image was generated with, if the provider reports one.
+    pub seed: Option<i64>,
+    /// The prompt after the provider rewrote it, if it did.
+    pub revised_prompt: Option<String>,
+    /// The model the provider generated the image with, if it isn't the one in the parameters, e.g. `dall-e-3`.
+    pub model: Option<String>,
+    /// The queued task the image was collected from, if it was queued. The task is kept in the state file
+    /// until the image is saved, so it can still be resumed if saving fails.
+    pub task_id: Option<String>,
+}
+
+impl ImageData {
+    /// Wrap an encoded image, checking that it is an image in a supported format.
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        let format = detect_format(&bytes)?;
+        Ok(Self {
+            bytes,
+            format,
+            seed: None,
+            revised_prompt: None,
+            model: None,
+            task_id: None,
+        })
+    }
+
+    /// Read an image file, e.g. an existing image to upscale.
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::new(std::fs::read(path)?)
+    }
+
+    /// Set the seed the image was generated with.
+    pub fn with_seed(self, seed: Option<i64>) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// The MIME type of the image, e.g. `image/png`.
+    pub fn mime_type(&self) -> &'static str {
+        self.format.mime_type()
+    }
+
+    /// Decode the image to edit it.
+    pub fn decode(&self) -> Result<DynamicImage> {
+        Ok(image::load_from_memory_with_format(
+            &self.bytes,
+            self.format.image_format(),
+        )?)
+    }
+
+    /// The width and height of the image, read without decoding it.
+    pub fn dimensions(&self) -> Result<(u32, u32)> {
+        let reader = image::ImageReader::with_format(
+            std::io::Cursor::new(&self.bytes),
+            self.format.image_format(),
+        );
+        Ok(reader.into_dimensions()?)
+    }
+
+    /// Encode the image in base64, e.g. to send it back to a provider.
+    pub fn to_base64(&self) -> String {
+        string::bytes_to_base64(&self.bytes)
+    }
+}
+
+/// Decode every image a provider returned.
+pub fn decode_images(images: &[Base64Image]) -> Result<Vec<ImageData>> {
+    images.iter().map(Base64Image::decode).collect()
+}
+
+/// An image that was generated and saved to disk.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct GeneratedImage {
+    /// Where the image was saved.
+    pub path: PathBuf,
+    /// The seed the image was generated with, if the provider reports one.
+    pub seed: Option<i64>,
+    /// The prompt after the provider rewrote it, if it did.
+    pub revised_prompt: Option<String>,
+    /// Smaller copies of the image, in the order of the requested thumbnail sizes.
+    pub thumbnails: Vec<PathBuf>,
+}
+
+/// The path to save a generated image to, named after `timestamp`.
+/// When a request returns several images, the index is appended so they don't overwrite each other. If that name is
+/// already taken, e.g. by another run that finished in the same second, the `attempt` number is appended as well.
+pub fn output_path(
+    directory: &Path,
+    timestamp: i64,
+    index: usize,
+    count: usize,
+    attempt: usize,
+    extension: &str,
+) -> PathBuf {
+    let mut stem = timestamp.to_string();
+    if count > 1 {
+        stem = format!("{}-{}", stem, index);
+    }
+    if attempt > 0 {
+        stem = format!("{}-{}", stem, attempt);
+    }
+    directory.join(format!("{}.{}", stem, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_path() {
+        let directory = Path::new("output");
+        let single = output_path(directory, 1735689600, 0, 1, 0, "png");
+        assert_eq!(single, directory.join("1735689600.png"));
+        let variant = output_path(directory, 1735689600, 2, 4, 0, "webp");
+        assert_eq!(variant, directory.join("1735689600-2.webp"));
+        let taken = output_path(directory, 1735689600, 2, 4, 1, "webp");
+        assert_eq!(taken, directory.join("1735689600-2-1.webp"));
     }
 }
@@ -0,0 +1,130 @@
+//! Resize generated images to the exact dimensions that were requested.
+use super::{convert::encode, ImageData};
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// How to fit an image to the requested dimensions when the provider can't generate them natively.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizePolicy {
+    /// Scale the image to cover the requested size, then crop the overflow from the center.
+    #[default]
+    Crop,
+    /// Scale the image to fit inside the requested size, then pad the edges.
+    /// Padding is transparent, or black for formats without an alpha channel.
+    Pad,
+    /// Keep the image at the size the provider generated.
+    Keep,
+}
+
+/// Pick the candidate size whose aspect ratio is closest to the requested one.
+/// Ties go to the larger candidate, so less of the image is upscaled.
+pub fn closest_aspect_ratio(width: u32, height: u32, candidates: &[(u32, u32)]) -> (u32, u32) {
+    let requested = aspect_ratio(width, height);
+    candidates
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            let a_distance = (aspect_ratio(a.0, a.1) - requested).abs();
+            let b_distance = (aspect_ratio(b.0, b.1) - requested).abs();
+            a_distance
+                .total_cmp(&b_distance)
+                .then((b.0 * b.1).cmp(&(a.0 * a.1)))
+        })
+        .unwrap_or((width, height))
+}
+
+/// The log of the aspect ratio, so that 2:1 and 1:2 are equally far from square.
+fn aspect_ratio(width: u32, height: u32) -> f64 {
+    (width.max(1) as f64 / height.max(1) as f64).ln()
+}
+
+/// Resize the image to `width` x `height` using the given policy, keeping its format.
+/// Returns the original dimensions if the image was resized.
+pub fn fit_to_size(
+    image: &mut ImageData,
+    width: u32,
+    height: u32,
+    policy: ResizePolicy,
+) -> Result<Option<(u32, u32)>> {
+    let original = image.dimensions()?;
+    if original == (width, height) || policy == ResizePolicy::Keep {
+        return Ok(None);
+    }
+    let decoded = image.decode()?;
+
+    let resized = match policy {
+        ResizePolicy::Crop => decoded.resize_to_fill(width, height, FilterType::Lanczos3),
+        ResizePolicy::Pad => {
+            let fitted = decoded.resize(width, height, FilterType::Lanczos3);
+            let mut canvas = RgbaImage::new(width, height);
+            image::imageops::overlay(
+                &mut canvas,
+                &fitted.to_rgba8(),
+                ((width - fitted.width()) / 2).into(),
+                ((height - fitted.height()) / 2).into(),
+            );
+            DynamicImage::ImageRgba8(canvas)
+        }
+        ResizePolicy::Keep => decoded,
+    };
+    image.bytes = encode(&resized, image.format, None)?;
+    Ok(Some(original))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::convert::OutputFormat;
+
+    fn test_image(width: u32, height: u32) -> Result<ImageData> {
+        let image = RgbaImage::from_pixel(width, height, image::Rgba([255, 0, 0, 255]));
+        ImageData::new(encode(
+            &DynamicImage::ImageRgba8(image),
+            OutputFormat::Png,
+            None,
+        )?)
+    }
+
+    #[test]
+    fn test_closest_aspect_ratio() {
+        let candidates = [(1024, 1024), (1792, 1024), (1024, 1792)];
+        assert_eq!(closest_aspect_ratio(512, 768, &candidates), (1024, 1792));
+        assert_eq!(closest_aspect_ratio(1920, 1080, &candidates), (1792, 1024));
+        assert_eq!(closest_aspect_ratio(600, 580, &candidates), (1024, 1024));
+    }
+
+    #[test]
+    fn test_crop_to_size() -> Result<()> {
+        let mut image = test_image(64, 32)?;
+        let original = fit_to_size(&mut image, 16, 24, ResizePolicy::Crop)?;
+        assert_eq!(original, Some((64, 32)));
+        assert_eq!(image.dimensions()?, (16, 24));
+        // Cropping leaves no padding.
+        assert_eq!(image.decode()?.to_rgba8().get_pixel(0, 0)[3], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pad_to_size() -> Result<()> {
+        let mut image = test_image(64, 32)?;
+        fit_to_size(&mut image, 32, 32, ResizePolicy::Pad)?;
+        let image = image.decode()?.to_rgba8();
+        assert_eq!(image.dimensions(), (32, 32));
+        // The top and bottom are padded, the middle is the image.
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(16, 16)[3], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn test_matching_size_is_untouched() -> Result<()> {
+        let mut image = test_image(32, 32)?;
+        let before = image.clone();
+        assert_eq!(fit_to_size(&mut image, 32, 32, ResizePolicy::Crop)?, None);
+        assert_eq!(image, before);
+        Ok(())
+    }
+}
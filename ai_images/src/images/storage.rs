@@ -0,0 +1,227 @@
+//! Save generated images to the output directory. This is the only place images are named, converted, and written,
+//! so every provider's images are saved the same way.
+use super::{convert, metadata::ImageMetadata, output_path, GeneratedImage, ImageData};
+use crate::ImageParams;
+use anyhow::{Error, Result};
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Save the images to the output directory of `params`, named after the current timestamp, e.g. `1735689600.png`.
+/// When there are several, the index is appended, e.g. `1735689600-0.png`. Each image is converted to the requested
+/// format, saved with its parameters, if it has any, and has its thumbnails saved next to it.
+/// The `width` and `height` of the parameters are set to the size of the saved image.
+/// Existing files are never overwritten. If a name is taken, e.g. by another run that finished in the same second,
+/// a number is appended, e.g. `1735689600-1.png`.
+pub fn save(
+    images: Vec<(ImageData, Option<ImageMetadata>)>,
+    params: &ImageParams,
+) -> Result<Vec<GeneratedImage>> {
+    save_at(images, params, chrono::Utc::now().timestamp())
+}
+
+/// Save the images like `save`, named after `timestamp`.
+fn save_at(
+    images: Vec<(ImageData, Option<ImageMetadata>)>,
+    params: &ImageParams,
+    timestamp: i64,
+) -> Result<Vec<GeneratedImage>> {
+    if images.is_empty() {
+        return Err(Error::msg("The provider did not return any images."));
+    }
+    let count = images.len();
+    images
+        .into_iter()
+        .enumerate()
+        .map(|(index, (mut image, metadata))| {
+            if let Some(format) = params.format {
+                convert::convert(&mut image, format, params.quality)?;
+            }
+            // Save the parameters after converting, since converting rewrites the image.
+            if let Some(metadata) = metadata {
+                let (width, height) = image.dimensions()?;
+                ImageMetadata {
+                    width,
+                    height,
+                    ..metadata
+                }
+                .embed(&mut image)?;
+            }
+            let (path, mut file) = create_output_file(params, timestamp, index, count, &image)?;
+            file.write_all(&image.bytes)?;
+            let thumbnails = params
+                .thumbnail_sizes
+                .iter()
+                .map(|size| save_thumbnail(&image, &path, *size, params.quality))
+                .collect::<Result<Vec<PathBuf>>>()?;
+            Ok(GeneratedImage {
+                path,
+                seed: image.seed,
+                revised_prompt: image.revised_prompt,
+                thumbnails,
+            })
+        })
+        .collect()
+}
+
+/// Create the file to save an image to, with the first name that isn't taken by an image or its thumbnails.
+/// The file is created only if it doesn't exist, so a run saving at the same moment can't claim the same name.
+fn create_output_file(
+    params: &ImageParams,
+    timestamp: i64,
+    index: usize,
+    count: usize,
+    image: &ImageData,
+) -> Result<(PathBuf, File)> {
+    for attempt in 0.. {
+        let path = output_path(
+            &params.output_directory,
+            timestamp,
+            index,
+            count,
+            attempt,
+            image.format.extension(),
+        );
+        let thumbnails_taken = params
+            .thumbnail_sizes
+            .iter()
+            .map(|size| thumbnail_path(image, &path, *size))
+            .collect::<Result<Vec<PathBuf>>>()?
+            .iter()
+            .any(|thumbnail| thumbnail.exists());
+        if thumbnails_taken {
+            continue;
+        }
+        match File::create_new(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Err(Error::msg(format!(
+        "Unable to find a free file name in {:?}.",
+        params.output_directory
+    )))
+}
+
+/// Save a thumbnail that fits in a `size` x `size` square next to the image saved at `path`, in the same format.
+/// Thumbnails are named after the image and their size, e.g. `1735689600-256.webp`.
+fn save_thumbnail(
+    image: &ImageData,
+    path: &Path,
+    size: u32,
+    quality: Option<u8>,
+) -> Result<PathBuf> {
+    let thumbnail_path = thumbnail_path(image, path, size)?;
+    std::fs::write(&thumbnail_path, convert::thumbnail(image, size, quality)?)?;
+    Ok(thumbnail_path)
+}
+
+/// The path of the `size` thumbnail of the image saved at `path`.
+fn thumbnail_path(image: &ImageData, path: &Path, size: u32) -> Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .ok_or(Error::msg(format!("{:?} is not a file.", path)))?
+        .to_string_lossy();
+    Ok(path.with_file_name(format!("{}-{}.{}", stem, size, image.format.extension())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::convert::{encode, OutputFormat};
+    use image::{DynamicImage, RgbaImage};
+
+    #[test]
+    fn test_save() -> Result<()> {
+        let directory = std::env::temp_dir().join("test_storage_save");
+        std::fs::create_dir_all(&directory)?;
+        let image = RgbaImage::from_pixel(64, 32, image::Rgba([0, 0, 255, 255]));
+        let image = ImageData::new(encode(
+            &DynamicImage::ImageRgba8(image),
+            OutputFormat::Png,
+            None,
+        )?)?
+        .with_seed(Some(42));
+        let params = ImageParams {
+            output_directory: directory,
+            format: Some(OutputFormat::Webp),
+            thumbnail_sizes: vec![16],
+            ..Default::default()
+        };
+        let metadata = ImageMetadata {
+            prompt: "A castle".to_string(),
+            ..Default::default()
+        };
+
+        let saved = save(vec![(image, Some(metadata))], &params)?;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].seed, Some(42));
+        assert_eq!(saved[0].path.extension(), Some("webp".as_ref()));
+        let stored = ImageData::read(&saved[0].path)?;
+        assert_eq!(stored.format, OutputFormat::Webp);
+        assert_eq!(stored.dimensions()?, (64, 32));
+        assert_eq!(
+            ImageData::read(&saved[0].thumbnails[0])?.dimensions()?,
+            (16, 8)
+        );
+
+        std::fs::remove_file(&saved[0].path)?;
+        std::fs::remove_file(&saved[0].thumbnails[0])?;
+        assert!(save(Vec::new(), &params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_in_the_same_second() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let image = |color| -> Result<ImageData> {
+            let image = RgbaImage::from_pixel(32, 32, image::Rgba(color));
+            ImageData::new(encode(
+                &DynamicImage::ImageRgba8(image),
+                OutputFormat::Png,
+                None,
+            )?)
+        };
+        let params = ImageParams {
+            output_directory: directory.path().to_path_buf(),
+            thumbnail_sizes: vec![16],
+            ..Default::default()
+        };
+
+        let first = save_at(vec![(image([255, 0, 0, 255])?, None)], &params, 1735689600)?;
+        let second = save_at(vec![(image([0, 255, 0, 255])?, None)], &params, 1735689600)?;
+        let batch = save_at(
+            vec![
+                (image([0, 0, 255, 255])?, None),
+                (image([0, 0, 0, 255])?, None),
+            ],
+            &params,
+            1735689600,
+        )?;
+        let names = first
+            .iter()
+            .chain(&second)
+            .chain(&batch)
+            .flat_map(|saved| std::iter::once(&saved.path).chain(&saved.thumbnails))
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "1735689600.png",
+                "1735689600-16.png",
+                "1735689600-1.png",
+                "1735689600-1-16.png",
+                "1735689600-0.png",
+                "1735689600-0-16.png",
+                "1735689600-1-1.png",
+                "1735689600-1-1-16.png",
+            ]
+        );
+        // The first image wasn't overwritten by the second.
+        let pixel = ImageData::read(&first[0].path)?.decode()?.to_rgba8();
+        assert_eq!(pixel.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+        Ok(())
+    }
+}
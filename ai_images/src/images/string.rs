@@ -1,17 +1,38 @@
 //! Functions to handle the conversion of images from and to base64 encoding.
+use super::convert::{detect_format, OutputFormat};
 use anyhow::Result;
 use base64::Engine;
+use image::DynamicImage;
 use std::path::Path;
 
-/// Convert a base64-encoded image to a PNG file which is saved to file_path.
-pub fn base64_to_png(image: &str, file_path: &Path) -> Result<()> {
-    // Check that file_path is not a directory.
-    if file_path.is_dir() {
-        return Err(anyhow::anyhow!("file_path must be a file path."));
-    }
+/// Decode a base64-encoded image, checking that the data is an image in a supported format.
+pub fn base64_to_bytes(image: &str) -> Result<(Vec<u8>, OutputFormat)> {
     let image = base64::prelude::BASE64_STANDARD.decode(image)?;
-    std::fs::write(file_path, image)?;
-    Ok(())
+    let format = detect_format(&image)?;
+    Ok((image, format))
+}
+
+/// Read an image file and encode it in base64.
+pub fn file_to_base64(file_path: &Path) -> Result<String> {
+    let image = std::fs::read(file_path)?;
+    Ok(bytes_to_base64(&image))
+}
+
+/// Encode the bytes of an image file in base64.
+pub fn bytes_to_base64(image: &[u8]) -> String {
+    base64::prelude::BASE64_STANDARD.encode(image)
+}
+
+/// Encode an image as a PNG in base64.
+pub fn image_to_base64(image: &DynamicImage) -> Result<String> {
+    Ok(base64::prelude::BASE64_STANDARD.encode(image_to_png(image)?))
+}
+
+/// Encode an image as a PNG.
+pub fn image_to_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
 }
 
 #[cfg(test)]
@@ -19,13 +40,14 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_base64_to_png() -> Result<()> {
+    fn test_base64_to_bytes() -> Result<()> {
         let image = "iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf8/9hAAABjElEQVR42mNk".to_string();
-        let output_directory = Path::new(".");
-        let output_path = output_directory.join("test_base64_to_png.png");
-        base64_to_png(&image, &output_path)?;
-        assert!(output_path.exists());
-        std::fs::remove_file(output_path)?;
+        let (bytes, format) = base64_to_bytes(&image)?;
+        assert!(bytes.starts_with(b"\x89PNG"));
+        assert_eq!(format, OutputFormat::Png);
+        // Error messages returned in place of an image are rejected.
+        let error = base64::prelude::BASE64_STANDARD.encode("Content policy violation");
+        assert!(base64_to_bytes(&error).is_err());
         Ok(())
     }
 }
@@ -1,18 +1,44 @@
 #![deny(unused_crate_dependencies)]
 
+mod cancel;
 pub mod cli;
 mod images;
 mod params;
+mod progress;
 pub mod providers;
 
+// The stand-in server is only used by the integration tests.
+#[cfg(test)]
+use test_support as _;
+
 use anyhow::{Error, Result};
-pub use params::{ImageParams, Prompt};
+pub use cancel::{CancellationToken, Cancelled};
+pub use images::{
+    convert::OutputFormat, metadata::ImageMetadata, resize::ResizePolicy, GeneratedImage, ImageData,
+};
+pub use params::{
+    ControlNetUnit, Embedding, ImageOperation, ImageParams, InitImage, InitImageResizeMode,
+    InpaintParams, InpaintingFill, Lora, MaskRect, Prompt, Upscale, UpscaleParams,
+};
+pub use progress::{Progress, ProgressEvent};
 pub use providers::ImageProviders;
 
 impl cli::Provider {
     pub fn to_image_provider(&self) -> Result<ImageProviders> {
-        match self.name {
-            cli::ImageProviders::OpenAi => Ok(ImageProviders::OpenAi(providers::OpenAiProvider)),
+        let provider = match self.name {
+            cli::ImageProviders::OpenAi => {
+                // Catch options the model doesn't support before any requests are sent.
+                self.config.openai.validate()?;
+                ImageProviders::OpenAi(providers::OpenAiProvider {
+                    api_key: self.config.api_key.clone(),
+                    base_url: self.config.url.clone(),
+                    organization: self.config.organization.clone(),
+                    project: self.config.project.clone(),
+                    deployment: self.config.deployment.clone(),
+                    api_version: self.config.api_version.clone(),
+                    options: self.config.openai.clone(),
+                })
+            }
             cli::ImageProviders::StableDiffusion => {
                 // If the Stable Diffusion provider is selected, check that the URL is provided.
                 if let Some(url) = &self.config.url {
@@ -21,17 +47,48 @@ impl cli::Provider {
                             "The URL for the Stable Diffusion provider must be provided.",
                         ));
                     }
-                    Ok(providers::ImageProviders::StableDiffusion(
+                    providers::ImageProviders::StableDiffusion(
                         providers::StableDiffusionXLProvider {
                             url: url.to_string(),
+                            mode: self.config.mode,
+                            poll_interval: self.config.poll_interval,
+                            task_timeout: self.config.task_timeout,
+                            state_file: self.config.state_file.clone(),
                         },
-                    ))
+                    )
                 } else {
-                    Err(Error::msg(
+                    return Err(Error::msg(
                         "The URL for the Stable Diffusion provider must be provided.",
-                    ))
+                    ));
                 }
             }
+            cli::ImageProviders::ComfyUi => {
+                let workflow = self.config.workflow.clone().ok_or(Error::msg(
+                    "The workflow for the ComfyUI provider must be provided.",
+                ))?;
+                let default = providers::ComfyUiProvider::default();
+                ImageProviders::ComfyUi(providers::ComfyUiProvider {
+                    url: self.config.url.clone().unwrap_or(default.url),
+                    workflow,
+                    inputs: self.config.comfyui.clone(),
+                    poll_interval: self.config.poll_interval,
+                    task_timeout: self.config.task_timeout,
+                })
+            }
+            cli::ImageProviders::StabilityAi => {
+                self.config.stability.validate()?;
+                let default = providers::StabilityAiProvider::default();
+                ImageProviders::StabilityAi(providers::StabilityAiProvider {
+                    url: self.config.url.clone().unwrap_or(default.url),
+                    api_key: self.config.api_key.clone(),
+                    options: self.config.stability.clone(),
+                })
+            }
+        };
+        // Register the configured limits so every request to this provider shares them.
+        if let Some(limits) = &self.rate_limits {
+            rate_limiter::configure(&provider.limiter_key(), limits.clone())?;
         }
+        Ok(provider)
     }
 }
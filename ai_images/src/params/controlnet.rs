@@ -0,0 +1,176 @@
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+fn default_module() -> String {
+    "none".to_string()
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+fn default_guidance_end() -> f32 {
+    1.0
+}
+
+/// A ControlNet unit that guides the generation with a control image, e.g. a pose or a depth map.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct ControlNetUnit {
+    /// The control image, e.g. a photo to take a pose from, or a pose that was already extracted.
+    pub image: PathBuf,
+
+    /// The preprocessor that turns the image into a control map, e.g. `openpose` or `depth_midas`,
+    /// as listed by `/controlnet/module_list`. Defaults to `none`, for images that are already control maps.
+    #[serde(default = "default_module")]
+    pub module: String,
+
+    /// The ControlNet model, e.g. `control_v11p_sd15_openpose`, as listed by `/controlnet/model_list`.
+    pub model: String,
+
+    /// How strongly the control map guides the image. Defaults to 1.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+
+    /// When the unit starts guiding, as a fraction of the sampling steps. Defaults to 0.
+    #[serde(default)]
+    pub guidance_start: f32,
+
+    /// When the unit stops guiding, as a fraction of the sampling steps. Defaults to 1.
+    #[serde(default = "default_guidance_end")]
+    pub guidance_end: f32,
+}
+
+impl ControlNetUnit {
+    /// Use `image` to guide the generation with `model`, with the default settings.
+    pub fn new(image: PathBuf, model: &str) -> Self {
+        Self {
+            image,
+            module: default_module(),
+            model: model.to_string(),
+            weight: default_weight(),
+            guidance_start: 0.0,
+            guidance_end: default_guidance_end(),
+        }
+    }
+
+    /// Check that the weight and guidance range make sense.
+    pub fn validate(&self) -> Result<()> {
+        if self.weight < 0.0 {
+            return Err(Error::msg(format!(
+                "The ControlNet weight must not be negative, but was {}.",
+                self.weight
+            )));
+        }
+        let range = 0.0..=1.0;
+        if !range.contains(&self.guidance_start)
+            || !range.contains(&self.guidance_end)
+            || self.guidance_start > self.guidance_end
+        {
+            return Err(Error::msg(format!(
+                "The ControlNet guidance range must be between 0 and 1, with the start before the end, but was {} to {}.",
+                self.guidance_start, self.guidance_end
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ControlNetUnit {
+    type Err = Error;
+
+    /// Parse a unit given as comma-separated `key=value` pairs, e.g.
+    /// `image=pose.png,model=control_v11p_sd15_openpose,module=openpose,weight=0.8,guidance_end=0.6`.
+    /// `image` and `model` are required.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut image = None;
+        let mut model = None;
+        let mut unit = Self::new(PathBuf::new(), "");
+        for pair in s.split(',') {
+            let (key, value) = pair.split_once('=').ok_or(Error::msg(format!(
+                "Invalid ControlNet setting {:?}. Expected `key=value`.",
+                pair
+            )))?;
+            let value = value.trim();
+            let number = || {
+                value.parse::<f32>().map_err(|e| {
+                    Error::msg(format!("Invalid ControlNet {}: {:?}: {}", key, value, e))
+                })
+            };
+            match key.trim() {
+                "image" => image = Some(PathBuf::from(value)),
+                "model" => model = Some(value.to_string()),
+                "module" => unit.module = value.to_string(),
+                "weight" => unit.weight = number()?,
+                "guidance_start" => unit.guidance_start = number()?,
+                "guidance_end" => unit.guidance_end = number()?,
+                key => {
+                    return Err(Error::msg(format!(
+                        "Unknown ControlNet setting {:?}. Expected image, model, module, weight, guidance_start, or guidance_end.",
+                        key
+                    )))
+                }
+            }
+        }
+        let (Some(image), Some(model)) = (image, model) else {
+            return Err(Error::msg(format!(
+                "Invalid ControlNet unit {:?}. Both `image` and `model` are required.",
+                s
+            )));
+        };
+        let unit = Self {
+            image,
+            model,
+            ..unit
+        };
+        unit.validate()?;
+        Ok(unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_controlnet_unit() -> Result<()> {
+        let unit: ControlNetUnit =
+            "image=pose.png, model=control_v11p_sd15_openpose, module=openpose, weight=0.8, guidance_end=0.6"
+                .parse()?;
+        assert_eq!(
+            unit,
+            ControlNetUnit {
+                module: "openpose".to_string(),
+                weight: 0.8,
+                guidance_end: 0.6,
+                ..ControlNetUnit::new(PathBuf::from("pose.png"), "control_v11p_sd15_openpose")
+            }
+        );
+        assert!("image=pose.png".parse::<ControlNetUnit>().is_err());
+        assert!("image=pose.png,model=canny,strength=2"
+            .parse::<ControlNetUnit>()
+            .is_err());
+        assert!(
+            "image=pose.png,model=canny,guidance_start=0.8,guidance_end=0.2"
+                .parse::<ControlNetUnit>()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_defaults() -> Result<()> {
+        let unit: ControlNetUnit = toml::from_str(
+            r#"
+            image = "pose.png"
+            model = "control_v11p_sd15_openpose"
+            "#,
+        )?;
+        assert_eq!(
+            unit,
+            ControlNetUnit::new(PathBuf::from("pose.png"), "control_v11p_sd15_openpose")
+        );
+        Ok(())
+    }
+}
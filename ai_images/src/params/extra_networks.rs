@@ -0,0 +1,158 @@
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// A LoRA to apply to the generation, with how strongly to apply it.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct Lora {
+    /// The name or alias of the LoRA, as listed by `/sdapi/v1/loras`.
+    pub name: String,
+    /// How strongly to apply the LoRA. Defaults to 1.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+/// A textual-inversion embedding to add to the prompt, with an optional emphasis weight.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct Embedding {
+    /// The name of the embedding, as listed by `/sdapi/v1/embeddings`.
+    pub name: String,
+    /// How much to emphasize the embedding. Added as-is if unset.
+    pub weight: Option<f32>,
+}
+
+/// Split `name:weight` into the name and the weight, if there is one.
+fn parse_weighted(s: &str) -> Result<(String, Option<f32>)> {
+    let (name, weight) = match s.rsplit_once(':') {
+        Some((name, weight)) => (
+            name,
+            Some(
+                weight
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|e| Error::msg(format!("Invalid weight in {:?}: {}", s, e)))?,
+            ),
+        ),
+        None => (s, None),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::msg(format!(
+            "Invalid network {:?}. Expected `name` or `name:weight`.",
+            s
+        )));
+    }
+    Ok((name.to_string(), weight))
+}
+
+impl FromStr for Lora {
+    type Err = Error;
+
+    /// Parse a LoRA given as `name` or `name:weight`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, weight) = parse_weighted(s)?;
+        Ok(Self {
+            name,
+            weight: weight.unwrap_or(default_weight()),
+        })
+    }
+}
+
+impl FromStr for Embedding {
+    type Err = Error;
+
+    /// Parse an embedding given as `name` or `name:weight`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, weight) = parse_weighted(s)?;
+        Ok(Self { name, weight })
+    }
+}
+
+impl fmt::Display for Lora {
+    /// Write the LoRA in Automatic1111's prompt syntax, e.g. `<lora:name:0.8>`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<lora:{}:{}>", self.name, self.weight)
+    }
+}
+
+impl fmt::Display for Embedding {
+    /// Embeddings are triggered by their name, and emphasized with `(name:weight)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.weight {
+            Some(weight) => write!(f, "({}:{})", self.name, weight),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lora() -> Result<()> {
+        assert_eq!(
+            "add_detail:0.8".parse::<Lora>()?,
+            Lora {
+                name: "add_detail".to_string(),
+                weight: 0.8
+            }
+        );
+        assert_eq!("add_detail".parse::<Lora>()?.weight, 1.0);
+        assert!("add_detail:high".parse::<Lora>().is_err());
+        assert!(":0.5".parse::<Lora>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_display() -> Result<()> {
+        assert_eq!(
+            "add_detail:0.8".parse::<Lora>()?.to_string(),
+            "<lora:add_detail:0.8>"
+        );
+        assert_eq!(
+            "add_detail".parse::<Lora>()?.to_string(),
+            "<lora:add_detail:1>"
+        );
+        assert_eq!(
+            "easynegative".parse::<Embedding>()?.to_string(),
+            "easynegative"
+        );
+        assert_eq!(
+            "easynegative:1.2".parse::<Embedding>()?.to_string(),
+            "(easynegative:1.2)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_extra_networks() -> Result<()> {
+        let params = crate::ImageParams {
+            prompt: crate::Prompt {
+                base: "A castle".to_string(),
+                suffix: Some("highly detailed".to_string()),
+                ..Default::default()
+            },
+            loras: vec!["add_detail:0.8".parse()?],
+            embeddings: vec!["style:1.1".parse()?],
+            negative_embeddings: vec!["easynegative".parse()?],
+            ..Default::default()
+        };
+        let rendered = params.with_extra_networks();
+        assert_eq!(
+            rendered.prompt.to_string(),
+            "A castle highly detailed (style:1.1) <lora:add_detail:0.8>"
+        );
+        assert_eq!(rendered.prompt.negative, Some("easynegative".to_string()));
+        assert!(rendered.loras.is_empty());
+        // Parameters without any networks are unchanged.
+        let params = crate::ImageParams::default();
+        assert_eq!(params.with_extra_networks(), params);
+        Ok(())
+    }
+}
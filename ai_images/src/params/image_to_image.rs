@@ -0,0 +1,113 @@
+use crate::images::string::file_to_base64;
+use anyhow::{Error, Result};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// How the init image is fitted to the requested width and height before it is changed.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InitImageResizeMode {
+    /// Stretch the image to the requested size.
+    #[default]
+    JustResize,
+    /// Scale the image to cover the requested size, then crop the edges.
+    CropAndResize,
+    /// Scale the image to fit inside the requested size, then fill the edges with the image's colors.
+    ResizeAndFill,
+    /// Stretch the image in latent space. Gives softer results than `JustResize`.
+    LatentUpscale,
+}
+
+impl InitImageResizeMode {
+    /// The value Stable Diffusion's API uses for this mode.
+    pub fn api_value(&self) -> u8 {
+        match self {
+            InitImageResizeMode::JustResize => 0,
+            InitImageResizeMode::CropAndResize => 1,
+            InitImageResizeMode::ResizeAndFill => 2,
+            InitImageResizeMode::LatentUpscale => 3,
+        }
+    }
+}
+
+/// An existing image to generate a new image from, e.g. a rough sketch or a previous portrait.
+#[derive(Args, Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct InitImage {
+    /// The path to the image to start from.
+    #[clap(long = "init-image")]
+    pub path: PathBuf,
+
+    /// How much the image is changed, from `0` (unchanged) to `1` (replaced entirely). Defaults to 0.75.
+    #[clap(long, default_value = "0.75")]
+    #[serde(default = "default_denoising_strength")]
+    pub denoising_strength: f32,
+
+    /// How the image is fitted to the requested width and height. Defaults to stretching it.
+    #[clap(long, value_enum, default_value = "just-resize")]
+    #[serde(default)]
+    pub resize_mode: InitImageResizeMode,
+}
+
+fn default_denoising_strength() -> f32 {
+    0.75
+}
+
+impl InitImage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            denoising_strength: default_denoising_strength(),
+            resize_mode: InitImageResizeMode::default(),
+        }
+    }
+
+    /// Check that the image exists and the denoising strength is in range, then encode the image in base64.
+    pub fn to_base64(&self) -> Result<String> {
+        if !(0.0..=1.0).contains(&self.denoising_strength) {
+            return Err(Error::msg(format!(
+                "The denoising strength must be between 0 and 1, but was {}.",
+                self.denoising_strength
+            )));
+        }
+        if !self.path.is_file() {
+            return Err(Error::msg(format!(
+                "The init image {:?} does not exist.",
+                self.path
+            )));
+        }
+        file_to_base64(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_defaults() -> Result<()> {
+        let init_image: InitImage = toml::from_str(r#"path = "sketch.png""#)?;
+        assert_eq!(init_image, InitImage::new(PathBuf::from("sketch.png")));
+        let init_image: InitImage = toml::from_str(
+            r#"
+path = "sketch.png"
+denoising_strength = 0.4
+resize_mode = "crop_and_resize"
+"#,
+        )?;
+        assert_eq!(init_image.denoising_strength, 0.4);
+        assert_eq!(init_image.resize_mode.api_value(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_init_image() {
+        let missing = InitImage::new(PathBuf::from("does-not-exist.png"));
+        assert!(missing.to_base64().is_err());
+        let too_strong = InitImage {
+            denoising_strength: 1.5,
+            ..InitImage::new(PathBuf::from("cargo.toml"))
+        };
+        assert!(too_strong.to_base64().is_err());
+    }
+}
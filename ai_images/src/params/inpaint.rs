@@ -0,0 +1,240 @@
+use anyhow::{Error, Result};
+use clap::{Args, ValueEnum};
+use image::{imageops::FilterType, GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A rectangle of an image, in pixels from the top left corner.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MaskRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for MaskRect {
+    type Err = Error;
+
+    /// Parse a rectangle given as `x,y,width,height`.
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| Error::msg(format!("Invalid mask rectangle {:?}: {}", s, e)))?;
+        match values[..] {
+            [x, y, width, height] => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(Error::msg(format!(
+                "Invalid mask rectangle {:?}. Expected `x,y,width,height`.",
+                s
+            ))),
+        }
+    }
+}
+
+/// What Stable Diffusion fills the masked area with before repainting it.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InpaintingFill {
+    /// Fill the area with the colors around it.
+    Fill,
+    /// Keep the original image in the area. Best for small fixes.
+    #[default]
+    Original,
+    /// Fill the area with noise. Best for replacing something entirely.
+    LatentNoise,
+    /// Fill the area with nothing.
+    LatentNothing,
+}
+
+impl InpaintingFill {
+    /// The value Stable Diffusion's API uses for this fill.
+    pub fn api_value(&self) -> u8 {
+        match self {
+            InpaintingFill::Fill => 0,
+            InpaintingFill::Original => 1,
+            InpaintingFill::LatentNoise => 2,
+            InpaintingFill::LatentNothing => 3,
+        }
+    }
+}
+
+/// An image to repaint part of, e.g. to fix a bad hand or swap a character's weapon.
+/// The area to repaint is given by either a mask image or a rectangle.
+#[derive(Args, Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct InpaintParams {
+    /// The path to the image to repaint.
+    #[clap(long)]
+    pub image: PathBuf,
+
+    /// A mask image. White areas are repainted and black areas are kept.
+    #[clap(long)]
+    pub mask: Option<PathBuf>,
+
+    /// A rectangle to repaint, given as `x,y,width,height` in pixels. Used instead of a mask image.
+    #[clap(long)]
+    pub mask_rect: Option<MaskRect>,
+
+    /// How much the masked area is changed, from `0` (unchanged) to `1` (replaced entirely). Defaults to 0.75.
+    /// Only used by Stable Diffusion.
+    #[clap(long, default_value = "0.75")]
+    #[serde(default = "default_denoising_strength")]
+    pub denoising_strength: f32,
+
+    /// What the masked area is filled with before it is repainted. Only used by Stable Diffusion.
+    #[clap(long, value_enum, default_value = "original")]
+    #[serde(default)]
+    pub inpainting_fill: InpaintingFill,
+
+    /// How many pixels the edge of the mask is blurred by. Only used by Stable Diffusion.
+    #[clap(long, default_value = "4")]
+    #[serde(default = "default_mask_blur")]
+    pub mask_blur: u32,
+}
+
+fn default_denoising_strength() -> f32 {
+    0.75
+}
+
+fn default_mask_blur() -> u32 {
+    4
+}
+
+impl InpaintParams {
+    /// Repaint the area covered by a mask image.
+    pub fn with_mask(image: PathBuf, mask: PathBuf) -> Self {
+        Self {
+            image,
+            mask: Some(mask),
+            mask_rect: None,
+            denoising_strength: default_denoising_strength(),
+            inpainting_fill: InpaintingFill::default(),
+            mask_blur: default_mask_blur(),
+        }
+    }
+
+    /// Repaint a rectangle of the image.
+    pub fn with_rect(image: PathBuf, mask_rect: MaskRect) -> Self {
+        Self {
+            mask: None,
+            mask_rect: Some(mask_rect),
+            ..Self::with_mask(image, PathBuf::new())
+        }
+    }
+
+    /// Check that the image exists and exactly one kind of mask was given.
+    pub fn validate(&self) -> Result<()> {
+        if !self.image.is_file() {
+            return Err(Error::msg(format!(
+                "The image to inpaint {:?} does not exist.",
+                self.image
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.denoising_strength) {
+            return Err(Error::msg(format!(
+                "The denoising strength must be between 0 and 1, but was {}.",
+                self.denoising_strength
+            )));
+        }
+        match (&self.mask, &self.mask_rect) {
+            (Some(_), Some(_)) => Err(Error::msg(
+                "Inpainting takes either a mask image or a mask rectangle, not both.",
+            )),
+            (None, None) => Err(Error::msg(
+                "Inpainting requires a mask image or a mask rectangle.",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Build the mask at the given size. White areas are repainted and black areas are kept.
+    /// Mask images with a different size are stretched to match.
+    pub fn mask_image(&self, width: u32, height: u32) -> Result<GrayImage> {
+        self.validate()?;
+        if let Some(mask) = &self.mask {
+            let mask = image::open(mask)?;
+            let mask = if (mask.width(), mask.height()) == (width, height) {
+                mask
+            } else {
+                mask.resize_exact(width, height, FilterType::Triangle)
+            };
+            return Ok(mask.to_luma8());
+        }
+        let mut mask = GrayImage::new(width, height);
+        if let Some(rect) = &self.mask_rect {
+            let x_end = rect.x.saturating_add(rect.width).min(width);
+            let y_end = rect.y.saturating_add(rect.height).min(height);
+            for y in rect.y.min(height)..y_end {
+                for x in rect.x.min(width)..x_end {
+                    mask.put_pixel(x, y, Luma([255]));
+                }
+            }
+        }
+        Ok(mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mask_rect() -> Result<()> {
+        let rect: MaskRect = "10, 20,30,40".parse()?;
+        assert_eq!(
+            rect,
+            MaskRect {
+                x: 10,
+                y: 20,
+                width: 30,
+                height: 40
+            }
+        );
+        assert!("10,20,30".parse::<MaskRect>().is_err());
+        assert!("a,b,c,d".parse::<MaskRect>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rect_mask_image() -> Result<()> {
+        let image_path = PathBuf::from("test_rect_mask_image.png");
+        GrayImage::new(32, 32).save(&image_path)?;
+        let params = InpaintParams::with_rect(
+            image_path.clone(),
+            MaskRect {
+                x: 24,
+                y: 0,
+                width: 16,
+                height: 8,
+            },
+        );
+        let mask = params.mask_image(32, 32);
+        std::fs::remove_file(image_path)?;
+        let mask = mask?;
+        assert_eq!(mask.get_pixel(30, 4)[0], 255);
+        assert_eq!(mask.get_pixel(30, 8)[0], 0);
+        assert_eq!(mask.get_pixel(0, 0)[0], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_requires_one_mask() -> Result<()> {
+        let params: InpaintParams = toml::from_str(
+            r#"
+image = "cargo.toml"
+mask = "mask.png"
+mask_rect = { x = 0, y = 0, width = 10, height = 10 }
+"#,
+        )?;
+        assert_eq!(params.inpainting_fill, InpaintingFill::Original);
+        assert!(params.validate().is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,17 @@
+mod controlnet;
+mod extra_networks;
+mod image_to_image;
+mod inpaint;
+mod operation;
 mod prompt;
 mod text_to_image;
+mod upscale;
 
+pub use controlnet::ControlNetUnit;
+pub use extra_networks::{Embedding, Lora};
+pub use image_to_image::{InitImage, InitImageResizeMode};
+pub use inpaint::{InpaintParams, InpaintingFill, MaskRect};
+pub use operation::ImageOperation;
 pub use prompt::Prompt;
 pub use text_to_image::ImageParams;
+pub use upscale::{Upscale, UpscaleParams};
@@ -0,0 +1,15 @@
+use super::{InitImage, InpaintParams, UpscaleParams};
+
+/// What an image is generated from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ImageOperation {
+    /// Generate a new image from the prompt.
+    #[default]
+    TextToImage,
+    /// Generate an image from an existing image and the prompt.
+    ImageToImage(InitImage),
+    /// Repaint the masked part of an existing image using the prompt.
+    Inpaint(InpaintParams),
+    /// Enlarge an existing image with one of the provider's upscalers.
+    Upscale(UpscaleParams),
+}
@@ -1,7 +1,14 @@
+use super::controlnet::ControlNetUnit;
+use super::extra_networks::{Embedding, Lora};
 use super::prompt::Prompt;
+use super::upscale::{scale_size, Upscale};
+use crate::images::{convert::OutputFormat, metadata::ImageMetadata, resize::ResizePolicy};
+use anyhow::{Error, Result};
 use clap::Args;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// Parameters for the image generation request.
 #[derive(Args, Deserialize, Debug, Serialize, Clone, PartialEq)]
@@ -34,9 +41,200 @@ pub struct ImageParams {
     #[clap(long, default_value = "UniPC")]
     pub sampler_name: String,
 
-    /// The CFG scale to use. Not supported by all providers.
+    /// The CFG scale to use, e.g. 6.5. Not supported by all providers.
     #[clap(long, default_value = "2")]
-    pub cfg_scale: u32,
+    pub cfg_scale: f32,
+
+    /// The noise schedule the sampler uses, e.g. `Karras` or `Exponential`. Defaults to the sampler's own.
+    /// Only used by Stable Diffusion.
+    #[clap(long)]
+    pub scheduler: Option<String>,
+
+    /// How many of the text encoder's last layers to skip, e.g. 2 for anime models. Only used by Stable Diffusion.
+    #[clap(long)]
+    pub clip_skip: Option<u32>,
+
+    /// The VAE to decode the image with, as listed by `/sdapi/v1/sd-vae`, e.g. `sdxl_vae.safetensors`.
+    /// Defaults to the instance's setting. Only used by Stable Diffusion.
+    #[clap(long)]
+    pub vae: Option<String>,
+
+    /// Generate an image that tiles seamlessly, e.g. for textures. Only used by Stable Diffusion.
+    #[clap(long)]
+    #[serde(default)]
+    pub tiling: bool,
+
+    /// Fix faces with the instance's face restoration model. Only used by Stable Diffusion.
+    #[clap(long)]
+    #[serde(default)]
+    pub restore_faces: bool,
+
+    /// Any other Stable Diffusion settings to use for this request only, by their `/sdapi/v1/options` names,
+    /// e.g. `eta_noise_seed_delta = 31337`. Only set in the configuration file, and only used by Stable Diffusion.
+    #[clap(skip)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub override_settings: BTreeMap<String, Value>,
+
+    /// How to fit the image to the requested width and height if the provider can't generate them natively.
+    /// Defaults to cropping.
+    #[clap(long, value_enum)]
+    pub resize_policy: Option<ResizePolicy>,
+
+    /// The seed to generate from. Random if unset or -1. Only used by Stable Diffusion and ComfyUI.
+    #[clap(long)]
+    pub seed: Option<i64>,
+
+    /// The variation seed, blended into the seed by `subseed_strength`. Only used by Stable Diffusion.
+    #[clap(long)]
+    pub subseed: Option<i64>,
+
+    /// How much of the variation seed to blend in, from 0 to 1. Only used by Stable Diffusion.
+    #[clap(long)]
+    pub subseed_strength: Option<f32>,
+
+    /// How many images to generate in each batch. Defaults to 1. Only used by Stable Diffusion and ComfyUI.
+    #[clap(long)]
+    pub batch_size: Option<u32>,
+
+    /// How many batches to generate. Defaults to 1. Only used by Stable Diffusion.
+    #[clap(long)]
+    pub n_iter: Option<u32>,
+
+    /// The file format to save the image in. Defaults to the format the provider returned.
+    #[clap(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// The quality to save JPEG images with, from 1 to 100. Defaults to 85.
+    #[clap(long = "image-quality", id = "image_quality")]
+    pub quality: Option<u8>,
+
+    /// The sizes of the thumbnails to save next to the image, e.g. `256,512`.
+    /// Each thumbnail fits in a square of that size.
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub thumbnail_sizes: Vec<u32>,
+
+    /// Generate at the requested size, then enlarge the image by `hr_scale` and add detail in a second pass.
+    /// Only used by Stable Diffusion.
+    #[clap(long)]
+    #[serde(default)]
+    pub enable_hr: bool,
+
+    /// How much the hires fix enlarges the image. Defaults to 2.
+    #[clap(long)]
+    pub hr_scale: Option<f32>,
+
+    /// The upscaler the hires fix enlarges the image with, e.g. `Latent` or `R-ESRGAN 4x+`. Defaults to `Latent`.
+    #[clap(long)]
+    pub hr_upscaler: Option<String>,
+
+    /// How much the second pass of the hires fix changes the image, from 0 to 1. Defaults to the instance's setting.
+    #[clap(long)]
+    pub denoising_strength: Option<f32>,
+
+    /// Enlarge every generated image with one of Stable Diffusion's upscalers after it is generated.
+    /// Only used by Stable Diffusion.
+    #[clap(skip)]
+    pub upscale: Option<Upscale>,
+
+    /// LoRAs to apply, as `name:weight`. Can be given more than once. Only used by Stable Diffusion.
+    #[clap(long = "lora")]
+    #[serde(default)]
+    pub loras: Vec<Lora>,
+
+    /// Textual-inversion embeddings to add to the prompt, as `name` or `name:weight`. Can be given more than once.
+    /// Only used by Stable Diffusion.
+    #[clap(long = "embedding")]
+    #[serde(default)]
+    pub embeddings: Vec<Embedding>,
+
+    /// Textual-inversion embeddings to add to the negative prompt. Only used by Stable Diffusion.
+    #[clap(long = "negative-embedding")]
+    #[serde(default)]
+    pub negative_embeddings: Vec<Embedding>,
+
+    /// ControlNet units that guide the composition, e.g. to keep the same pose across a row of characters.
+    /// Given as `image=pose.png,model=control_v11p_sd15_openpose,module=openpose`. Can be given more than once.
+    /// Only used by Stable Diffusion, and needs the ControlNet extension.
+    #[clap(long = "controlnet")]
+    #[serde(default)]
+    pub controlnet: Vec<ControlNetUnit>,
+
+    /// Saved with a queued Stable Diffusion task and handed back when the task is resumed,
+    /// e.g. whatever is needed to finish the asset the image belongs to. Not part of the configuration.
+    #[clap(skip)]
+    #[serde(skip)]
+    pub task_context: Value,
+
+    /// The width and height that were asked for, when `width` and `height` were rounded to a size the provider can
+    /// generate. Saved with a queued Stable Diffusion task, so its images are fitted to it when the task is resumed.
+    #[clap(skip)]
+    #[serde(skip)]
+    pub requested_size: Option<(u32, u32)>,
+}
+
+impl ImageParams {
+    /// Read the parameters saved in a generated PNG image, so it can be regenerated or varied.
+    pub fn from_image(path: &Path) -> Result<Self> {
+        let metadata = ImageMetadata::read(path)?.ok_or(Error::msg(format!(
+            "{:?} does not contain any generation parameters.",
+            path
+        )))?;
+        Ok(metadata.to_image_params())
+    }
+
+    /// Render the LoRAs and embeddings into the prompt in Automatic1111's syntax, after the suffix.
+    /// Negative embeddings are added to the end of the negative prompt.
+    pub fn with_extra_networks(&self) -> Self {
+        let networks: Vec<String> = self
+            .embeddings
+            .iter()
+            .map(ToString::to_string)
+            .chain(self.loras.iter().map(ToString::to_string))
+            .collect();
+        let negative_networks: Vec<String> = self
+            .negative_embeddings
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let append = |text: &Option<String>, networks: &[String]| match (text, networks.is_empty())
+        {
+            (_, true) => text.clone(),
+            (Some(text), false) if !text.is_empty() => {
+                Some(format!("{} {}", text, networks.join(" ")))
+            }
+            (_, false) => Some(networks.join(" ")),
+        };
+        Self {
+            prompt: Prompt {
+                suffix: append(&self.prompt.suffix, &networks),
+                negative: append(&self.prompt.negative, &negative_networks),
+                ..self.prompt.clone()
+            },
+            loras: Vec::new(),
+            embeddings: Vec::new(),
+            negative_embeddings: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// How much the hires fix enlarges the image, or 1 if it is disabled.
+    pub fn hires_scale(&self) -> f32 {
+        match self.enable_hr {
+            true => self.hr_scale.unwrap_or(2.0),
+            false => 1.0,
+        }
+    }
+
+    /// The size of the image after the hires fix. Fractions of a pixel are dropped, the same way Stable Diffusion does.
+    pub fn hires_size(&self, width: u32, height: u32) -> (u32, u32) {
+        scale_size(width, height, self.hires_scale())
+    }
+
+    /// The total number of images requested.
+    pub fn image_count(&self) -> u32 {
+        self.batch_size.unwrap_or(1).max(1) * self.n_iter.unwrap_or(1).max(1)
+    }
 }
 
 impl Default for ImageParams {
@@ -49,7 +247,82 @@ impl Default for ImageParams {
             height: 1024,
             steps: 15,
             sampler_name: "UniPC".to_string(),
-            cfg_scale: 2,
+            cfg_scale: 2.0,
+            scheduler: None,
+            clip_skip: None,
+            vae: None,
+            tiling: false,
+            restore_faces: false,
+            override_settings: BTreeMap::new(),
+            resize_policy: None,
+            seed: None,
+            subseed: None,
+            subseed_strength: None,
+            batch_size: None,
+            n_iter: None,
+            format: None,
+            quality: None,
+            thumbnail_sizes: Vec::new(),
+            enable_hr: false,
+            hr_scale: None,
+            hr_upscaler: None,
+            denoising_strength: None,
+            upscale: None,
+            loras: Vec::new(),
+            embeddings: Vec::new(),
+            negative_embeddings: Vec::new(),
+            controlnet: Vec::new(),
+            task_context: Value::Null,
+            requested_size: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_settings() -> Result<()> {
+        // Configurations written before the CFG scale could be fractional still load.
+        let params: ImageParams = toml::from_str(
+            r#"
+            prompt = { base = "A cat" }
+            output_directory = "."
+            width = 1024
+            height = 1024
+            steps = 15
+            sampler_name = "UniPC"
+            cfg_scale = 2
+            "#,
+        )?;
+        assert_eq!(params.cfg_scale, 2.0);
+        assert!(!params.tiling && !params.restore_faces);
+        assert!(params.override_settings.is_empty());
+
+        let params: ImageParams = toml::from_str(
+            r#"
+            prompt = { base = "A cat" }
+            output_directory = "."
+            width = 1024
+            height = 1024
+            steps = 15
+            sampler_name = "DPM++ 2M"
+            cfg_scale = 6.5
+            scheduler = "Karras"
+            clip_skip = 2
+            tiling = true
+            override_settings = { eta_noise_seed_delta = 31337 }
+            "#,
+        )?;
+        assert_eq!(params.cfg_scale, 6.5);
+        assert_eq!(params.scheduler.as_deref(), Some("Karras"));
+        assert_eq!(params.clip_skip, Some(2));
+        assert!(params.tiling);
+        assert_eq!(
+            params.override_settings.get("eta_noise_seed_delta"),
+            Some(&Value::from(31337))
+        );
+        Ok(())
+    }
+}
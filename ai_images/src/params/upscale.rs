@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// One of Stable Diffusion's upscalers, and how much to enlarge the image with it.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct Upscale {
+    /// The upscaler to use, as listed by `/sdapi/v1/upscalers`. Defaults to `R-ESRGAN 4x+`.
+    #[serde(default = "default_upscaler")]
+    pub upscaler: String,
+
+    /// How many times larger to make the image. Defaults to 2.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_upscaler() -> String {
+    "R-ESRGAN 4x+".to_string()
+}
+
+fn default_scale() -> f32 {
+    2.0
+}
+
+impl Default for Upscale {
+    fn default() -> Self {
+        Self {
+            upscaler: default_upscaler(),
+            scale: default_scale(),
+        }
+    }
+}
+
+impl Upscale {
+    /// The size of an image after it is upscaled. Fractions of a pixel are dropped, the same way Stable Diffusion does.
+    pub fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        scale_size(width, height, self.scale)
+    }
+}
+
+/// Scale a size, dropping fractions of a pixel.
+pub(crate) fn scale_size(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    (
+        (width as f32 * scale) as u32,
+        (height as f32 * scale) as u32,
+    )
+}
+
+/// An existing image to enlarge, e.g. a finished portrait that needs to be printed.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq)]
+pub struct UpscaleParams {
+    /// The path to the image to upscale.
+    pub image: PathBuf,
+
+    /// The upscaler and how much to enlarge the image.
+    #[serde(flatten)]
+    pub upscale: Upscale,
+}
+
+impl UpscaleParams {
+    /// Upscale the image with the default upscaler.
+    pub fn new(image: PathBuf) -> Self {
+        Self {
+            image,
+            upscale: Upscale::default(),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Report the progress of a generation while it runs.
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An update on a generation that is still running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// The request is waiting in the provider's queue, behind `position` other tasks.
+    Queued { position: usize },
+    /// The image is being rendered.
+    Rendering {
+        /// The current sampling step of the current pass.
+        step: u32,
+        /// The number of sampling steps in the current pass.
+        steps: u32,
+        /// How much of the whole request is done, from 0 to 1. Includes every batch and the hires fix.
+        fraction: f32,
+        /// How long the provider expects the rest of the request to take.
+        eta: Option<Duration>,
+        /// A preview of the image in progress, if the provider sends them.
+        preview: Option<PathBuf>,
+    },
+}
+
+/// Receives progress events while images are generated. Does nothing by default.
+#[derive(Clone, Default)]
+pub struct Progress {
+    callback: Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>,
+}
+
+impl Progress {
+    /// Call `callback` with every progress event.
+    pub fn new(callback: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Some(Arc::new(callback)),
+        }
+    }
+
+    /// Whether anyone is listening. Providers skip polling for progress if not.
+    pub fn is_enabled(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    /// Send an event to the callback, if there is one.
+    pub fn report(&self, event: ProgressEvent) {
+        if let Some(callback) = &self.callback {
+            callback(event);
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+impl fmt::Display for ProgressEvent {
+    /// A one-line progress bar, e.g. `[#####---------------] 25% step 5/20, 12s left`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgressEvent::Queued { position: 0 } => write!(f, "Queued, up next"),
+            ProgressEvent::Queued { position } => {
+                write!(f, "Queued, {} task(s) ahead", position)
+            }
+            ProgressEvent::Rendering {
+                step,
+                steps,
+                fraction,
+                eta,
+                ..
+            } => {
+                const WIDTH: usize = 20;
+                let fraction = fraction.clamp(0.0, 1.0);
+                let filled = (fraction * WIDTH as f32).round() as usize;
+                write!(
+                    f,
+                    "[{}{}] {:>3.0}% step {}/{}",
+                    "#".repeat(filled),
+                    "-".repeat(WIDTH - filled),
+                    fraction * 100.0,
+                    step,
+                    steps
+                )?;
+                if let Some(eta) = eta {
+                    write!(f, ", {}s left", eta.as_secs())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_report() -> anyhow::Result<()> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let received = events.clone();
+        let progress = Progress::new(move |event| {
+            if let Ok(mut events) = received.lock() {
+                events.push(event);
+            }
+        });
+        assert!(progress.is_enabled());
+        progress.report(ProgressEvent::Queued { position: 2 });
+        let events = events.lock().map_err(|_| anyhow::Error::msg("poisoned"))?;
+        assert_eq!(events.as_slice(), &[ProgressEvent::Queued { position: 2 }]);
+        // Reporting without a callback does nothing.
+        Progress::default().report(ProgressEvent::Queued { position: 0 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_display() {
+        let event = ProgressEvent::Rendering {
+            step: 5,
+            steps: 20,
+            fraction: 0.25,
+            eta: Some(Duration::from_secs(12)),
+            preview: None,
+        };
+        assert_eq!(
+            event.to_string(),
+            "[#####---------------]  25% step 5/20, 12s left"
+        );
+        assert_eq!(
+            ProgressEvent::Queued { position: 3 }.to_string(),
+            "Queued, 3 task(s) ahead"
+        );
+    }
+}
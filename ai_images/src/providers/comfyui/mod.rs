@@ -0,0 +1,8 @@
+//! Generate images with a ComfyUI server by running a workflow exported in the API format.
+
+mod provider;
+mod workflow;
+
+use super::{ImageData, ImageParams, ImageProvider};
+pub use provider::ComfyUiProvider;
+pub use workflow::{ComfyUiInputs, Workflow};
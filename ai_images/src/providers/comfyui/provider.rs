@@ -0,0 +1,248 @@
+use super::{ComfyUiInputs, ImageData, ImageParams, ImageProvider, Workflow};
+use crate::cancel::CancellationToken;
+use crate::progress::Progress;
+use crate::providers::seconds;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A provider for generating images with a ComfyUI server by running a workflow.
+#[derive(Args, Deserialize, Debug, Serialize)]
+pub struct ComfyUiProvider {
+    /// The URL for the ComfyUI server.
+    pub url: String,
+
+    /// The workflow to run, exported from ComfyUI with "Export (API)".
+    pub workflow: PathBuf,
+
+    /// Which node inputs of the workflow the image parameters are written to.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub inputs: ComfyUiInputs,
+
+    /// How often the workflow is checked on, in seconds. Defaults to 1.
+    #[clap(long = "comfyui-poll-interval")]
+    pub poll_interval: Option<f32>,
+
+    /// How long to wait for the workflow to finish, in seconds. Defaults to 300.
+    #[clap(long = "comfyui-task-timeout")]
+    pub task_timeout: Option<f32>,
+}
+
+impl Default for ComfyUiProvider {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8188".to_string(),
+            workflow: PathBuf::from("workflow_api.json"),
+            inputs: ComfyUiInputs::default(),
+            poll_interval: None,
+            task_timeout: None,
+        }
+    }
+}
+
+/// An image saved by the workflow, as listed in the history.
+#[derive(Debug, Deserialize, PartialEq)]
+struct OutputImage {
+    filename: String,
+    #[serde(default)]
+    subfolder: String,
+    #[serde(rename = "type")]
+    folder_type: String,
+}
+
+#[async_trait]
+impl ImageProvider for ComfyUiProvider {
+    /// Fill in the workflow, queue it, and download every image it saves.
+    async fn text_to_image(
+        &self,
+        params: ImageParams,
+        _progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let mut workflow = Workflow::from_file(&self.workflow)?;
+        // ComfyUI needs an explicit seed, so a random one is picked if none was requested.
+        let seed = match params.seed {
+            Some(seed) if seed >= 0 => seed,
+            _ => rand::random::<u32>().into(),
+        };
+        let seed = workflow.fill(&self.inputs, &params, seed)?;
+
+        let prompt_id = self.queue_prompt(&workflow).await?;
+        // Cancelling stops waiting for the workflow, but ComfyUI still finishes running it.
+        let outputs = cancel
+            .run_until_cancelled(self.poll_history(&prompt_id))
+            .await?;
+        let mut images = Vec::new();
+        for output in &outputs {
+            // The format is read from the downloaded data, since a workflow can save in any format.
+            images.push(ImageData::new(self.download(output).await?)?.with_seed(seed));
+        }
+        Ok(images)
+    }
+}
+
+impl ComfyUiProvider {
+    /// Get the sanitized URL for the ComfyUI server.
+    /// The URL should not have a trailing slash.
+    pub fn get_url(&self) -> String {
+        self.url.trim_end_matches('/').to_string()
+    }
+
+    /// Send a POST request to `/prompt` to queue the workflow, returning its prompt ID.
+    async fn queue_prompt(&self, workflow: &Workflow) -> Result<String> {
+        let url = format!("{}/prompt", self.get_url());
+        let body = json!({ "prompt": workflow.to_value(), "client_id": "ai_images" });
+        let client = reqwest::Client::new();
+        let response = client.post(url).json(&body).send().await.map_err(|e| {
+            anyhow!(
+                "Could not reach ComfyUI at {}. Is it running? {}",
+                self.get_url(),
+                e
+            )
+        })?;
+        let status = response.status();
+        let response_text = response.text().await?;
+        // Invalid workflows are rejected with the errors of each node.
+        if !status.is_success() {
+            return Err(anyhow!(
+                "ComfyUI rejected the workflow with status {}. Response: {}",
+                status,
+                response_text
+            ));
+        }
+        let response: Value = serde_json::from_str(&response_text)?;
+        response["prompt_id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(anyhow!(
+                "ComfyUI did not return a prompt ID. Response: {:?}",
+                response_text
+            ))
+    }
+
+    /// Get the images saved by the workflow, or None if it hasn't finished yet.
+    async fn get_history(&self, prompt_id: &str) -> Result<Option<Vec<OutputImage>>> {
+        let url = format!("{}/history/{}", self.get_url(), prompt_id);
+        let response = reqwest::get(url).await?;
+        let response: Value = serde_json::from_str(&response.text().await?)?;
+        // The history is empty until the workflow has finished running.
+        let Some(entry) = response.get(prompt_id) else {
+            return Ok(None);
+        };
+        if entry["status"]["status_str"] == "error" {
+            return Err(anyhow!(
+                "The ComfyUI workflow failed. Messages: {}",
+                entry["status"]["messages"]
+            ));
+        }
+        if entry["status"]["completed"] == false {
+            return Ok(None);
+        }
+        images_from_outputs(&entry["outputs"]).map(Some)
+    }
+
+    /// How often the workflow is checked on.
+    pub(crate) fn poll_interval(&self) -> Result<Duration> {
+        seconds("poll_interval", self.poll_interval.unwrap_or(1.0))
+    }
+
+    /// How long to wait for the workflow before giving up on it.
+    pub(crate) fn task_timeout(&self) -> Result<Duration> {
+        seconds("task_timeout", self.task_timeout.unwrap_or(300.0))
+    }
+
+    /// Poll the history until the workflow has finished, returning the images it saved.
+    async fn poll_history(&self, prompt_id: &str) -> Result<Vec<OutputImage>> {
+        let timeout = self.task_timeout()?;
+        let mut interval = tokio::time::interval(self.poll_interval()?);
+        let poll = async {
+            loop {
+                interval.tick().await;
+                if let Some(images) = self.get_history(prompt_id).await? {
+                    return Ok(images);
+                }
+            }
+        };
+        tokio::time::timeout(timeout, poll).await.map_err(|_| {
+            anyhow!(
+                "The ComfyUI workflow timed out after {} seconds.",
+                timeout.as_secs_f32()
+            )
+        })?
+    }
+
+    /// Download an image saved by the workflow from `/view`.
+    async fn download(&self, image: &OutputImage) -> Result<Vec<u8>> {
+        let url = format!("{}/view", self.get_url());
+        let response = reqwest::Client::new()
+            .get(url)
+            .query(&[
+                ("filename", image.filename.as_str()),
+                ("subfolder", image.subfolder.as_str()),
+                ("type", image.folder_type.as_str()),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Unable to download {:?} from ComfyUI. Status: {}",
+                image.filename,
+                response.status()
+            ));
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// Collect the images from the outputs of every node, in node order.
+/// Previews are saved to the temp folder and are skipped, so only the workflow's saved images are returned.
+fn images_from_outputs(outputs: &Value) -> Result<Vec<OutputImage>> {
+    let Some(outputs) = outputs.as_object() else {
+        return Ok(Vec::new());
+    };
+    let mut node_ids: Vec<&String> = outputs.keys().collect();
+    node_ids.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+    let mut images = Vec::new();
+    for node_id in node_ids {
+        if let Some(node_images) = outputs[node_id].get("images") {
+            let node_images: Vec<OutputImage> = serde_json::from_value(node_images.clone())?;
+            images.extend(
+                node_images
+                    .into_iter()
+                    .filter(|image| image.folder_type == "output"),
+            );
+        }
+    }
+    if images.is_empty() {
+        return Err(anyhow!(
+            "The ComfyUI workflow finished without saving any images. Does it have a Save Image node?"
+        ));
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images_from_outputs() -> Result<()> {
+        let outputs = json!({
+            "10": { "images": [{ "filename": "preview.png", "subfolder": "", "type": "temp" }] },
+            "9": { "images": [
+                { "filename": "ComfyUI_00001_.png", "subfolder": "", "type": "output" },
+                { "filename": "ComfyUI_00002_.png", "subfolder": "", "type": "output" }
+            ] }
+        });
+        let images = images_from_outputs(&outputs)?;
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].filename, "ComfyUI_00001_.png");
+        assert!(images_from_outputs(&json!({})).is_err());
+        Ok(())
+    }
+}
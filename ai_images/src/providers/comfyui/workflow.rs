@@ -0,0 +1,339 @@
+//! Fill in the inputs of a ComfyUI workflow that was exported in the API format.
+
+use crate::ImageParams;
+use anyhow::{Error, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Which node inputs of the workflow the image parameters are written to.
+/// Each input is given as `node_id.input_name`, e.g. `6.text`.
+/// Inputs that aren't set are found from the workflow's sampler, latent image, and checkpoint loader nodes.
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ComfyUiInputs {
+    /// The node input the prompt is written to. Defaults to the text of the sampler's positive conditioning.
+    #[clap(long)]
+    pub prompt_input: Option<String>,
+
+    /// The node input the negative prompt is written to. Defaults to the text of the sampler's negative conditioning.
+    #[clap(long)]
+    pub negative_prompt_input: Option<String>,
+
+    /// The node input the width is written to. Defaults to the latent image's width.
+    #[clap(long)]
+    pub width_input: Option<String>,
+
+    /// The node input the height is written to. Defaults to the latent image's height.
+    #[clap(long)]
+    pub height_input: Option<String>,
+
+    /// The node input the batch size is written to. Defaults to the latent image's batch size.
+    #[clap(long)]
+    pub batch_size_input: Option<String>,
+
+    /// The node input the seed is written to. Defaults to the sampler's seed.
+    #[clap(long)]
+    pub seed_input: Option<String>,
+
+    /// The node input the number of steps is written to. Defaults to the sampler's steps.
+    #[clap(long)]
+    pub steps_input: Option<String>,
+
+    /// The node input the model is written to. Defaults to the checkpoint loader's checkpoint.
+    #[clap(long)]
+    pub model_input: Option<String>,
+}
+
+/// A reference to one input of one node, written as `node_id.input_name`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeInput {
+    pub node: String,
+    pub input: String,
+}
+
+impl FromStr for NodeInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Node IDs of grouped nodes can contain colons, but input names never contain dots.
+        match s.rsplit_once('.') {
+            Some((node, input)) if !node.is_empty() && !input.is_empty() => Ok(NodeInput {
+                node: node.to_string(),
+                input: input.to_string(),
+            }),
+            _ => Err(Error::msg(format!(
+                "The workflow input {:?} must be written as `node_id.input_name`, e.g. `6.text`.",
+                s
+            ))),
+        }
+    }
+}
+
+const SAMPLERS: &[&str] = &["KSampler", "KSamplerAdvanced"];
+const LATENT_IMAGES: &[&str] = &["EmptyLatentImage", "EmptySD3LatentImage"];
+const CHECKPOINT_LOADERS: &[&str] = &["CheckpointLoaderSimple"];
+
+/// A ComfyUI workflow in the API format: an object of nodes keyed by their ID.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Workflow {
+    nodes: Map<String, Value>,
+}
+
+impl Workflow {
+    /// Load a workflow exported from ComfyUI with "Export (API)".
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let workflow = std::fs::read_to_string(path).map_err(|e| {
+            Error::msg(format!(
+                "Unable to read the ComfyUI workflow {:?}: {}",
+                path, e
+            ))
+        })?;
+        Self::from_value(serde_json::from_str(&workflow)?)
+    }
+
+    pub fn from_value(workflow: Value) -> Result<Self> {
+        // Workflows saved from the editor list their nodes in an array and can't be queued.
+        if workflow.get("nodes").is_some_and(Value::is_array) {
+            return Err(Error::msg(
+                "The ComfyUI workflow was saved in the editor format. Export it with \"Export (API)\" instead.",
+            ));
+        }
+        let Value::Object(nodes) = workflow else {
+            return Err(Error::msg(
+                "The ComfyUI workflow must be an object of nodes keyed by their ID.",
+            ));
+        };
+        Ok(Self { nodes })
+    }
+
+    /// The workflow as sent to ComfyUI's `/prompt` endpoint.
+    pub fn to_value(&self) -> Value {
+        Value::Object(self.nodes.clone())
+    }
+
+    /// Write the image parameters into the workflow, returning the seed if the workflow has a seed input.
+    pub fn fill(
+        &mut self,
+        inputs: &ComfyUiInputs,
+        params: &ImageParams,
+        seed: i64,
+    ) -> Result<Option<i64>> {
+        let prompt = self
+            .resolve(&inputs.prompt_input, |workflow| {
+                workflow.conditioning_text("positive")
+            })?
+            .ok_or(Error::msg(
+                "Unable to find where to write the prompt in the ComfyUI workflow. Set `prompt_input`, e.g. `6.text`.",
+            ))?;
+        self.set(&prompt, json!(params.prompt.to_string()))?;
+
+        if let Some(negative) = &params.prompt.negative
+            && let Some(input) = self.resolve(&inputs.negative_prompt_input, |workflow| {
+                workflow.conditioning_text("negative")
+            })?
+        {
+            self.set(&input, json!(negative))?;
+        }
+        if let Some(input) = self.resolve(&inputs.width_input, |workflow| {
+            workflow.find(LATENT_IMAGES, "width")
+        })? {
+            self.set(&input, json!(params.width))?;
+        }
+        if let Some(input) = self.resolve(&inputs.height_input, |workflow| {
+            workflow.find(LATENT_IMAGES, "height")
+        })? {
+            self.set(&input, json!(params.height))?;
+        }
+        if let Some(batch_size) = params.batch_size
+            && let Some(input) = self.resolve(&inputs.batch_size_input, |workflow| {
+                workflow.find(LATENT_IMAGES, "batch_size")
+            })?
+        {
+            self.set(&input, json!(batch_size))?;
+        }
+        if let Some(input) = self.resolve(&inputs.steps_input, |workflow| {
+            workflow.find(SAMPLERS, "steps")
+        })? {
+            self.set(&input, json!(params.steps))?;
+        }
+        if let Some(model) = &params.model
+            && let Some(input) = self.resolve(&inputs.model_input, |workflow| {
+                workflow.find(CHECKPOINT_LOADERS, "ckpt_name")
+            })?
+        {
+            self.set(&input, json!(model))?;
+        }
+
+        // KSamplerAdvanced calls its seed `noise_seed`.
+        let seed_input = self.resolve(&inputs.seed_input, |workflow| {
+            workflow
+                .find(SAMPLERS, "seed")
+                .or_else(|| workflow.find(SAMPLERS, "noise_seed"))
+        })?;
+        match seed_input {
+            Some(input) => {
+                self.set(&input, json!(seed))?;
+                Ok(Some(seed))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Use the configured input if there is one, otherwise look for the default one.
+    fn resolve(
+        &self,
+        configured: &Option<String>,
+        default: impl Fn(&Self) -> Option<NodeInput>,
+    ) -> Result<Option<NodeInput>> {
+        match configured {
+            Some(input) => Ok(Some(input.parse()?)),
+            None => Ok(default(self)),
+        }
+    }
+
+    /// Set the value of a node input. The node must exist, but the input doesn't have to.
+    fn set(&mut self, target: &NodeInput, value: Value) -> Result<()> {
+        let inputs = self
+            .nodes
+            .get_mut(&target.node)
+            .and_then(|node| node.get_mut("inputs"))
+            .and_then(Value::as_object_mut)
+            .ok_or(Error::msg(format!(
+                "The ComfyUI workflow has no node {:?} with inputs.",
+                target.node
+            )))?;
+        inputs.insert(target.input.clone(), value);
+        Ok(())
+    }
+
+    /// The node IDs in numeric order, so the first sampler in the workflow is used when there are several.
+    fn node_ids(&self) -> Vec<&String> {
+        let mut ids: Vec<&String> = self.nodes.keys().collect();
+        ids.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+        ids
+    }
+
+    /// Find the first node of one of the class types that has the input.
+    fn find(&self, class_types: &[&str], input: &str) -> Option<NodeInput> {
+        self.node_ids().into_iter().find_map(|id| {
+            let node = &self.nodes[id];
+            let class_type = node["class_type"].as_str()?;
+            (class_types.contains(&class_type) && node["inputs"].get(input).is_some()).then(|| {
+                NodeInput {
+                    node: id.clone(),
+                    input: input.to_string(),
+                }
+            })
+        })
+    }
+
+    /// Follow the sampler's positive or negative conditioning to the text input of the node it comes from.
+    fn conditioning_text(&self, conditioning: &str) -> Option<NodeInput> {
+        let sampler = self.find(SAMPLERS, conditioning)?;
+        // Links are written as `[node_id, output_index]`.
+        let linked = self.nodes[&sampler.node]["inputs"][conditioning][0].as_str()?;
+        self.nodes.get(linked)?["inputs"].get("text")?;
+        Some(NodeInput {
+            node: linked.to_string(),
+            input: "text".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Prompt;
+
+    fn workflow() -> Result<Workflow> {
+        Workflow::from_value(json!({
+            "3": {
+                "class_type": "KSampler",
+                "inputs": {
+                    "seed": 0, "steps": 20, "cfg": 8, "sampler_name": "euler",
+                    "model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]
+                }
+            },
+            "4": { "class_type": "CheckpointLoaderSimple", "inputs": { "ckpt_name": "sd_xl_base_1.0.safetensors" } },
+            "5": { "class_type": "EmptyLatentImage", "inputs": { "width": 512, "height": 512, "batch_size": 1 } },
+            "6": { "class_type": "CLIPTextEncode", "inputs": { "text": "", "clip": ["4", 1] } },
+            "7": { "class_type": "CLIPTextEncode", "inputs": { "text": "", "clip": ["4", 1] } },
+            "9": { "class_type": "SaveImage", "inputs": { "filename_prefix": "ComfyUI", "images": ["8", 0] } }
+        }))
+    }
+
+    fn params() -> ImageParams {
+        ImageParams {
+            prompt: Prompt {
+                base: "A castle".to_string(),
+                negative: Some("blurry".to_string()),
+                ..Default::default()
+            },
+            width: 768,
+            height: 1024,
+            batch_size: Some(2),
+            model: Some("juggernaut.safetensors".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fill_default_inputs() -> Result<()> {
+        let mut workflow = workflow()?;
+        let seed = workflow.fill(&ComfyUiInputs::default(), &params(), 42)?;
+        assert_eq!(seed, Some(42));
+        let filled = workflow.to_value();
+        assert_eq!(filled["6"]["inputs"]["text"], "A castle");
+        assert_eq!(filled["7"]["inputs"]["text"], "blurry");
+        assert_eq!(filled["5"]["inputs"]["width"], 768);
+        assert_eq!(filled["5"]["inputs"]["height"], 1024);
+        assert_eq!(filled["5"]["inputs"]["batch_size"], 2);
+        assert_eq!(filled["3"]["inputs"]["seed"], 42);
+        assert_eq!(filled["3"]["inputs"]["steps"], 15);
+        assert_eq!(filled["4"]["inputs"]["ckpt_name"], "juggernaut.safetensors");
+        // Inputs without a matching parameter keep their values.
+        assert_eq!(filled["3"]["inputs"]["sampler_name"], "euler");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_configured_inputs() -> Result<()> {
+        let mut workflow = workflow()?;
+        let inputs = ComfyUiInputs {
+            prompt_input: Some("7.text".to_string()),
+            negative_prompt_input: Some("6.text".to_string()),
+            ..Default::default()
+        };
+        workflow.fill(&inputs, &params(), 42)?;
+        let filled = workflow.to_value();
+        assert_eq!(filled["7"]["inputs"]["text"], "A castle");
+        assert_eq!(filled["6"]["inputs"]["text"], "blurry");
+
+        let missing = ComfyUiInputs {
+            prompt_input: Some("99.text".to_string()),
+            ..Default::default()
+        };
+        assert!(workflow.fill(&missing, &params(), 42).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_editor_format_is_rejected() {
+        let workflow = Workflow::from_value(json!({ "nodes": [], "links": [] }));
+        assert!(workflow.is_err());
+    }
+
+    #[test]
+    fn test_node_input_from_str() {
+        assert_eq!(
+            "12:3.text".parse::<NodeInput>().ok(),
+            Some(NodeInput {
+                node: "12:3".to_string(),
+                input: "text".to_string(),
+            })
+        );
+        assert!("text".parse::<NodeInput>().is_err());
+    }
+}
@@ -1,51 +1,469 @@
 //! Generate images for RPG assets.
 
+mod comfyui;
 mod openai;
+mod stability_ai;
 mod stable_diffusion;
 
-use super::images::Base64Image;
-use super::params::ImageParams;
-use anyhow::Result;
+use super::cancel::CancellationToken;
+use super::images::{
+    metadata::ImageMetadata, resize, storage, Base64Image, GeneratedImage, ImageData,
+};
+use super::params::{
+    ImageOperation, ImageParams, InitImage, InpaintParams, Upscale, UpscaleParams,
+};
+use super::progress::Progress;
+use anyhow::{Error, Result};
 use async_trait::async_trait;
 use clap::Subcommand;
-pub use openai::OpenAiProvider;
+pub use comfyui::{ComfyUiInputs, ComfyUiProvider};
+pub use openai::{
+    OpenAiImageBackground, OpenAiImageFormat, OpenAiImageModel, OpenAiImageOptions,
+    OpenAiImageQuality, OpenAiImageStyle, OpenAiProvider,
+};
+use rate_limiter::RateLimits;
 use serde::{Deserialize, Serialize};
-pub use stable_diffusion::StableDiffusionXLProvider;
-use std::path::PathBuf;
+pub use stability_ai::{
+    StabilityAiEndpoint, StabilityAiFormat, StabilityAiOptions, StabilityAiProvider,
+};
+pub use stable_diffusion::{
+    PendingTask, StableDiffusionEmbeddings, StableDiffusionLora, StableDiffusionMode,
+    StableDiffusionModel, StableDiffusionXLProvider,
+};
+use std::path::Path;
+use std::time::Duration;
 
 /// Different image generation providers.
 #[derive(Subcommand, Deserialize, Debug, Serialize)]
 pub enum ImageProviders {
     OpenAi(openai::OpenAiProvider),
     StableDiffusion(stable_diffusion::StableDiffusionXLProvider),
+    ComfyUi(comfyui::ComfyUiProvider),
+    StabilityAi(stability_ai::StabilityAiProvider),
+}
+
+/// A queued task that was resumed, with the images it saved or the reason it failed.
+#[derive(Debug)]
+pub struct ResumedTask {
+    pub task: PendingTask,
+    pub images: Result<Vec<GeneratedImage>>,
 }
 
 /// Defines an image generation provider.
+/// Providers only return the images they generated, and `ImageProviders` resizes, converts, and saves them,
+/// so every provider's images are named and saved the same way.
 #[async_trait]
 pub trait ImageProvider {
-    /// Generate an image and return the file path where it is saved.
-    async fn text_to_image(&self, params: ImageParams) -> Result<PathBuf>;
+    /// Generate images and return every image that was generated.
+    /// Providers that can report their progress send it to `progress` while they run.
+    /// If `cancel` is triggered, providers stop their job where they can and return a `Cancelled` error.
+    async fn text_to_image(
+        &self,
+        params: ImageParams,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>>;
+
+    /// Generate images from an existing image and return every image that was generated.
+    /// Providers that don't support this return an error.
+    async fn image_to_image(
+        &self,
+        _params: ImageParams,
+        _init_image: InitImage,
+        _progress: &Progress,
+        _cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        Err(Error::msg(
+            "This provider does not support generating images from an init image.",
+        ))
+    }
+
+    /// Repaint the masked part of an existing image and return every result.
+    /// Providers that don't support this return an error.
+    async fn inpaint(
+        &self,
+        _params: ImageParams,
+        _inpaint: InpaintParams,
+        _progress: &Progress,
+        _cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        Err(Error::msg("This provider does not support inpainting."))
+    }
+
+    /// Enlarge an image with one of the provider's upscalers. Providers that don't support this return an error.
+    async fn upscale(&self, _image: &ImageData, _upscale: &Upscale) -> Result<ImageData> {
+        Err(Error::msg("This provider does not support upscaling."))
+    }
 }
 
 impl ImageProviders {
-    /// Generate an image using the specified provider.
-    pub async fn generate_image(&self, params: ImageParams) -> Result<PathBuf> {
+    /// Generate images using the specified provider. Stable Diffusion returns one image per requested variant.
+    /// If the provider can't generate the requested size, the closest size it supports is generated and then resized.
+    pub async fn generate_image(&self, params: ImageParams) -> Result<Vec<GeneratedImage>> {
+        self.run(
+            params,
+            ImageOperation::TextToImage,
+            &Progress::default(),
+            &CancellationToken::default(),
+        )
+        .await
+    }
+
+    /// Generate an image from an existing image, e.g. to restyle a sketch or iterate on a previous image.
+    pub async fn image_to_image(
+        &self,
+        params: ImageParams,
+        init_image: InitImage,
+    ) -> Result<Vec<GeneratedImage>> {
+        self.run(
+            params,
+            ImageOperation::ImageToImage(init_image),
+            &Progress::default(),
+            &CancellationToken::default(),
+        )
+        .await
+    }
+
+    /// Repaint the masked part of an existing image, e.g. to fix a bad hand or swap a character's weapon.
+    /// The result has the same size as the original image, regardless of the requested width and height.
+    pub async fn inpaint(
+        &self,
+        params: ImageParams,
+        inpaint: InpaintParams,
+    ) -> Result<Vec<GeneratedImage>> {
+        self.run(
+            params,
+            ImageOperation::Inpaint(inpaint),
+            &Progress::default(),
+            &CancellationToken::default(),
+        )
+        .await
+    }
+
+    /// Enlarge an existing image, e.g. to print a finished portrait. The parameters it was generated with are kept.
+    pub async fn upscale(
+        &self,
+        params: ImageParams,
+        upscale: UpscaleParams,
+    ) -> Result<Vec<GeneratedImage>> {
+        self.run(
+            params,
+            ImageOperation::Upscale(upscale),
+            &Progress::default(),
+            &CancellationToken::default(),
+        )
+        .await
+    }
+
+    /// Generate an image using the specified provider and operation, sending progress events to `progress`.
+    /// Triggering `cancel` stops the generation and returns a `Cancelled` error. Stable Diffusion also stops the job
+    /// on the instance, so it doesn't keep using the GPU.
+    pub async fn run(
+        &self,
+        params: ImageParams,
+        operation: ImageOperation,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<GeneratedImage>> {
+        let (width, height) = match &operation {
+            ImageOperation::Inpaint(inpaint) => {
+                inpaint.validate()?;
+                image::image_dimensions(&inpaint.image)?
+            }
+            ImageOperation::Upscale(upscale) => image::image_dimensions(&upscale.image)?,
+            _ => (params.width, params.height),
+        };
+        let (native_width, native_height) = self.native_size(width, height);
+        let request = ImageParams {
+            width: native_width,
+            height: native_height,
+            requested_size: Some((width, height)),
+            ..params.clone()
+        };
+        // The hires fix and upscaling make the image larger than the requested size.
+        let (width, height) = match &operation {
+            ImageOperation::TextToImage => self.hires_size(&params, width, height),
+            ImageOperation::Upscale(upscale) => upscale.upscale.scaled_size(width, height),
+            _ => (width, height),
+        };
+        // Upscaling an existing image keeps the parameters it was generated with, if it has any.
+        let source_metadata = match &operation {
+            ImageOperation::Upscale(upscale) => {
+                Some(ImageMetadata::read(&upscale.image).ok().flatten())
+            }
+            _ => None,
+        };
+
+        // Wait for the provider's rate limits before sending the request.
+        let limiter = rate_limiter::limiter(&self.limiter_key(), self.default_rate_limits())?;
+        let _permit = cancel.run_until_cancelled(limiter.acquire(0)).await?;
+        let provider = self.provider();
+        let (images, post_upscale) = match operation {
+            ImageOperation::TextToImage => (
+                provider.text_to_image(request, progress, cancel).await?,
+                params.upscale.clone(),
+            ),
+            ImageOperation::ImageToImage(init_image) => (
+                provider
+                    .image_to_image(request, init_image, progress, cancel)
+                    .await?,
+                params.upscale.clone(),
+            ),
+            ImageOperation::Inpaint(inpaint) => (
+                provider.inpaint(request, inpaint, progress, cancel).await?,
+                params.upscale.clone(),
+            ),
+            ImageOperation::Upscale(upscale) => {
+                let image = ImageData::read(&upscale.image)?;
+                let upscaled = cancel
+                    .run_until_cancelled(provider.upscale(&image, &upscale.upscale))
+                    .await?;
+                let seed = source_metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.as_ref()?.seed);
+                (vec![upscaled.with_seed(seed)], None)
+            }
+        };
+        self.finish(
+            &params,
+            images,
+            (width, height),
+            post_upscale.as_ref(),
+            source_metadata.as_ref(),
+            cancel,
+        )
+        .await
+    }
+
+    /// Collect the images of Stable Diffusion tasks that an earlier run queued but never collected,
+    /// e.g. because it timed out or exited first. Tasks that are still running are waited for.
+    /// The images are resized, converted, and saved with their parameters and thumbnails, the same way as when
+    /// they are generated. Every task is returned with its images or the error it failed with,
+    /// unless `cancel` is triggered, which stops the whole run.
+    pub async fn resume(
+        &self,
+        output_directory: &Path,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ResumedTask>> {
+        let ImageProviders::StableDiffusion(provider) = self else {
+            return Err(Error::msg(
+                "Only tasks queued on Stable Diffusion's agent-scheduler can be resumed.",
+            ));
+        };
+        let mut resumed = Vec::new();
+        for task in provider.pending_tasks(output_directory)? {
+            match self.resume_task(provider, &task, progress, cancel).await {
+                Err(e) if cancel.is_cancelled() => return Err(e),
+                images => resumed.push(ResumedTask { task, images }),
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Collect and finish the images of one queued task.
+    async fn resume_task(
+        &self,
+        provider: &stable_diffusion::StableDiffusionXLProvider,
+        task: &PendingTask,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<GeneratedImage>> {
+        let images = provider.resume_task(task, progress, cancel).await?;
+        let params = &task.params;
+        let (width, height) = task.requested_size.unwrap_or((params.width, params.height));
+        // Only txt2img tasks use the hires fix.
+        let (width, height) = match task.request_type.as_str() {
+            "txt2img" => self.hires_size(params, width, height),
+            _ => (width, height),
+        };
+        self.finish(
+            params,
+            images,
+            (width, height),
+            params.upscale.as_ref(),
+            None,
+            cancel,
+        )
+        .await
+    }
+
+    /// Fit the generated images to the requested size and upscale them if requested,
+    /// then save them with their parameters and thumbnails.
+    /// `source_metadata` is the metadata of the image that was upscaled, if the operation was an upscale.
+    /// Once the images are saved, the queued tasks they were collected from are removed from the state file.
+    async fn finish(
+        &self,
+        params: &ImageParams,
+        images: Vec<ImageData>,
+        (width, height): (u32, u32),
+        post_upscale: Option<&Upscale>,
+        source_metadata: Option<&Option<ImageMetadata>>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<GeneratedImage>> {
+        let provider = self.provider();
+        let policy = params.resize_policy.unwrap_or_default();
+        let mut task_ids: Vec<String> = images
+            .iter()
+            .filter_map(|image| image.task_id.clone())
+            .collect();
+        task_ids.dedup();
+        let mut finished = Vec::new();
+        for mut image in images {
+            if let Some((original_width, original_height)) =
+                resize::fit_to_size(&mut image, width, height, policy)?
+            {
+                eprintln!(
+                    "Warning: An image was generated at {}x{} and resized to {}x{} using the {:?} policy.",
+                    original_width, original_height, width, height, policy
+                );
+            }
+            if let Some(upscale) = post_upscale {
+                let upscaled = cancel
+                    .run_until_cancelled(provider.upscale(&image, upscale))
+                    .await?;
+                image = ImageData {
+                    bytes: upscaled.bytes,
+                    format: upscaled.format,
+                    ..image
+                };
+            }
+            let metadata = match source_metadata {
+                Some(metadata) => metadata.clone(),
+                None => Some(self.metadata(params, &image)),
+            };
+            finished.push((image, metadata));
+        }
+        let saved = storage::save(finished, params)?;
+        if let ImageProviders::StableDiffusion(provider) = self {
+            for task_id in task_ids {
+                provider.forget_task(&params.output_directory, &task_id)?;
+            }
+        }
+        Ok(saved)
+    }
+
+    /// The provider to send requests to.
+    fn provider(&self) -> &(dyn ImageProvider + Sync) {
+        match self {
+            ImageProviders::OpenAi(provider) => provider,
+            ImageProviders::StableDiffusion(provider) => provider,
+            ImageProviders::ComfyUi(provider) => provider,
+            ImageProviders::StabilityAi(provider) => provider,
+        }
+    }
+
+    /// The generation parameters to save with an image. Only the settings the provider uses are included.
+    /// The size is filled in when the image is saved.
+    fn metadata(&self, params: &ImageParams, image: &ImageData) -> ImageMetadata {
+        let metadata = ImageMetadata {
+            prompt: params.prompt.to_string(),
+            seed: image.seed,
+            model: image.model.clone().or_else(|| params.model.clone()),
+            provider: Some(self.name().to_string()),
+            revised_prompt: image.revised_prompt.clone(),
+            ..Default::default()
+        };
+        match self {
+            ImageProviders::OpenAi(_) => metadata,
+            // The LoRAs and embeddings are saved in the prompt, the same way Automatic1111 saves them.
+            ImageProviders::StableDiffusion(_) => {
+                let prompt = params.with_extra_networks().prompt;
+                ImageMetadata {
+                    prompt: prompt.to_string(),
+                    negative_prompt: prompt.negative,
+                    steps: Some(params.steps),
+                    sampler_name: Some(params.sampler_name.clone()),
+                    scheduler: params.scheduler.clone(),
+                    cfg_scale: Some(params.cfg_scale),
+                    vae: params.vae.clone(),
+                    clip_skip: params.clip_skip,
+                    ..metadata
+                }
+            }
+            ImageProviders::ComfyUi(_) => ImageMetadata {
+                negative_prompt: params.prompt.negative.clone(),
+                steps: Some(params.steps),
+                ..metadata
+            },
+            ImageProviders::StabilityAi(_) => ImageMetadata {
+                negative_prompt: params.prompt.negative.clone(),
+                ..metadata
+            },
+        }
+    }
+
+    /// The name of the provider, as used in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageProviders::OpenAi(_) => "OpenAi",
+            ImageProviders::StableDiffusion(_) => "StableDiffusion",
+            ImageProviders::ComfyUi(_) => "ComfyUi",
+            ImageProviders::StabilityAi(_) => "StabilityAi",
+        }
+    }
+
+    /// The size of the image after the hires fix. Only Stable Diffusion supports it.
+    fn hires_size(&self, params: &ImageParams, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            ImageProviders::StableDiffusion(_) => params.hires_size(width, height),
+            _ => (width, height),
+        }
+    }
+
+    /// The size closest to the requested one that the provider can generate.
+    pub fn native_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            ImageProviders::OpenAi(provider) => provider.options.native_size(width, height),
+            // Stability AI only takes an aspect ratio and picks the resolution itself.
+            ImageProviders::StabilityAi(_) => (width, height),
+            // Stable Diffusion works in multiples of 8, so round up and crop the difference.
+            ImageProviders::StableDiffusion(_) | ImageProviders::ComfyUi(_) => {
+                (width.div_ceil(8) * 8, height.div_ceil(8) * 8)
+            }
+        }
+    }
+
+    /// The key used to share a rate limiter between all requests to the same provider.
+    pub fn limiter_key(&self) -> String {
         match self {
-            ImageProviders::OpenAi(provider) => provider.text_to_image(params).await,
+            ImageProviders::OpenAi(provider) => format!("images/OpenAi/{}", provider.get_url()),
             ImageProviders::StableDiffusion(provider) => {
-                let image = provider.queue_txt2img(&params).await?;
-                // Image filename is the current timestamp.
-                let image_filename: String = format!("{}.png", chrono::Utc::now().timestamp());
-                let image_path = params.output_directory.join(image_filename);
-                image.to_file(&image_path)?;
-                Ok(image_path)
+                format!("images/StableDiffusion/{}", provider.get_url())
             }
+            ImageProviders::ComfyUi(provider) => format!("images/ComfyUi/{}", provider.get_url()),
+            ImageProviders::StabilityAi(provider) => {
+                format!("images/StabilityAi/{}", provider.get_url())
+            }
+        }
+    }
+
+    /// The limits used if none are configured.
+    /// A local Stable Diffusion or ComfyUI instance can only render one image at a time, so it never gets more than one job.
+    fn default_rate_limits(&self) -> RateLimits {
+        match self {
+            ImageProviders::OpenAi(_) | ImageProviders::StabilityAi(_) => RateLimits::default(),
+            ImageProviders::StableDiffusion(_) | ImageProviders::ComfyUi(_) => RateLimits {
+                max_in_flight: Some(1),
+                ..Default::default()
+            },
         }
     }
 }
 
 impl Default for ImageProviders {
     fn default() -> Self {
-        ImageProviders::OpenAi(openai::OpenAiProvider)
+        ImageProviders::OpenAi(openai::OpenAiProvider::default())
+    }
+}
+
+/// Convert a number of seconds from the configuration, which must be more than 0.
+pub(crate) fn seconds(name: &str, seconds: f32) -> Result<Duration> {
+    match Duration::try_from_secs_f32(seconds) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        _ => Err(Error::msg(format!(
+            "`{}` must be a number of seconds greater than 0, but was {}.",
+            name, seconds
+        ))),
     }
 }
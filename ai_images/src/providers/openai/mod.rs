@@ -1,4 +1,9 @@
+mod options;
 mod provider;
 
-use super::{ImageParams, ImageProvider};
+use super::{ImageParams, ImageProvider, InpaintParams};
+pub use options::{
+    OpenAiImageBackground, OpenAiImageFormat, OpenAiImageModel, OpenAiImageOptions,
+    OpenAiImageQuality, OpenAiImageStyle,
+};
 pub use provider::OpenAiProvider;
@@ -0,0 +1,345 @@
+use crate::images::resize::closest_aspect_ratio;
+use anyhow::{Error, Result};
+use async_openai::types::{CreateImageRequestArgs, ImageModel, ImageResponseFormat};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The OpenAI image models.
+#[derive(ValueEnum, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum OpenAiImageModel {
+    #[serde(rename = "dall-e-2")]
+    #[value(name = "dall-e-2")]
+    DallE2,
+    #[default]
+    #[serde(rename = "dall-e-3")]
+    #[value(name = "dall-e-3")]
+    DallE3,
+    #[serde(rename = "gpt-image-1")]
+    #[value(name = "gpt-image-1")]
+    GptImage1,
+}
+
+/// The quality of the generated image.
+/// `standard` and `hd` are used by DALL-E 3. `low`, `medium`, `high`, and `auto` are used by GPT Image.
+#[derive(ValueEnum, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAiImageQuality {
+    Standard,
+    Hd,
+    Low,
+    Medium,
+    High,
+    Auto,
+}
+
+/// The style of the generated image. Only used by DALL-E 3.
+#[derive(ValueEnum, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAiImageStyle {
+    Vivid,
+    Natural,
+}
+
+/// The background of the generated image. Only used by GPT Image.
+#[derive(ValueEnum, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAiImageBackground {
+    Auto,
+    Transparent,
+    Opaque,
+}
+
+/// The file format of the generated image. Only used by GPT Image, since DALL-E always returns PNGs.
+#[derive(ValueEnum, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAiImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+/// Options for OpenAI's image generation API.
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct OpenAiImageOptions {
+    /// The OpenAI image model to use. Defaults to DALL-E 3.
+    #[clap(long = "openai-model", id = "openai_model")]
+    pub model: Option<OpenAiImageModel>,
+
+    /// The model name to send instead of `model`'s, e.g. a LocalAI model.
+    /// `model` still decides which options and sizes are used, so it should be the model the name stands for.
+    #[clap(long = "openai-model-name")]
+    pub model_name: Option<String>,
+
+    /// The quality of the generated image.
+    #[clap(long)]
+    pub quality: Option<OpenAiImageQuality>,
+
+    /// The style of the generated image. Only used by DALL-E 3.
+    #[clap(long)]
+    pub style: Option<OpenAiImageStyle>,
+
+    /// The background of the generated image. Only used by GPT Image.
+    #[clap(long)]
+    pub background: Option<OpenAiImageBackground>,
+
+    /// The file format of the generated image. Only used by GPT Image.
+    #[clap(long)]
+    pub output_format: Option<OpenAiImageFormat>,
+}
+
+impl OpenAiImageOptions {
+    /// The model to use, falling back to DALL-E 3.
+    pub fn model(&self) -> OpenAiImageModel {
+        self.model.clone().unwrap_or_default()
+    }
+
+    /// The model name sent in requests: `model_name` if it is set, and the name of `model` otherwise.
+    pub fn model_name(&self) -> String {
+        self.model_name.clone().unwrap_or_else(|| {
+            serde_json::to_value(self.model())
+                .ok()
+                .and_then(|model| model.as_str().map(str::to_string))
+                .unwrap_or_default()
+        })
+    }
+
+    /// The file format the image will be returned in.
+    pub fn output_format(&self) -> OpenAiImageFormat {
+        self.output_format.clone().unwrap_or_default()
+    }
+
+    /// The supported size closest to the requested one.
+    /// DALL-E 2 only generates squares, so the smallest one that covers the requested size is used.
+    pub fn native_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.model() {
+            OpenAiImageModel::DallE2 => {
+                let side = [256, 512, 1024]
+                    .into_iter()
+                    .find(|side| *side >= width.max(height))
+                    .unwrap_or(1024);
+                (side, side)
+            }
+            OpenAiImageModel::DallE3 => {
+                closest_aspect_ratio(width, height, &[(1024, 1024), (1792, 1024), (1024, 1792)])
+            }
+            OpenAiImageModel::GptImage1 => {
+                closest_aspect_ratio(width, height, &[(1024, 1024), (1536, 1024), (1024, 1536)])
+            }
+        }
+    }
+
+    /// Check that the options are supported by the selected model.
+    pub fn validate(&self) -> Result<()> {
+        let model = self.model();
+        let unsupported = |option: &str| {
+            Err(Error::msg(format!(
+                "The OpenAI image model {:?} does not support the {} option.",
+                model, option
+            )))
+        };
+
+        if let Some(quality) = &self.quality {
+            let supported = match model {
+                OpenAiImageModel::DallE2 => *quality == OpenAiImageQuality::Standard,
+                OpenAiImageModel::DallE3 => {
+                    matches!(
+                        quality,
+                        OpenAiImageQuality::Standard | OpenAiImageQuality::Hd
+                    )
+                }
+                OpenAiImageModel::GptImage1 => !matches!(
+                    quality,
+                    OpenAiImageQuality::Standard | OpenAiImageQuality::Hd
+                ),
+            };
+            if !supported {
+                return unsupported(&format!("{:?} quality", quality));
+            }
+        }
+        if self.style.is_some() && model != OpenAiImageModel::DallE3 {
+            return unsupported("style");
+        }
+        if model != OpenAiImageModel::GptImage1 {
+            if self.background.is_some() {
+                return unsupported("background");
+            }
+            if self.output_format.is_some() {
+                return unsupported("output_format");
+            }
+        }
+        if self.background == Some(OpenAiImageBackground::Transparent)
+            && self.output_format() == OpenAiImageFormat::Jpeg
+        {
+            return Err(Error::msg(
+                "Transparent backgrounds require the png or webp output format.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Build the body of an image generation request.
+    /// async-openai doesn't know about the GPT Image options yet, so they're added to the serialized request.
+    pub fn request_body(&self, prompt: &str, width: u32, height: u32) -> Result<Value> {
+        self.validate()?;
+        let model = self.model();
+        let (width, height) = self.native_size(width, height);
+
+        let mut request = CreateImageRequestArgs::default();
+        request.prompt(prompt).model(match model {
+            OpenAiImageModel::DallE2 => ImageModel::DallE2,
+            OpenAiImageModel::DallE3 => ImageModel::DallE3,
+            OpenAiImageModel::GptImage1 => ImageModel::Other("gpt-image-1".to_string()),
+        });
+        // GPT Image always returns base64-encoded images and rejects the response_format option.
+        if model != OpenAiImageModel::GptImage1 {
+            request.response_format(ImageResponseFormat::B64Json);
+        }
+        if let Some(style) = &self.style {
+            request.style(match style {
+                OpenAiImageStyle::Vivid => async_openai::types::ImageStyle::Vivid,
+                OpenAiImageStyle::Natural => async_openai::types::ImageStyle::Natural,
+            });
+        }
+        let mut body = serde_json::to_value(request.build()?)?;
+
+        if let Some(model_name) = &self.model_name {
+            body["model"] = serde_json::to_value(model_name)?;
+        }
+        // async-openai doesn't have variants for GPT Image's sizes, so the size is always set here.
+        body["size"] = serde_json::to_value(format!("{}x{}", width, height))?;
+        if let Some(quality) = &self.quality {
+            body["quality"] = serde_json::to_value(quality)?;
+        }
+        if let Some(background) = &self.background {
+            body["background"] = serde_json::to_value(background)?;
+        }
+        if let Some(output_format) = &self.output_format {
+            body["output_format"] = serde_json::to_value(output_format)?;
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_request_body() -> Result<()> {
+        let body = OpenAiImageOptions::default().request_body("A castle", 1920, 1080)?;
+        assert_eq!(body["model"], "dall-e-3");
+        assert_eq!(body["size"], "1792x1024");
+        assert_eq!(body["response_format"], "b64_json");
+        assert!(body.get("quality").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpt_image_request_body() -> Result<()> {
+        let options = OpenAiImageOptions {
+            model: Some(OpenAiImageModel::GptImage1),
+            quality: Some(OpenAiImageQuality::High),
+            background: Some(OpenAiImageBackground::Transparent),
+            output_format: Some(OpenAiImageFormat::Webp),
+            ..Default::default()
+        };
+        let body = options.request_body("A healing potion icon", 1024, 1536)?;
+        assert_eq!(body["model"], "gpt-image-1");
+        assert_eq!(body["size"], "1024x1536");
+        assert_eq!(body["quality"], "high");
+        assert_eq!(body["background"], "transparent");
+        assert_eq!(body["output_format"], "webp");
+        assert!(body.get("response_format").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_name_override() -> Result<()> {
+        let options = OpenAiImageOptions {
+            model: Some(OpenAiImageModel::DallE3),
+            model_name: Some("dalle3-deployment".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.model_name(), "dalle3-deployment");
+        let body = options.request_body("A castle", 1920, 1080)?;
+        assert_eq!(body["model"], "dalle3-deployment");
+        // The sizes of the model it stands for are still used.
+        assert_eq!(body["size"], "1792x1024");
+        assert_eq!(OpenAiImageOptions::default().model_name(), "dall-e-3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_native_size() {
+        let options = |model| OpenAiImageOptions {
+            model: Some(model),
+            ..Default::default()
+        };
+        assert_eq!(
+            options(OpenAiImageModel::DallE2).native_size(512, 768),
+            (1024, 1024)
+        );
+        assert_eq!(
+            options(OpenAiImageModel::DallE2).native_size(300, 200),
+            (512, 512)
+        );
+        assert_eq!(
+            options(OpenAiImageModel::DallE3).native_size(512, 768),
+            (1024, 1792)
+        );
+        assert_eq!(
+            options(OpenAiImageModel::GptImage1).native_size(512, 768),
+            (1024, 1536)
+        );
+    }
+
+    #[test]
+    fn test_validate_per_model() {
+        let options = |model, quality, style| OpenAiImageOptions {
+            model: Some(model),
+            quality,
+            style,
+            ..Default::default()
+        };
+        assert!(options(
+            OpenAiImageModel::DallE3,
+            Some(OpenAiImageQuality::Hd),
+            Some(OpenAiImageStyle::Natural)
+        )
+        .validate()
+        .is_ok());
+        assert!(
+            options(OpenAiImageModel::DallE2, Some(OpenAiImageQuality::Hd), None)
+                .validate()
+                .is_err()
+        );
+        assert!(options(
+            OpenAiImageModel::GptImage1,
+            Some(OpenAiImageQuality::Hd),
+            None
+        )
+        .validate()
+        .is_err());
+        assert!(options(
+            OpenAiImageModel::GptImage1,
+            None,
+            Some(OpenAiImageStyle::Vivid)
+        )
+        .validate()
+        .is_err());
+        let background = OpenAiImageOptions {
+            background: Some(OpenAiImageBackground::Transparent),
+            ..Default::default()
+        };
+        assert!(background.validate().is_err());
+        let jpeg = OpenAiImageOptions {
+            model: Some(OpenAiImageModel::GptImage1),
+            background: Some(OpenAiImageBackground::Transparent),
+            output_format: Some(OpenAiImageFormat::Jpeg),
+            ..Default::default()
+        };
+        assert!(jpeg.validate().is_err());
+    }
+}
@@ -1,82 +1,299 @@
-use super::{ImageParams, ImageProvider};
+use super::{ImageParams, ImageProvider, InpaintParams, OpenAiImageModel, OpenAiImageOptions};
+use crate::cancel::CancellationToken;
+use crate::images::{string::image_to_png, Base64Image, ImageData};
+use crate::progress::Progress;
 use anyhow::{Error, Result};
+use api_keys::{ApiKey, ApiKeySource};
 use async_openai::{
+    config::{AzureConfig, OpenAIConfig},
     error::OpenAIError,
-    types::{CreateImageRequestArgs, ImageResponseFormat, ImageSize},
+    types::{
+        CreateImageEditRequest, CreateImageEditRequestArgs, DallE2ImageSize, Image, ImageInput,
+        ImageModel, ImageResponseFormat, ImagesResponse, InputSource,
+    },
     Client,
 };
 use async_trait::async_trait;
 use clap::Args;
-use dotenvy::dotenv;
+use image::{imageops::FilterType, DynamicImage};
+use reqwest::header::HeaderValue;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde_json::Value;
 
-/// An image provider that generates images using OpenAI's DALL-E 3 API. Requires that you set the `OPENAI_API_KEY` environment variable, or have it in a `.env` file.
-#[derive(Args, Deserialize, Debug, Serialize)]
-pub struct OpenAiProvider;
+/// The URL of OpenAI's API.
+const DEFAULT_URL: &str = "https://api.openai.com/v1";
 
-impl Default for OpenAiProvider {
-    fn default() -> Self {
-        OpenAiProvider
-    }
+/// The Azure OpenAI API version used if none is configured.
+const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+
+/// An image provider that generates images using OpenAI's image API. Defaults to DALL-E 3.
+/// By default, the API key is read from the `OPENAI_API_KEY` environment variable or a `.env` file.
+/// Any other API that serves `/images/generations` the same way, e.g. LocalAI or a proxy, can be used by setting
+/// `base_url`. An Azure OpenAI image deployment can be used by also setting `deployment`.
+#[derive(Args, Deserialize, Debug, Default, Serialize)]
+pub struct OpenAiProvider {
+    /// Where to load the API key from.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub api_key: ApiKeySource,
+
+    /// The URL the API's paths are appended to, e.g. `http://localhost:8080/v1`. Defaults to `https://api.openai.com/v1`.
+    /// When it is set, the API key is optional, since local servers often don't need one.
+    #[clap(long = "openai-base-url")]
+    pub base_url: Option<String>,
+
+    /// The organization to send requests as, in the `OpenAI-Organization` header.
+    #[clap(long = "openai-organization")]
+    pub organization: Option<String>,
+
+    /// The project to send requests as, in the `OpenAI-Project` header.
+    #[clap(long = "openai-project")]
+    pub project: Option<String>,
+
+    /// The Azure OpenAI deployment to generate with. When it is set, `base_url` is the resource's endpoint,
+    /// e.g. `https://my-resource.openai.azure.com`, and the API key is read from `AZURE_OPENAI_API_KEY` by default.
+    #[clap(long = "openai-deployment")]
+    pub deployment: Option<String>,
+
+    /// The Azure OpenAI API version. Defaults to `2024-10-21`. Only used with `deployment`.
+    #[clap(long = "openai-api-version")]
+    pub api_version: Option<String>,
+
+    /// The model and the options to generate the image with.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub options: OpenAiImageOptions,
 }
 
 #[async_trait]
 impl ImageProvider for OpenAiProvider {
-    async fn text_to_image(&self, params: ImageParams) -> Result<PathBuf> {
-        // Load environment variables from a .env file.
-        dotenv()?;
+    async fn text_to_image(
+        &self,
+        params: ImageParams,
+        _progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        // Create the request. This fails early if the options aren't supported by the model.
+        let request =
+            self.options
+                .request_body(&params.prompt.to_string(), params.width, params.height)?;
 
-        // Create a new OpenAI client.
-        let client = Client::new();
+        // Send the request to OpenAI's API, keeping the API key out of any error messages.
+        let (client, api_key) = self.client()?;
+        // OpenAI has no way to stop a request once it is sent, so cancelling only stops waiting for it.
+        let response = cancel
+            .run_until_cancelled(async {
+                client
+                    .generate(request)
+                    .await
+                    .map_err(|e| api_key.redact_error(e))
+            })
+            .await?;
+        self.images_from_response(&response)
+    }
 
-        // Check that the OpenAI API key is set.
-        if std::env::var("OPENAI_API_KEY").is_err() {
-            return Err(Error::msg("OpenAI API key not set"));
+    /// Repaint the masked part of an image using the images edit endpoint.
+    /// Only DALL-E 2 and GPT Image support edits. DALL-E 2 edits are square, so the image is cropped to fit.
+    async fn inpaint(
+        &self,
+        params: ImageParams,
+        inpaint: InpaintParams,
+        _progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let model = self.options.model();
+        let mut image = image::open(&inpaint.image)?;
+        let mut mask = DynamicImage::ImageLuma8(inpaint.mask_image(image.width(), image.height())?);
+        let mut request = CreateImageEditRequestArgs::default();
+        match model {
+            OpenAiImageModel::DallE2 => {
+                let (side, _) = self.options.native_size(image.width(), image.height());
+                image = image.resize_to_fill(side, side, FilterType::Lanczos3);
+                mask = mask.resize_to_fill(side, side, FilterType::Triangle);
+                request
+                    .model(ImageModel::DallE2)
+                    .response_format(ImageResponseFormat::B64Json)
+                    .size(match side {
+                        256 => DallE2ImageSize::S256x256,
+                        512 => DallE2ImageSize::S512x512,
+                        _ => DallE2ImageSize::S1024x1024,
+                    });
+            }
+            OpenAiImageModel::DallE3 => {
+                return Err(Error::msg(
+                    "DALL-E 3 does not support inpainting. Use dall-e-2 or gpt-image-1 instead.",
+                ))
+            }
+            OpenAiImageModel::GptImage1 => {
+                request.model(ImageModel::Other("gpt-image-1".to_string()));
+            }
         }
 
-        // Standardize the image size.
-        let size = to_openai_size(&params.width, &params.height);
+        // OpenAI repaints the fully transparent areas of the mask, so the white areas are made transparent.
+        let mask = mask.to_luma8();
+        let mut openai_mask = image.to_rgba8();
+        for (x, y, pixel) in openai_mask.enumerate_pixels_mut() {
+            if mask.get_pixel(x, y)[0] >= 128 {
+                pixel[3] = 0;
+            }
+        }
 
-        // Create the request.
-        let request = CreateImageRequestArgs::default()
-            .model(async_openai::types::ImageModel::DallE3)
+        if let Some(model_name) = &self.options.model_name {
+            request.model(ImageModel::Other(model_name.clone()));
+        }
+        let request = request
             .prompt(params.prompt.to_string())
-            .response_format(ImageResponseFormat::B64Json)
-            .size(size)
+            .image(png_input(
+                "image.png",
+                &DynamicImage::ImageRgba8(image.to_rgba8()),
+            )?)
+            .mask(png_input(
+                "mask.png",
+                &DynamicImage::ImageRgba8(openai_mask),
+            )?)
             .build()?;
 
-        // Send the request to OpenAI's API.
-        let response = client.images().create(request).await?;
+        // Send the request to OpenAI's API, keeping the API key out of any error messages.
+        let (client, api_key) = self.client()?;
+        let response = cancel
+            .run_until_cancelled(async {
+                client
+                    .edit(request)
+                    .await
+                    .map_err(|e| api_key.redact_error(e))
+            })
+            .await?;
+        self.images_from_response(&response)
+    }
+}
 
-        // Download and save the image to the current directory.
-        let image: Vec<PathBuf> = response.save(&params.output_directory).await?;
+/// A client for OpenAI's API, or for an Azure OpenAI deployment, which is addressed and authenticated differently.
+enum OpenAiClient {
+    OpenAi(Client<OpenAIConfig>),
+    Azure(Client<AzureConfig>),
+}
 
-        // Rename the image file to the current timestamp.
-        let timestamp = chrono::Utc::now().timestamp().to_string();
-        let new_image = params.output_directory.join(format!("{}.png", timestamp));
-        std::fs::rename(&image[0], &new_image)?;
+impl OpenAiClient {
+    /// Send a request to the image generation endpoint.
+    async fn generate(&self, request: Value) -> Result<ImagesResponse, OpenAIError> {
+        match self {
+            OpenAiClient::OpenAi(client) => client.images().create_byot(request).await,
+            OpenAiClient::Azure(client) => client.images().create_byot(request).await,
+        }
+    }
 
-        // Return the path to the image.
-        let image: Option<PathBuf> = Some(new_image);
-        match image {
-            Some(image) => Ok(image),
-            None => Err(anyhow::Error::new(OpenAIError::StreamError(
-                "Response did not return any images".to_string(),
-            ))),
+    /// Send a request to the image edit endpoint.
+    async fn edit(&self, request: CreateImageEditRequest) -> Result<ImagesResponse, OpenAIError> {
+        match self {
+            OpenAiClient::OpenAi(client) => client.images().create_edit(request).await,
+            OpenAiClient::Azure(client) => client.images().create_edit(request).await,
         }
     }
 }
 
-fn to_openai_size(width: &u32, height: &u32) -> ImageSize {
-    match (width, height) {
-        (1024, 1024) => ImageSize::S1024x1024,
-        (1024, 1792) => ImageSize::S1024x1792,
-        (1792, 1024) => ImageSize::S1792x1024,
-        (256, 256) => ImageSize::S256x256,
-        (512, 512) => ImageSize::S512x512,
-        _ => ImageSize::S1024x1024,
+impl OpenAiProvider {
+    /// Get the sanitized URL requests are sent to, including the deployment for Azure OpenAI.
+    /// The URL should not have a trailing slash.
+    pub fn get_url(&self) -> String {
+        let base_url = self
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_URL)
+            .trim_end_matches('/');
+        match &self.deployment {
+            Some(deployment) => format!("{}/openai/deployments/{}", base_url, deployment),
+            None => base_url.to_string(),
+        }
+    }
+
+    /// Create a new client, returning the API key so it can be redacted from errors.
+    fn client(&self) -> Result<(OpenAiClient, ApiKey)> {
+        if let Some(deployment) = &self.deployment {
+            let base_url = self.base_url.as_deref().ok_or(Error::msg(
+                "An Azure OpenAI `deployment` requires the resource's endpoint as the `url`.",
+            ))?;
+            let api_key = self.api_key.load("AZURE_OPENAI_API_KEY")?;
+            let config = AzureConfig::new()
+                .with_api_base(base_url.trim_end_matches('/'))
+                .with_deployment_id(deployment)
+                .with_api_version(
+                    self.api_version
+                        .as_deref()
+                        .unwrap_or(DEFAULT_AZURE_API_VERSION),
+                )
+                .with_api_key(api_key.expose());
+            return Ok((OpenAiClient::Azure(Client::with_config(config)), api_key));
+        }
+
+        let api_key = match &self.base_url {
+            Some(_) => self
+                .api_key
+                .load_optional("OPENAI_API_KEY")?
+                .unwrap_or(ApiKey::new("")),
+            None => self.api_key.load("OPENAI_API_KEY")?,
+        };
+        let mut config = OpenAIConfig::new()
+            .with_api_key(api_key.expose())
+            .with_api_base(self.get_url());
+        // The client panics on header values it can't send, so they are checked first.
+        for (name, value) in [
+            ("organization", &self.organization),
+            ("project", &self.project),
+        ] {
+            if let Some(value) = value
+                && HeaderValue::from_str(value).is_err()
+            {
+                return Err(Error::msg(format!(
+                    "The OpenAI {} {:?} can't be sent in a header.",
+                    name, value
+                )));
+            }
+        }
+        if let Some(organization) = &self.organization {
+            config = config.with_org_id(organization);
+        }
+        if let Some(project) = &self.project {
+            config = config.with_project_id(project);
+        }
+        Ok((OpenAiClient::OpenAi(Client::with_config(config)), api_key))
     }
+
+    /// Decode every image in the response, with the model that generated it.
+    fn images_from_response(&self, response: &ImagesResponse) -> Result<Vec<ImageData>> {
+        let model = Some(self.options.model_name());
+        response
+            .data
+            .iter()
+            .map(|image| match image.as_ref() {
+                // DALL-E 3 rewrites the prompt, so keep the version it actually used.
+                Image::B64Json {
+                    b64_json,
+                    revised_prompt,
+                } => Ok(ImageData {
+                    revised_prompt: revised_prompt.clone(),
+                    model: model.clone(),
+                    ..Base64Image {
+                        image: b64_json.to_string(),
+                        seed: None,
+                    }
+                    .decode()?
+                }),
+                Image::Url { .. } => Err(Error::msg(
+                    "OpenAI returned an image URL instead of a base64-encoded image.",
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Encode an image as a PNG file upload.
+fn png_input(filename: &str, image: &DynamicImage) -> Result<ImageInput> {
+    Ok(ImageInput {
+        source: InputSource::VecU8 {
+            filename: filename.to_string(),
+            vec: image_to_png(image)?,
+        },
+    })
 }
 
 #[cfg(test)]
@@ -86,11 +303,12 @@ mod tests {
     #[tokio::test]
     async fn test_generate_request() -> Result<()> {
         let params = ImageParams::default();
-        let provider = OpenAiProvider;
-        let image = provider.text_to_image(params).await?;
-        assert!(image.exists());
-        // Clean up the image file and any directories created.
-        std::fs::remove_file(image)?;
+        let provider = OpenAiProvider::default();
+        let images = provider
+            .text_to_image(params, &Progress::default(), &CancellationToken::default())
+            .await?;
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].model.as_deref(), Some("dall-e-3"));
         Ok(())
     }
 }
@@ -0,0 +1,8 @@
+//! Generate images with Stability AI's hosted Stable Image API.
+
+mod options;
+mod provider;
+
+use super::{ImageData, ImageParams, ImageProvider};
+pub use options::{StabilityAiEndpoint, StabilityAiFormat, StabilityAiOptions};
+pub use provider::StabilityAiProvider;
@@ -0,0 +1,132 @@
+use crate::images::resize::closest_aspect_ratio;
+use anyhow::{Error, Result};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// The aspect ratios the generate endpoints accept.
+const ASPECT_RATIOS: [(u32, u32); 9] = [
+    (1, 1),
+    (16, 9),
+    (9, 16),
+    (21, 9),
+    (9, 21),
+    (3, 2),
+    (2, 3),
+    (5, 4),
+    (4, 5),
+];
+
+/// The Stable Image generate endpoints of the Stability AI API.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StabilityAiEndpoint {
+    /// Stable Image Core, the fastest and cheapest.
+    #[default]
+    Core,
+    /// Stable Image Ultra, the highest quality.
+    Ultra,
+    /// Stable Diffusion 3.5. The model can be picked with the `model` parameter, e.g. `sd3.5-large-turbo`.
+    Sd3,
+}
+
+/// The file format of the generated image.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StabilityAiFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+/// Options for Stability AI's image generation API.
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct StabilityAiOptions {
+    /// The endpoint to generate the image with. Defaults to `core`.
+    #[clap(long = "stability-endpoint")]
+    pub endpoint: Option<StabilityAiEndpoint>,
+
+    /// The file format of the generated image. Defaults to `png`. `webp` isn't supported by `sd3`.
+    #[clap(long = "stability-output-format", id = "stability_output_format")]
+    #[serde(rename = "stability_output_format")]
+    pub output_format: Option<StabilityAiFormat>,
+}
+
+impl StabilityAiOptions {
+    /// The endpoint to use, falling back to Stable Image Core.
+    pub fn endpoint(&self) -> StabilityAiEndpoint {
+        self.endpoint.unwrap_or_default()
+    }
+
+    /// The file format the image will be returned in.
+    pub fn output_format(&self) -> StabilityAiFormat {
+        self.output_format.unwrap_or_default()
+    }
+
+    /// Check that the options are supported by the selected endpoint.
+    pub fn validate(&self) -> Result<()> {
+        if self.endpoint() == StabilityAiEndpoint::Sd3
+            && self.output_format() == StabilityAiFormat::Webp
+        {
+            return Err(Error::msg(
+                "The Stability AI sd3 endpoint does not support the webp output format.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The path of the endpoint, relative to the API's URL.
+    pub fn path(&self) -> String {
+        let endpoint = serde_json::to_value(self.endpoint())
+            .ok()
+            .and_then(|endpoint| endpoint.as_str().map(str::to_string))
+            .unwrap_or_default();
+        format!("/v2beta/stable-image/generate/{}", endpoint)
+    }
+
+    /// The name of the model the endpoint generates with, to save with the image.
+    /// The sd3 endpoint generates with `model` if it is set, and with SD 3.5 Large otherwise.
+    pub fn model_name(&self, model: Option<&str>) -> String {
+        match self.endpoint() {
+            StabilityAiEndpoint::Core => "stable-image-core".to_string(),
+            StabilityAiEndpoint::Ultra => "stable-image-ultra".to_string(),
+            StabilityAiEndpoint::Sd3 => model.unwrap_or("sd3.5-large").to_string(),
+        }
+    }
+}
+
+/// The supported aspect ratio closest to the requested size, e.g. `16:9`.
+/// The endpoints pick the image's resolution themselves, so the image is resized to the requested size afterwards.
+pub fn aspect_ratio(width: u32, height: u32) -> String {
+    let (width, height) = closest_aspect_ratio(width, height, &ASPECT_RATIOS);
+    format!("{}:{}", width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aspect_ratio() {
+        assert_eq!(aspect_ratio(1024, 1024), "1:1");
+        assert_eq!(aspect_ratio(1920, 1080), "16:9");
+        assert_eq!(aspect_ratio(512, 768), "2:3");
+        assert_eq!(aspect_ratio(800, 1000), "4:5");
+        assert_eq!(aspect_ratio(2560, 1080), "21:9");
+    }
+
+    #[test]
+    fn test_validate_and_path() {
+        let sd3 = |output_format| StabilityAiOptions {
+            endpoint: Some(StabilityAiEndpoint::Sd3),
+            output_format,
+        };
+        assert!(sd3(Some(StabilityAiFormat::Jpeg)).validate().is_ok());
+        assert!(sd3(Some(StabilityAiFormat::Webp)).validate().is_err());
+        assert_eq!(sd3(None).path(), "/v2beta/stable-image/generate/sd3");
+        assert_eq!(
+            StabilityAiOptions::default().path(),
+            "/v2beta/stable-image/generate/core"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use super::options::aspect_ratio;
+use super::{ImageData, ImageParams, ImageProvider, StabilityAiEndpoint, StabilityAiOptions};
+use crate::cancel::CancellationToken;
+use crate::images::Base64Image;
+use crate::progress::Progress;
+use anyhow::{Error, Result};
+use api_keys::{ApiKey, ApiKeySource};
+use async_trait::async_trait;
+use clap::Args;
+use reqwest::multipart::Form;
+use serde::{Deserialize, Serialize};
+
+/// The URL of Stability AI's API.
+const DEFAULT_URL: &str = "https://api.stability.ai";
+
+fn default_url() -> String {
+    DEFAULT_URL.to_string()
+}
+
+/// An image provider that generates images using Stability AI's hosted Stable Image API. Defaults to Stable Image Core.
+/// By default, the API key is read from the `STABILITY_API_KEY` environment variable or a `.env` file.
+#[derive(Args, Deserialize, Debug, Serialize)]
+pub struct StabilityAiProvider {
+    /// The URL of the API. Only needs to be changed to use a proxy or a stand-in server.
+    #[clap(long = "stability-url", default_value = DEFAULT_URL)]
+    #[serde(default = "default_url")]
+    pub url: String,
+
+    /// Where to load the API key from.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub api_key: ApiKeySource,
+
+    /// The endpoint and the options to generate the image with.
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub options: StabilityAiOptions,
+}
+
+impl Default for StabilityAiProvider {
+    fn default() -> Self {
+        Self {
+            url: default_url(),
+            api_key: ApiKeySource::default(),
+            options: StabilityAiOptions::default(),
+        }
+    }
+}
+
+/// The response from a generate endpoint, when asked for JSON.
+#[derive(Debug, Deserialize)]
+struct GenerateResponse {
+    /// The base64-encoded image.
+    image: String,
+    /// `SUCCESS`, or `CONTENT_FILTERED` if the image was blurred by the content filter.
+    finish_reason: String,
+    seed: Option<i64>,
+}
+
+/// The body of a rejected request.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    name: Option<String>,
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+#[async_trait]
+impl ImageProvider for StabilityAiProvider {
+    async fn text_to_image(
+        &self,
+        params: ImageParams,
+        _progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        self.options.validate()?;
+        let api_key = self.api_key.load("STABILITY_API_KEY")?;
+        // Stability AI has no way to stop a request once it is sent, so cancelling only stops waiting for it.
+        let response = cancel
+            .run_until_cancelled(async {
+                self.generate(&params, &api_key)
+                    .await
+                    .map_err(|e| api_key.redact_error(e))
+            })
+            .await?;
+        if response.finish_reason == "CONTENT_FILTERED" {
+            return Err(Error::msg(
+                "Stability AI's content filter blurred the generated image. Try rewording the prompt.",
+            ));
+        }
+        let image = Base64Image {
+            image: response.image,
+            seed: response.seed,
+        }
+        .decode()?;
+        Ok(vec![ImageData {
+            model: Some(self.options.model_name(params.model.as_deref())),
+            ..image
+        }])
+    }
+}
+
+impl StabilityAiProvider {
+    /// Get the sanitized URL for the API.
+    /// The URL should not have a trailing slash.
+    pub fn get_url(&self) -> String {
+        self.url.trim_end_matches('/').to_string()
+    }
+
+    /// Send the prompt to the generate endpoint as a form, asking for the image as base64-encoded JSON
+    /// so the seed comes back with it.
+    async fn generate(&self, params: &ImageParams, api_key: &ApiKey) -> Result<GenerateResponse> {
+        let url = format!("{}{}", self.get_url(), self.options.path());
+        let response = reqwest::Client::new()
+            .post(url)
+            .bearer_auth(api_key.expose())
+            .header(reqwest::header::ACCEPT, "application/json")
+            .multipart(self.request_form(params)?)
+            .send()
+            .await
+            .map_err(|e| {
+                Error::msg(format!(
+                    "Could not reach Stability AI at {}: {}",
+                    self.get_url(),
+                    e
+                ))
+            })?;
+        let status = response.status();
+        let response_text = response.text().await?;
+        if !status.is_success() {
+            let reason = match serde_json::from_str::<ErrorResponse>(&response_text) {
+                Ok(error) if !error.errors.is_empty() => format!(
+                    "{}: {}",
+                    error.name.unwrap_or_default(),
+                    error.errors.join(" ")
+                ),
+                _ => response_text,
+            };
+            return Err(Error::msg(format!(
+                "Stability AI rejected the request with status {}. {}",
+                status, reason
+            )));
+        }
+        serde_json::from_str(&response_text).map_err(|e| {
+            Error::msg(format!(
+                "Unable to read Stability AI's response: {}. Response: {:?}",
+                e, response_text
+            ))
+        })
+    }
+
+    /// Build the form of a generate request.
+    /// A seed of 0 is random to Stability AI, so it is only sent when one was requested.
+    fn request_form(&self, params: &ImageParams) -> Result<Form> {
+        let output_format = serde_json::to_value(self.options.output_format())?;
+        let mut form = Form::new()
+            .text("prompt", params.prompt.to_string())
+            .text("aspect_ratio", aspect_ratio(params.width, params.height))
+            .text(
+                "output_format",
+                output_format.as_str().unwrap_or("png").to_string(),
+            );
+        if let Some(negative) = &params.prompt.negative {
+            form = form.text("negative_prompt", negative.clone());
+        }
+        if let Some(seed) = params.seed.filter(|seed| *seed >= 0) {
+            form = form.text("seed", seed.to_string());
+        }
+        if let (StabilityAiEndpoint::Sd3, Some(model)) = (self.options.endpoint(), &params.model) {
+            form = form.text("model", model.clone());
+        }
+        Ok(form)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_responses() -> Result<()> {
+        let response: GenerateResponse = serde_json::from_str(
+            r#"{"image": "iVBORw0KGgo=", "finish_reason": "SUCCESS", "seed": 343940597}"#,
+        )?;
+        assert_eq!(response.finish_reason, "SUCCESS");
+        assert_eq!(response.seed, Some(343940597));
+        let error: ErrorResponse = serde_json::from_str(
+            r#"{"id": "a1b2", "name": "bad_request", "errors": ["prompt: cannot be empty"]}"#,
+        )?;
+        assert_eq!(error.name.as_deref(), Some("bad_request"));
+        assert_eq!(error.errors, vec!["prompt: cannot be empty"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Guide generations with the ControlNet extension.
+
+use super::{ImageParams, StableDiffusionXLProvider};
+use crate::images::string::file_to_base64;
+use crate::params::ControlNetUnit;
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Extension scripts to run with a request, keyed by the script's name. Only ControlNet is supported.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AlwaysOnScripts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controlnet: Option<ScriptArgs<ControlNetArgs>>,
+}
+
+/// The arguments passed to an extension script.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScriptArgs<T> {
+    pub args: Vec<T>,
+}
+
+/// A ControlNet unit as the extension's API expects it.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ControlNetArgs {
+    pub enabled: bool,
+    /// The base64-encoded control image.
+    pub image: String,
+    pub module: String,
+    pub model: String,
+    pub weight: f32,
+    pub guidance_start: f32,
+    pub guidance_end: f32,
+    /// Whether to return the preprocessor's detected map after the generated images. Always false, since the map
+    /// isn't one of the requested images.
+    pub save_detected_map: bool,
+}
+
+impl ControlNetArgs {
+    /// Read the unit's image and fill in its settings.
+    pub fn from_unit(unit: &ControlNetUnit) -> Result<Self> {
+        Ok(Self {
+            enabled: true,
+            image: file_to_base64(&unit.image)?,
+            module: unit.module.clone(),
+            model: unit.model.clone(),
+            weight: unit.weight,
+            guidance_start: unit.guidance_start,
+            guidance_end: unit.guidance_end,
+            save_detected_map: false,
+        })
+    }
+}
+
+impl AlwaysOnScripts {
+    /// The scripts requested by the parameters, or None if there are none.
+    pub fn from_params(params: &ImageParams) -> Result<Option<Self>> {
+        if params.controlnet.is_empty() {
+            return Ok(None);
+        }
+        let args = params
+            .controlnet
+            .iter()
+            .map(ControlNetArgs::from_unit)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Self {
+            controlnet: Some(ScriptArgs { args }),
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModuleList {
+    module_list: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    model_list: Vec<String>,
+}
+
+impl StableDiffusionXLProvider {
+    /// Get a list of the ControlNet preprocessors, e.g. `openpose` or `canny`.
+    pub async fn get_controlnet_modules(&self) -> Result<Vec<String>> {
+        let endpoint = "/controlnet/module_list";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::get(&url).await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to list ControlNet modules. Is the ControlNet extension installed? Request URL: {:?}, Status: {}",
+                url,
+                response.status()
+            )));
+        }
+        let modules: ModuleList = serde_json::from_str(&response.text().await?)?;
+        Ok(modules.module_list)
+    }
+
+    /// Get a list of the installed ControlNet models. Each name ends with the model's hash in brackets.
+    pub async fn get_controlnet_models(&self) -> Result<Vec<String>> {
+        let endpoint = "/controlnet/model_list";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::get(&url).await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to list ControlNet models. Is the ControlNet extension installed? Request URL: {:?}, Status: {}",
+                url,
+                response.status()
+            )));
+        }
+        let models: ModelList = serde_json::from_str(&response.text().await?)?;
+        Ok(models.model_list)
+    }
+
+    /// Check that every requested ControlNet module and model is installed, since the extension skips units it
+    /// can't load instead of failing the request. Models can be named with or without their hash.
+    pub async fn validate_controlnet(&self, params: &ImageParams) -> Result<()> {
+        if params.controlnet.is_empty() {
+            return Ok(());
+        }
+        let modules = self.get_controlnet_modules().await?;
+        let models = self.get_controlnet_models().await?;
+        for unit in &params.controlnet {
+            unit.validate()?;
+            if !modules.contains(&unit.module) {
+                return Err(Error::msg(format!(
+                    "The ControlNet module {:?} is not available. Available modules: {}",
+                    unit.module,
+                    modules.join(", ")
+                )));
+            }
+            let installed = models
+                .iter()
+                .any(|model| model == &unit.model || without_hash(model) == unit.model);
+            if !installed {
+                return Err(Error::msg(format!(
+                    "The ControlNet model {:?} is not installed. Available models: {}",
+                    unit.model,
+                    models.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove the hash from a ControlNet model name, e.g. `control_v11p_sd15_openpose [cab727d4]`.
+fn without_hash(model: &str) -> &str {
+    match model.rsplit_once(" [") {
+        Some((name, hash)) if hash.ends_with(']') => name,
+        _ => model,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_hash() {
+        assert_eq!(
+            without_hash("control_v11p_sd15_openpose [cab727d4]"),
+            "control_v11p_sd15_openpose"
+        );
+        assert_eq!(
+            without_hash("control_v11p_sd15_openpose"),
+            "control_v11p_sd15_openpose"
+        );
+    }
+
+    #[test]
+    fn test_no_scripts_without_units() -> Result<()> {
+        assert_eq!(AlwaysOnScripts::from_params(&ImageParams::default())?, None);
+        Ok(())
+    }
+}
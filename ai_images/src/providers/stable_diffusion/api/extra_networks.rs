@@ -0,0 +1,132 @@
+use super::{ImageParams, StableDiffusionXLProvider};
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A LoRA installed on the Stable Diffusion instance.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct StableDiffusionLora {
+    pub name: String,
+    pub alias: Option<String>,
+    pub path: Option<String>,
+}
+
+/// The textual-inversion embeddings installed on the Stable Diffusion instance.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StableDiffusionEmbeddings {
+    /// Embeddings that can be used with the loaded model.
+    #[serde(default)]
+    pub loaded: Map<String, Value>,
+    /// Embeddings that were trained for a different model architecture.
+    #[serde(default)]
+    pub skipped: Map<String, Value>,
+}
+
+impl StableDiffusionXLProvider {
+    /// Get a list of the installed LoRAs.
+    pub async fn get_loras(&self) -> Result<Vec<StableDiffusionLora>> {
+        let endpoint = "/sdapi/v1/loras";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::get(&url).await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to list LoRAs. Request URL: {:?}, Status: {}",
+                url,
+                response.status()
+            )));
+        }
+        let loras = response.text().await?;
+        let loras: Vec<StableDiffusionLora> = serde_json::from_str(&loras)?;
+        Ok(loras)
+    }
+
+    /// Get the installed textual-inversion embeddings.
+    pub async fn get_embeddings(&self) -> Result<StableDiffusionEmbeddings> {
+        let endpoint = "/sdapi/v1/embeddings";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::get(&url).await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to list embeddings. Request URL: {:?}, Status: {}",
+                url,
+                response.status()
+            )));
+        }
+        let embeddings = response.text().await?;
+        let embeddings: StableDiffusionEmbeddings = serde_json::from_str(&embeddings)?;
+        Ok(embeddings)
+    }
+
+    /// Check that every requested LoRA and embedding is installed, so a typo fails before anything is generated
+    /// instead of being silently ignored by the prompt parser.
+    pub async fn validate_extra_networks(&self, params: &ImageParams) -> Result<()> {
+        if !params.loras.is_empty() {
+            let loras = self.get_loras().await?;
+            let mut available: Vec<&str> = loras
+                .iter()
+                .flat_map(|lora| std::iter::once(lora.name.as_str()).chain(lora.alias.as_deref()))
+                .collect();
+            available.sort_unstable();
+            available.dedup();
+            for lora in &params.loras {
+                if !available.contains(&lora.name.as_str()) {
+                    return Err(Error::msg(format!(
+                        "The LoRA {:?} is not installed. Available LoRAs: {}",
+                        lora.name,
+                        available.join(", ")
+                    )));
+                }
+            }
+        }
+
+        let embeddings = params.embeddings.iter().chain(&params.negative_embeddings);
+        if embeddings.clone().next().is_some() {
+            let installed = self.get_embeddings().await?;
+            for embedding in embeddings {
+                if installed.skipped.contains_key(&embedding.name) {
+                    return Err(Error::msg(format!(
+                        "The embedding {:?} is installed but is not compatible with the loaded model.",
+                        embedding.name
+                    )));
+                }
+                if !installed.loaded.contains_key(&embedding.name) {
+                    let available: Vec<&str> =
+                        installed.loaded.keys().map(String::as_str).collect();
+                    return Err(Error::msg(format!(
+                        "The embedding {:?} is not installed. Available embeddings: {}",
+                        embedding.name,
+                        available.join(", ")
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial(stable_diffusion)]
+    async fn test_get_loras() -> Result<()> {
+        let provider = StableDiffusionXLProvider::default();
+        let loras = provider.get_loras().await?;
+        println!("LoRAs: {:?}", loras);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_embeddings() -> Result<()> {
+        let response = r#"{
+            "loaded": {"easynegative": {"step": null, "sd_checkpoint": null, "sd_checkpoint_name": null, "shape": 768, "vectors": 8}},
+            "skipped": {"sdxl_style": {"step": null, "sd_checkpoint": null, "sd_checkpoint_name": null, "shape": 2048, "vectors": 1}}
+        }"#;
+        let embeddings: StableDiffusionEmbeddings = serde_json::from_str(response)?;
+        assert!(embeddings.loaded.contains_key("easynegative"));
+        assert!(embeddings.skipped.contains_key("sdxl_style"));
+        Ok(())
+    }
+}
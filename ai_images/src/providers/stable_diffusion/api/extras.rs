@@ -0,0 +1,62 @@
+use super::{Base64Image, StableDiffusionXLProvider};
+use crate::params::Upscale;
+use anyhow::{Error, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Request body for upscaling a single image.
+#[derive(Debug, Serialize)]
+pub struct ExtraSingleImageRequestBody {
+    /// The base64-encoded image to upscale.
+    pub image: String,
+    /// 0 scales the image by `upscaling_resize`, and 1 resizes it to a given size.
+    pub resize_mode: u8,
+    /// How many times larger to make the image.
+    pub upscaling_resize: f32,
+    /// The name of the upscaler, as listed by `/sdapi/v1/upscalers`.
+    pub upscaler_1: String,
+}
+
+impl ExtraSingleImageRequestBody {
+    pub fn new(image: String, upscale: &Upscale) -> Self {
+        Self {
+            image,
+            resize_mode: 0,
+            upscaling_resize: upscale.scale,
+            upscaler_1: upscale.upscaler.clone(),
+        }
+    }
+}
+
+impl StableDiffusionXLProvider {
+    /// Send a POST request to `/sdapi/v1/extra-single-image` to upscale an image.
+    /// The agent-scheduler doesn't queue upscaling, so this is always sent directly.
+    pub async fn post_extra_single_image(
+        &self,
+        request: &ExtraSingleImageRequestBody,
+    ) -> Result<Base64Image> {
+        let endpoint = "/sdapi/v1/extra-single-image";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::Client::new()
+            .post(url)
+            .json(request)
+            .send()
+            .await?;
+        let status = response.status();
+        let response: Value = serde_json::from_str(&response.text().await?)?;
+        // Unknown upscalers are rejected with a 404 and a detail message.
+        if !status.is_success() {
+            return Err(Error::msg(format!(
+                "Failed to upscale the image with {:?}. Status: {}, Response: {}",
+                request.upscaler_1, status, response
+            )));
+        }
+        let image = response["image"]
+            .as_str()
+            .ok_or(Error::msg("Unable to get the upscaled image."))?;
+        Ok(Base64Image {
+            image: image.to_string(),
+            seed: None,
+        })
+    }
+}
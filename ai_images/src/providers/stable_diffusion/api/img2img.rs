@@ -0,0 +1,107 @@
+use super::{
+    txt2img::{images_from_response, Txt2ImgRequestBody},
+    Base64Image, StableDiffusionXLProvider,
+};
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A txt2img request that starts from existing images instead of noise.
+/// With a mask, only the masked area is repainted.
+#[derive(Debug, Serialize, Args, Deserialize, Default)]
+pub struct Img2ImgRequestBody {
+    /// The base64-encoded images to start from.
+    #[clap(long)]
+    pub init_images: Vec<String>,
+    /// How much the images are changed, from 0 (unchanged) to 1 (replaced entirely).
+    #[clap(long)]
+    pub denoising_strength: f32,
+    /// How the images are fitted to the requested size. 0 stretches, 1 crops, 2 fills, and 3 resizes in latent space.
+    #[clap(long)]
+    pub resize_mode: u8,
+    /// A base64-encoded mask. White areas are repainted and black areas are kept. Only used for inpainting.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<String>,
+    /// What the masked area is filled with before it is repainted. 0 fills, 1 keeps the original, 2 adds noise, and 3 adds nothing.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inpainting_fill: Option<u8>,
+    /// How many pixels the edge of the mask is blurred by.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_blur: Option<u32>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub txt2img: Txt2ImgRequestBody,
+}
+
+impl StableDiffusionXLProvider {
+    /// Send a POST request to `/sdapi/v1/img2img` to start a new image generation task from the init images.
+    /// The response contains the images in base64 encoding.
+    pub async fn post_img2img(&self, request: &Img2ImgRequestBody) -> Result<Vec<Base64Image>> {
+        let endpoint = "/sdapi/v1/img2img";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let body = serde_json::to_string(request)?;
+        let client = reqwest::Client::new();
+        let response = client.post(url).body(body).send().await?;
+        let response: Value = serde_json::from_str(&response.text().await?)?;
+        images_from_response(&response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use base64::Engine;
+    use serial_test::serial;
+
+    #[test]
+    fn test_request_body_is_flat() -> Result<()> {
+        let request = Img2ImgRequestBody {
+            init_images: vec!["aW1hZ2U=".to_string()],
+            denoising_strength: 0.5,
+            resize_mode: 1,
+            ..Default::default()
+        };
+        let body = serde_json::to_value(&request)?;
+        assert_eq!(body["init_images"][0], "aW1hZ2U=");
+        assert_eq!(body["resize_mode"], 1);
+        assert_eq!(body["width"], 1024);
+        assert!(body.get("txt2img").is_none());
+        assert!(body.get("mask").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial(stable_diffusion, local_server)]
+    async fn test_post_img2img() {
+        let provider = StableDiffusionXLProvider::default();
+        let init_image = image::RgbImage::from_pixel(512, 512, image::Rgb([128, 64, 32]));
+        let mut init_image_png = std::io::Cursor::new(Vec::new());
+        init_image
+            .write_to(&mut init_image_png, image::ImageFormat::Png)
+            .unwrap();
+        let request = Img2ImgRequestBody {
+            init_images: vec![base64::prelude::BASE64_STANDARD.encode(init_image_png.into_inner())],
+            denoising_strength: 0.6,
+            resize_mode: 0,
+            txt2img: Txt2ImgRequestBody {
+                prompt: "A cat".to_string(),
+                sampler_name: "DPM++ 2M".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let received_images = provider.post_img2img(&request).await.unwrap();
+        // Assert that we get one image at the requested size, not the init image's size.
+        assert_eq!(received_images.len(), 1);
+        let image = &received_images.first().unwrap().image;
+        let image = base64::prelude::BASE64_STANDARD.decode(image).unwrap();
+        let image = image::load_from_memory(&image).unwrap();
+        assert_eq!(image.width(), 1024);
+        assert_eq!(image.height(), 1024);
+    }
+}
@@ -0,0 +1,34 @@
+use super::StableDiffusionXLProvider;
+use crate::cancel::{cancelled_error, CancellationToken};
+use anyhow::{Error, Result};
+use std::future::Future;
+
+impl StableDiffusionXLProvider {
+    /// Send a POST request to `/sdapi/v1/interrupt` to stop the image that is being rendered.
+    /// The instance returns the images it finished so far, which are discarded.
+    pub async fn post_interrupt(&self) -> Result<()> {
+        let endpoint = "/sdapi/v1/interrupt";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::Client::new().post(url).send().await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to interrupt the render. Status: {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Wait for a direct request, interrupting the render on the instance if `cancel` is triggered first.
+    pub async fn interruptible<T>(
+        &self,
+        cancel: &CancellationToken,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(cancelled_error(self.post_interrupt().await)),
+            result = request => result,
+        }
+    }
+}
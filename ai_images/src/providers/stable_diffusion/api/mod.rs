@@ -1,5 +1,11 @@
 pub mod config;
+pub mod controlnet;
+pub mod extra_networks;
+pub mod extras;
+pub mod img2img;
+pub mod interrupt;
 pub mod model;
+pub mod progress;
 pub mod queue;
 pub mod status;
 pub mod txt2img;
@@ -9,8 +15,9 @@ use clap::Subcommand;
 use serde::Serialize;
 
 /// Types of requests that can be sent to the Stable Diffusion API to generate images.
-/// Right now only supports Txt2Img requests. Other potential requests include Img2Img and Control.
+/// Right now supports Txt2Img and Img2Img requests. Other potential requests include Control.
 #[derive(Debug, Serialize, Subcommand)]
 pub enum RequestBody {
     Txt2Img(txt2img::Txt2ImgRequestBody),
+    Img2Img(img2img::Img2ImgRequestBody),
 }
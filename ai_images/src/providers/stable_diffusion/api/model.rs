@@ -1,19 +1,57 @@
 use super::StableDiffusionXLProvider;
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A checkpoint installed on the Stable Diffusion instance.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StableDiffusionModel {
-    title: String,
-    model_name: String,
-    filename: String,
-    #[serde(rename = "type")]
-    model_type: String,
-    sha256: Option<String>,
-    hash: Option<String>,
-    config: Option<String>,
+    /// The name the instance loads the model by, e.g. `sd_xl_base_1.0.safetensors [31e35c80fc]`.
+    pub title: String,
+    /// The file name without its extension, e.g. `sd_xl_base_1.0`.
+    pub model_name: String,
+    /// The full path to the model file.
+    pub filename: String,
+    #[serde(rename = "type", default)]
+    pub model_type: String,
+    pub sha256: Option<String>,
+    /// The first 10 characters of the SHA-256 hash.
+    pub hash: Option<String>,
+    pub config: Option<String>,
 }
 
+impl StableDiffusionModel {
+    /// Whether the model's file is named `name`, with or without the directory and extension.
+    fn has_file_name(&self, name: &str) -> bool {
+        let path = Path::new(&self.filename);
+        [path.file_name(), path.file_stem()]
+            .into_iter()
+            .flatten()
+            .any(|file_name| file_name.eq_ignore_ascii_case(name))
+            || self.filename.eq_ignore_ascii_case(name)
+    }
+
+    /// Whether `prefix` is the start of the model's short hash or SHA-256 hash.
+    fn has_hash_prefix(&self, prefix: &str) -> bool {
+        let prefix = prefix.to_ascii_lowercase();
+        [&self.hash, &self.sha256]
+            .into_iter()
+            .flatten()
+            .any(|hash| hash.to_ascii_lowercase().starts_with(&prefix))
+    }
+}
+
+/// The models installed on each instance, keyed by URL, so they are only listed once per run.
+fn model_cache() -> &'static Mutex<HashMap<String, Vec<StableDiffusionModel>>> {
+    static MODELS: OnceLock<Mutex<HashMap<String, Vec<StableDiffusionModel>>>> = OnceLock::new();
+    MODELS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Hash prefixes shorter than this are too likely to be part of a name instead.
+const MIN_HASH_PREFIX: usize = 6;
+
 impl StableDiffusionXLProvider {
     /// Get the name of the currently loaded checkpoint
     pub async fn get_model_name(&self) -> Result<String> {
@@ -35,12 +73,51 @@ impl StableDiffusionXLProvider {
     pub async fn get_models(&self) -> Result<Vec<StableDiffusionModel>> {
         let endpoint = "/sdapi/v1/sd-models";
         let url = format!("{}{}", self.get_url(), endpoint);
-        let response = reqwest::get(url).await?;
+        let response = reqwest::get(&url).await?;
+        if !response.status().is_success() {
+            return Err(Error::msg(format!(
+                "Failed to list models. Request URL: {:?}, Status: {}",
+                url,
+                response.status()
+            )));
+        }
         let models = response.text().await?;
         let models: Vec<StableDiffusionModel> = serde_json::from_str(&models)?;
         Ok(models)
     }
 
+    /// Get the list of available models, reusing the list from an earlier call unless `refresh` is set.
+    pub async fn get_models_cached(&self, refresh: bool) -> Result<Vec<StableDiffusionModel>> {
+        let url = self.get_url();
+        if !refresh {
+            // A poisoned cache only means another thread panicked while holding the lock; the map is still usable.
+            let cache = model_cache().lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(models) = cache.get(&url) {
+                return Ok(models.clone());
+            }
+        }
+        let models = self.get_models().await?;
+        model_cache()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(url, models.clone());
+        Ok(models)
+    }
+
+    /// Find the installed model that `name` refers to, by its title, model name, file name, or a prefix of its hash.
+    /// If it isn't in the cached list, the list is fetched again in case the model was added since.
+    /// Typos fail with the closest model names.
+    pub async fn resolve_model(&self, name: &str) -> Result<StableDiffusionModel> {
+        let models = self.get_models_cached(false).await?;
+        if let Some(model) = find_model(&models, name)? {
+            return Ok(model.clone());
+        }
+        let models = self.get_models_cached(true).await?;
+        find_model(&models, name)?
+            .cloned()
+            .ok_or_else(|| unknown_model_error(&models, name))
+    }
+
     /// Set the model to use for generating images
     pub async fn set_model(&self, model_name: &str) -> Result<()> {
         let endpoint = "/sdapi/v1/options";
@@ -64,6 +141,89 @@ impl StableDiffusionXLProvider {
     }
 }
 
+/// Find the model that `name` refers to. Each way of naming a model is tried in turn, from the most to the least
+/// specific, and a name that matches more than one model at the same level is an error.
+fn find_model<'a>(
+    models: &'a [StableDiffusionModel],
+    name: &str,
+) -> Result<Option<&'a StableDiffusionModel>> {
+    let name = name.trim();
+    let is_hash_prefix =
+        name.len() >= MIN_HASH_PREFIX && name.chars().all(|c| c.is_ascii_hexdigit());
+    let matchers: [&dyn Fn(&StableDiffusionModel) -> bool; 4] = [
+        &|model| model.title.eq_ignore_ascii_case(name),
+        &|model| model.model_name.eq_ignore_ascii_case(name),
+        &|model| model.has_file_name(name),
+        &|model| is_hash_prefix && model.has_hash_prefix(name),
+    ];
+    for matches in matchers {
+        let found: Vec<&StableDiffusionModel> =
+            models.iter().filter(|model| matches(model)).collect();
+        match found.as_slice() {
+            [] => continue,
+            [model] => return Ok(Some(model)),
+            _ => {
+                let titles: Vec<&str> = found.iter().map(|model| model.title.as_str()).collect();
+                return Err(Error::msg(format!(
+                    "The model {:?} matches more than one model: {}. Use the full title instead.",
+                    name,
+                    titles.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The error for a model that isn't installed, listing the closest model names, or every model if none are close.
+fn unknown_model_error(models: &[StableDiffusionModel], name: &str) -> Error {
+    let lowercase = name.trim().to_lowercase();
+    let mut close: Vec<(usize, &str)> = models
+        .iter()
+        .filter_map(|model| {
+            let model_name = model.model_name.to_lowercase();
+            let distance = edit_distance(&lowercase, &model_name);
+            let is_close = distance <= (lowercase.len() / 3).max(2)
+                || model_name.contains(&lowercase)
+                || lowercase.contains(&model_name);
+            is_close.then_some((distance, model.model_name.as_str()))
+        })
+        .collect();
+    close.sort();
+    let names: Vec<&str> = match close.is_empty() {
+        true => models
+            .iter()
+            .map(|model| model.model_name.as_str())
+            .collect(),
+        false => close.iter().take(3).map(|(_, name)| *name).collect(),
+    };
+    let hint = match close.is_empty() {
+        true => "Available models",
+        false => "Did you mean",
+    };
+    Error::msg(format!(
+        "The model {:?} is not installed. {}: {}",
+        name.trim(),
+        hint,
+        names.join(", ")
+    ))
+}
+
+/// The number of single-character insertions, deletions, and substitutions to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used)]
@@ -97,4 +257,75 @@ mod tests {
         let model_name = &models[0].model_name;
         provider.set_model(model_name).await
     }
+
+    fn model(title: &str, model_name: &str, hash: &str) -> StableDiffusionModel {
+        StableDiffusionModel {
+            title: title.to_string(),
+            model_name: model_name.to_string(),
+            filename: format!("/models/Stable-diffusion/{}.safetensors", model_name),
+            model_type: String::new(),
+            sha256: None,
+            hash: Some(hash.to_string()),
+            config: None,
+        }
+    }
+
+    #[test]
+    fn test_find_model() -> Result<()> {
+        let models = [
+            model(
+                "sd_xl_base_1.0.safetensors [31e35c80fc]",
+                "sd_xl_base_1.0",
+                "31e35c80fc",
+            ),
+            model(
+                "juggernautXL_v9.safetensors [c9e3e68f89]",
+                "juggernautXL_v9",
+                "c9e3e68f89",
+            ),
+            model(
+                "juggernautXL_v8.safetensors [aeb7e9e689]",
+                "juggernautXL_v8",
+                "aeb7e9e689",
+            ),
+        ];
+        for name in [
+            "sd_xl_base_1.0.safetensors [31e35c80fc]",
+            "sd_xl_base_1.0",
+            "SD_XL_BASE_1.0",
+            "sd_xl_base_1.0.safetensors",
+            "/models/Stable-diffusion/sd_xl_base_1.0.safetensors",
+            "31e35c",
+        ] {
+            assert_eq!(find_model(&models, name)?, Some(&models[0]), "{}", name);
+        }
+        // Too short to be a hash.
+        assert_eq!(find_model(&models, "31e3")?, None);
+        assert_eq!(find_model(&models, "dreamshaper")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_model_suggestions() {
+        let models = [
+            model("sd_xl_base_1.0.safetensors", "sd_xl_base_1.0", "31e35c80fc"),
+            model(
+                "juggernautXL_v9.safetensors",
+                "juggernautXL_v9",
+                "c9e3e68f89",
+            ),
+        ];
+        let error = unknown_model_error(&models, "sd_xl_bsae_1.0").to_string();
+        assert!(error.contains("Did you mean: sd_xl_base_1.0"), "{}", error);
+        assert!(!error.contains("juggernaut"));
+        let error = unknown_model_error(&models, "dreamshaper").to_string();
+        assert!(error.contains("Available models: sd_xl_base_1.0, juggernautXL_v9"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
 }
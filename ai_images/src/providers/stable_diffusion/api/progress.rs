@@ -0,0 +1,98 @@
+use super::{Base64Image, StableDiffusionXLProvider};
+use crate::progress::{Progress, ProgressEvent};
+use anyhow::Result;
+use serde::Deserialize;
+use std::future::Future;
+use std::time::Duration;
+
+/// The response from `/sdapi/v1/progress`.
+#[derive(Debug, Deserialize)]
+struct ProgressResponse {
+    /// How much of the current request is done, from 0 to 1.
+    progress: f32,
+    /// The estimated number of seconds left.
+    eta_relative: f32,
+    state: ProgressState,
+    /// A base64-encoded preview of the image, if live previews are enabled.
+    current_image: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProgressState {
+    sampling_step: u32,
+    sampling_steps: u32,
+}
+
+impl StableDiffusionXLProvider {
+    /// Send a GET request to `/sdapi/v1/progress` to check on the image that is being rendered.
+    /// If the instance sends a preview, it is saved to the temp directory.
+    pub async fn get_progress(&self) -> Result<ProgressEvent> {
+        let endpoint = "/sdapi/v1/progress";
+        let url = format!("{}{}", self.get_url(), endpoint);
+        let response = reqwest::get(url).await?;
+        let response: ProgressResponse = serde_json::from_str(&response.text().await?)?;
+        let preview = match response.current_image {
+            Some(image) => {
+                let path = std::env::temp_dir()
+                    .join(format!("ai_images-preview-{}.png", std::process::id()));
+                Base64Image { image, seed: None }.to_file(&path).ok()
+            }
+            None => None,
+        };
+        Ok(ProgressEvent::Rendering {
+            step: response.state.sampling_step,
+            steps: response.state.sampling_steps,
+            fraction: response.progress,
+            eta: (response.eta_relative > 0.0)
+                .then(|| Duration::from_secs_f32(response.eta_relative)),
+            preview,
+        })
+    }
+
+    /// Wait for a direct request, reporting the instance's progress every second until it finishes.
+    /// Progress that can't be read is skipped, since it doesn't affect the result.
+    pub async fn with_progress<T>(
+        &self,
+        progress: &Progress,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if !progress.is_enabled() {
+            return request.await;
+        }
+        // Progress is checked alongside the request rather than in between polls of it,
+        // so a slow check never holds up the request.
+        let report = async {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if let Ok(event) = self.get_progress().await {
+                    progress.report(event);
+                }
+            }
+        };
+        tokio::select! {
+            result = request => result,
+            _ = report => unreachable!("progress is reported until the request finishes"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_progress() -> Result<()> {
+        let response = r#"{
+            "progress": 0.25, "eta_relative": 12.5,
+            "state": {"skipped": false, "interrupted": false, "job": "", "job_count": 1, "job_timestamp": "0",
+                      "job_no": 0, "sampling_step": 5, "sampling_steps": 20},
+            "current_image": null, "textinfo": null
+        }"#;
+        let response: ProgressResponse = serde_json::from_str(response)?;
+        assert_eq!(response.state.sampling_step, 5);
+        assert_eq!(response.state.sampling_steps, 20);
+        assert!(response.current_image.is_none());
+        Ok(())
+    }
+}
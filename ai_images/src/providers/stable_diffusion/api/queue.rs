@@ -1,6 +1,16 @@
 //! Send image generation tasks to the queue.
 
-use super::{txt2img::Txt2ImgRequestBody, Base64Image, ImageParams, StableDiffusionXLProvider};
+use super::{
+    controlnet::AlwaysOnScripts,
+    txt2img::{leading_grids, HiresFix, OverrideSettings},
+    Base64Image, ImageParams, StableDiffusionXLProvider,
+};
+use crate::cancel::{cancelled_error, CancellationToken, Cancelled};
+use crate::images::string::{file_to_base64, image_to_base64};
+use crate::images::{decode_images, ImageData};
+use crate::params::{InitImage, InpaintParams};
+use crate::progress::{Progress, ProgressEvent};
+use crate::providers::stable_diffusion::pending::{self, PendingTask};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{Number, Value};
@@ -22,8 +32,17 @@ enum TaskStatus {
     Interrupted,
 }
 
-#[derive(Debug, Serialize)]
-struct OverrideSettings {}
+/// How a task ended, or didn't, while it was waited for.
+enum TaskOutcome {
+    /// The task finished with these images.
+    Done(Vec<Base64Image>),
+    /// The task failed on the instance, for this reason.
+    Failed(String),
+    /// Someone else interrupted the task on the instance.
+    Interrupted,
+    /// The task was still queued or running when the timeout ran out.
+    Pending,
+}
 
 /// Request body for starting a new image generation task.
 #[derive(Debug, Serialize)]
@@ -35,8 +54,14 @@ struct RequestBody {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<Number>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub subseed: Option<Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subseed_strength: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub batch_size: Option<Number>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_iter: Option<Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub steps: Option<Number>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<Number>,
@@ -45,7 +70,13 @@ struct RequestBody {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sampler_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub cfg_scale: Option<Number>,
+    pub cfg_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiling: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore_faces: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub override_settings: Option<OverrideSettings>,
     pub script_name: String,
@@ -55,6 +86,30 @@ struct RequestBody {
     pub vae: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub callback_url: Option<String>,
+    /// The base64-encoded images to start from. Only used by img2img tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoising_strength: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resize_mode: Option<u8>,
+    /// The base64-encoded mask. Only used by inpainting tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inpainting_fill: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask_blur: Option<u32>,
+    /// Upscale the images and add detail in a second pass, using `denoising_strength`. Only used by txt2img tasks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_hr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hr_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hr_upscaler: Option<String>,
+    /// Extension scripts to run with the task, e.g. ControlNet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alwayson_scripts: Option<AlwaysOnScripts>,
 }
 
 impl Default for RequestBody {
@@ -63,58 +118,84 @@ impl Default for RequestBody {
             prompt: None,
             negative_prompt: None,
             seed: None,
+            subseed: None,
+            subseed_strength: None,
             batch_size: None,
+            n_iter: None,
             steps: None,
             width: None,
             height: None,
             sampler_name: None,
             cfg_scale: None,
+            scheduler: None,
+            tiling: None,
+            restore_faces: None,
             override_settings: None,
             script_name: "".to_string(),
             checkpoint: None,
             vae: None,
             callback_url: None,
+            init_images: None,
+            denoising_strength: None,
+            resize_mode: None,
+            mask: None,
+            inpainting_fill: None,
+            mask_blur: None,
+            enable_hr: None,
+            hr_scale: None,
+            hr_upscaler: None,
+            alwayson_scripts: None,
         }
     }
 }
 
 impl RequestBody {
-    pub fn from_params(params: &ImageParams) -> Self {
-        Self {
+    /// Build a task from the parameters. Fails if a ControlNet image can't be read.
+    pub fn from_params(params: &ImageParams) -> Result<Self> {
+        Ok(Self {
             prompt: if params.prompt.to_string().is_empty() {
                 None
             } else {
                 Some(params.prompt.to_string())
             },
             negative_prompt: params.prompt.negative.clone(),
-            seed: None,
-            batch_size: Some(Number::from(1)),
+            seed: params.seed.map(Number::from),
+            subseed: params.subseed.map(Number::from),
+            subseed_strength: params.subseed_strength,
+            batch_size: Some(Number::from(params.batch_size.unwrap_or(1))),
+            n_iter: params.n_iter.map(Number::from),
             steps: Some(Number::from(params.steps)),
             width: Some(Number::from(params.width)),
             height: Some(Number::from(params.height)),
             sampler_name: Some(params.sampler_name.clone()),
-            cfg_scale: Some(Number::from(params.cfg_scale)),
-            override_settings: None,
+            cfg_scale: Some(params.cfg_scale),
+            scheduler: params.scheduler.clone(),
+            tiling: params.tiling.then_some(true),
+            restore_faces: params.restore_faces.then_some(true),
+            override_settings: OverrideSettings::from_params(params),
             script_name: "".to_string(),
             checkpoint: params.model.clone(),
-            vae: None,
+            vae: params.vae.clone(),
             callback_url: None,
-        }
+            init_images: None,
+            denoising_strength: None,
+            resize_mode: None,
+            mask: None,
+            inpainting_fill: None,
+            mask_blur: None,
+            enable_hr: None,
+            hr_scale: None,
+            hr_upscaler: None,
+            alwayson_scripts: AlwaysOnScripts::from_params(params)?,
+        })
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TaskStatusParams {
-    checkpoint: Option<String>,
-    images: Vec<String>,
-    info: String,
-    parameters: Txt2ImgRequestBody,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 struct TaskStatusResponse {
     success: bool,
-    data: TaskStatusData,
+    /// Missing if the task isn't in the queue or its history.
+    data: Option<TaskStatusData>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -135,6 +216,32 @@ struct TaskStatusData {
     updated_at: String,
 }
 
+/// The tasks in the queue.
+#[derive(Debug, Deserialize)]
+struct QueueStatus {
+    current_task_id: Option<String>,
+    pending_tasks: Vec<QueuedTask>,
+    total_pending_tasks: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueuedTask {
+    id: String,
+}
+
+impl QueueStatus {
+    /// How many tasks will run before the task, including the one that is running now.
+    /// Only the first page of pending tasks is listed, so tasks further back are counted from the total.
+    fn position(&self, task_id: &str) -> usize {
+        let pending = self
+            .pending_tasks
+            .iter()
+            .position(|task| task.id == task_id)
+            .unwrap_or(self.total_pending_tasks);
+        pending + usize::from(self.current_task_id.is_some())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct TaskResults {
     success: bool,
@@ -150,10 +257,24 @@ struct TaskResultsData {
 }
 
 impl StableDiffusionXLProvider {
-    /// Send a POST request to `/agent-scheduler/v1/queue/txt2img` to start a new image generation task.
+    /// Check whether the agent-scheduler extension is installed by requesting its queue.
+    pub async fn has_agent_scheduler(&self) -> bool {
+        let url = format!("{}/agent-scheduler/v1/queue?limit=1", self.get_url());
+        match reqwest::get(url).await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+
+    /// Send a POST request to `/agent-scheduler/v1/queue/{request_type}` to start a new image generation task.
+    /// The request type is either `txt2img` or `img2img`.
     /// The response contains the task_id for the image generation task.
-    async fn start_image_generation_task(&self, request_body: &RequestBody) -> Result<TaskId> {
-        let endpoint = "/agent-scheduler/v1/queue/txt2img";
+    async fn start_image_generation_task(
+        &self,
+        request_type: &str,
+        request_body: &RequestBody,
+    ) -> Result<TaskId> {
+        let endpoint = format!("/agent-scheduler/v1/queue/{}", request_type);
         let url = format!("{}{}", self.get_url(), endpoint);
         let body = serde_json::to_string(request_body)?;
         let client = reqwest::Client::new();
@@ -163,77 +284,300 @@ impl StableDiffusionXLProvider {
         Ok(response.task_id)
     }
 
-    /// Check the status of the task.
-    async fn get_task_status(&self, task_id: &TaskId) -> Result<TaskStatus> {
+    /// Check the status of the task. Returns None if the instance doesn't know the task, e.g. because it was deleted.
+    async fn get_task_status(&self, task_id: &TaskId) -> Result<Option<TaskStatus>> {
         let endpoint = format!("/agent-scheduler/v1/task/{}", task_id);
         let url = format!("{}{}", self.get_url(), endpoint);
         let response = reqwest::get(url).await?;
         let response_text = response.text().await?;
         let response: TaskStatusResponse = serde_json::from_str(&response_text)?;
-        // The status is in the "msg" field of the response.
-        let status = response.data.status;
-        Ok(status)
+        Ok(response
+            .data
+            .filter(|_| response.success)
+            .map(|data| data.status))
+    }
+
+    /// Send a DELETE request to `/agent-scheduler/v1/task/{task_id}` to remove the task from the queue.
+    /// If the task is already running, the agent-scheduler interrupts it first.
+    async fn delete_task(&self, task_id: &str) -> Result<()> {
+        let url = format!("{}/agent-scheduler/v1/task/{}", self.get_url(), task_id);
+        let response = reqwest::Client::new().delete(url).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to delete task {}. Status: {}",
+                task_id,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the number of tasks ahead of the task in the queue.
+    async fn get_queue_position(&self, task_id: &str) -> Result<usize> {
+        let url = format!("{}/agent-scheduler/v1/queue", self.get_url());
+        let response = reqwest::get(url).await?;
+        let queue: QueueStatus = serde_json::from_str(&response.text().await?)?;
+        Ok(queue.position(task_id))
     }
 
-    /// Get the results of the task. Results are a base64-encoded image.
-    async fn get_task_results(&self, task_id: &str) -> Result<String> {
+    /// Report how far along the task is: its place in the queue while it waits, and the render progress once it runs.
+    /// Progress that can't be read is skipped, since it doesn't affect the result.
+    async fn report_task_progress(&self, task_id: &str, status: &TaskStatus, progress: &Progress) {
+        let event = match status {
+            TaskStatus::Pending => self
+                .get_queue_position(task_id)
+                .await
+                .map(|position| ProgressEvent::Queued { position }),
+            TaskStatus::Running => self.get_progress().await,
+            _ => return,
+        };
+        if let Ok(event) = event {
+            progress.report(event);
+        }
+    }
+
+    /// Get the results of the task. Results are base64-encoded images with their generation parameters.
+    /// If there are more results than the `count` images requested, the extras are a grid of the batch or images added
+    /// by extensions like ControlNet, and are dropped.
+    async fn get_task_results(&self, task_id: &str, count: usize) -> Result<Vec<Base64Image>> {
         let endpoint = format!("/agent-scheduler/v1/task/{}/results", task_id);
         let url = format!("{}{}", self.get_url(), endpoint);
         let response = reqwest::get(url).await?;
         let response_text = response.text().await?;
         let results: TaskResults = serde_json::from_str(&response_text)?;
-        let image = results
-            .data
-            .first()
-            .ok_or(anyhow!(
+        if results.data.is_empty() {
+            return Err(anyhow!(
                 "No images returned. Result response: {:?}",
                 response_text
-            ))?
-            .image
-            .clone();
-        // The image string is prefixed with "data:image/png;base64," which needs to be removed.
-        let image = image
-            .trim_start_matches("data:image/png;base64,")
-            .to_string();
-        Ok(image)
+            ));
+        }
+        let grids = leading_grids(results.data.len(), count);
+        let images = results
+            .data
+            .iter()
+            .skip(grids)
+            .take(count)
+            .map(|result| Base64Image {
+                // The image string is prefixed with "data:image/png;base64," which needs to be removed.
+                image: result
+                    .image
+                    .trim_start_matches("data:image/png;base64,")
+                    .to_string(),
+                seed: seed_from_infotext(&result.infotext),
+            })
+            .collect();
+        Ok(images)
     }
 
-    /// Poll the task until it is complete, returning the base64-encoded image.
-    async fn poll_task(&self, task_id: &TaskId) -> Result<Base64Image> {
-        let timeout = std::time::Duration::from_secs(300);
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    /// Start a task and save it to the state file, so its images can still be collected if this process exits first.
+    async fn start_pending_task(
+        &self,
+        request_type: &str,
+        request_body: &RequestBody,
+        params: &ImageParams,
+    ) -> Result<PendingTask> {
+        let task_id = self
+            .start_image_generation_task(request_type, request_body)
+            .await?;
+        let task = PendingTask {
+            task_id,
+            url: self.get_url(),
+            request_type: request_type.to_string(),
+            queued_at: chrono::Utc::now().timestamp(),
+            count: params.image_count() as usize,
+            params: params.clone(),
+            context: params.task_context.clone(),
+            requested_size: params.requested_size,
+        };
+        // The task is already running, so it is still waited for, even if it couldn't be saved.
+        let state_file = self.state_file(&params.output_directory);
+        if let Err(e) = pending::add_task(&state_file, &task) {
+            eprintln!(
+                "Warning: Task {} could not be saved to {:?}, so it can't be resumed: {}",
+                task.task_id, state_file, e
+            );
+        }
+        Ok(task)
+    }
 
+    /// Poll the task until it is complete, returning its images tagged with its ID.
+    /// If `cancel` is triggered first, the task is deleted so it doesn't keep running on the instance.
+    /// A task that was deleted, or finished without images, is removed from the state file. A task with images
+    /// is only removed once they are saved, and one that is still running when the timeout runs out, or whose
+    /// status or images can't be read, is kept, so it can be resumed.
+    async fn poll_task(
+        &self,
+        task: &PendingTask,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let timeout = self.task_timeout()?;
+        let state_file = self.state_file(&task.params.output_directory);
+        let outcome = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                let error = cancelled_error(self.delete_task(&task.task_id).await);
+                pending::remove_task(&state_file, &task.url, &task.task_id)?;
+                return Err(error);
+            }
+            outcome = tokio::time::timeout(timeout, self.wait_for_task(task, progress)) => {
+                outcome.unwrap_or(Ok(TaskOutcome::Pending))?
+            }
+        };
+        match outcome {
+            TaskOutcome::Done(images) => Ok(decode_images(&images)?
+                .into_iter()
+                .map(|image| ImageData {
+                    task_id: Some(task.task_id.clone()),
+                    ..image
+                })
+                .collect()),
+            TaskOutcome::Failed(reason) => {
+                pending::remove_task(&state_file, &task.url, &task.task_id)?;
+                Err(anyhow!(reason))
+            }
+            TaskOutcome::Interrupted => {
+                pending::remove_task(&state_file, &task.url, &task.task_id)?;
+                Err(Cancelled.into())
+            }
+            TaskOutcome::Pending => Err(anyhow!(
+                "Task {} timed out after {} seconds. It is still queued on the instance, so its images can be collected later by resuming it.",
+                task.task_id,
+                timeout.as_secs_f32()
+            )),
+        }
+    }
+
+    /// Check on the task until it finishes, reporting its progress in the meantime.
+    /// An error means the task couldn't be checked on, not that it failed.
+    async fn wait_for_task(&self, task: &PendingTask, progress: &Progress) -> Result<TaskOutcome> {
+        let task_id = &task.task_id;
+        let mut interval = tokio::time::interval(self.poll_interval()?);
         loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    let status = self.get_task_status(task_id).await?;
-                    match status {
-                        TaskStatus::Done => {
-                            let image: Base64Image = Base64Image { image: self.get_task_results(task_id).await? };
-                            return Ok(image);
-                        }
-                        TaskStatus::Failed => {
-                            return Err(anyhow!("Task failed."));
-                        }
-                        _ => {}
-                    }
+            interval.tick().await;
+            match self.get_task_status(task_id).await? {
+                Some(TaskStatus::Done) => {
+                    return Ok(TaskOutcome::Done(
+                        self.get_task_results(task_id, task.count).await?,
+                    ));
+                }
+                Some(TaskStatus::Failed) => {
+                    return Ok(TaskOutcome::Failed("Task failed.".to_string()));
                 }
-                _ = tokio::time::sleep(timeout) => {
-                    return Err(anyhow!("Task timed out."));
+                // Someone else interrupted the task on the instance.
+                Some(TaskStatus::Interrupted) => return Ok(TaskOutcome::Interrupted),
+                None => {
+                    return Ok(TaskOutcome::Failed(format!(
+                        "Task {} is no longer known to the agent-scheduler.",
+                        task_id
+                    )));
                 }
+                Some(status) if progress.is_enabled() => {
+                    self.report_task_progress(task_id, &status, progress).await;
+                }
+                Some(_) => {}
             }
         }
     }
 
+    /// Wait for a task that was queued by an earlier run, e.g. one that timed out or exited first, and return its images.
+    pub async fn resume_task(
+        &self,
+        task: &PendingTask,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        self.poll_task(task, progress, cancel).await
+    }
+
     /// Add a txt2img task to the queue and wait for it to complete.
-    pub async fn queue_txt2img(&self, params: &ImageParams) -> Result<Base64Image> {
-        let request_body = RequestBody::from_params(params);
-        let task_id = self.start_image_generation_task(&request_body).await?;
-        let image = self.poll_task(&task_id).await?;
-        Ok(image)
+    pub async fn queue_txt2img(
+        &self,
+        params: &ImageParams,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let request_body = match HiresFix::from_params(params) {
+            Some(hires_fix) => RequestBody {
+                enable_hr: Some(hires_fix.enable_hr),
+                hr_scale: Some(hires_fix.hr_scale),
+                hr_upscaler: hires_fix.hr_upscaler,
+                denoising_strength: hires_fix.denoising_strength,
+                ..RequestBody::from_params(params)?
+            },
+            None => RequestBody::from_params(params)?,
+        };
+        let task = self
+            .start_pending_task("txt2img", &request_body, params)
+            .await?;
+        self.poll_task(&task, progress, cancel).await
+    }
+
+    /// Add an img2img task to the queue and wait for it to complete.
+    pub async fn queue_img2img(
+        &self,
+        params: &ImageParams,
+        init_image: &InitImage,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let request_body = RequestBody {
+            init_images: Some(vec![init_image.to_base64()?]),
+            denoising_strength: Some(init_image.denoising_strength),
+            resize_mode: Some(init_image.resize_mode.api_value()),
+            ..RequestBody::from_params(params)?
+        };
+        let task = self
+            .start_pending_task("img2img", &request_body, params)
+            .await?;
+        self.poll_task(&task, progress, cancel).await
+    }
+
+    /// Add an inpainting task to the queue and wait for it to complete.
+    pub async fn queue_inpaint(
+        &self,
+        params: &ImageParams,
+        inpaint: &InpaintParams,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let (image, mask) = inpaint_images(inpaint)?;
+        let request_body = RequestBody {
+            init_images: Some(vec![image]),
+            denoising_strength: Some(inpaint.denoising_strength),
+            resize_mode: Some(0),
+            mask: Some(mask),
+            inpainting_fill: Some(inpaint.inpainting_fill.api_value()),
+            mask_blur: Some(inpaint.mask_blur),
+            ..RequestBody::from_params(params)?
+        };
+        let task = self
+            .start_pending_task("img2img", &request_body, params)
+            .await?;
+        self.poll_task(&task, progress, cancel).await
     }
 }
 
+/// Parse the seed from an image's infotext. The last line holds the generation parameters,
+/// e.g. `Steps: 15, Sampler: UniPC, CFG scale: 2, Seed: 1234, Size: 1024x1024`.
+fn seed_from_infotext(infotext: &str) -> Option<i64> {
+    infotext
+        .lines()
+        .rev()
+        .flat_map(|line| line.split(", "))
+        .find_map(|field| field.trim().strip_prefix("Seed: "))?
+        .parse()
+        .ok()
+}
+
+/// Encode the image to inpaint and its mask in base64. The mask is built at the image's size.
+pub fn inpaint_images(inpaint: &InpaintParams) -> Result<(String, String)> {
+    inpaint.validate()?;
+    let (width, height) = image::image_dimensions(&inpaint.image)?;
+    let mask = image::DynamicImage::ImageLuma8(inpaint.mask_image(width, height)?);
+    Ok((file_to_base64(&inpaint.image)?, image_to_base64(&mask)?))
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used)]
@@ -245,7 +589,9 @@ mod tests {
     async fn test_start_image_generation_task() -> Result<()> {
         let provider = StableDiffusionXLProvider::default();
         let request_body = RequestBody::default();
-        let task_id = provider.start_image_generation_task(&request_body).await?;
+        let task_id = provider
+            .start_image_generation_task("txt2img", &request_body)
+            .await?;
         // Assert that we get a task_id.
         assert!(!task_id.is_empty());
         Ok(())
@@ -256,10 +602,69 @@ mod tests {
     async fn test_poll_task() -> Result<()> {
         let provider = StableDiffusionXLProvider::default();
         let params = ImageParams::default();
-        let request_body = RequestBody::from_params(&params);
-        let task_id = provider.start_image_generation_task(&request_body).await?;
-        let image = provider.poll_task(&task_id).await?;
-        assert!(!image.image.is_empty());
+        let request_body = RequestBody::from_params(&params)?;
+        let task = provider
+            .start_pending_task("txt2img", &request_body, &params)
+            .await?;
+        let images = provider
+            .poll_task(&task, &Progress::default(), &CancellationToken::default())
+            .await?;
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].task_id, Some(task.task_id.clone()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_from_infotext() {
+        let infotext = "A cat\nNegative prompt: blurry\nSteps: 15, Sampler: UniPC, CFG scale: 2, Seed: 1234, Variation seed: 99, Size: 1024x1024";
+        assert_eq!(seed_from_infotext(infotext), Some(1234));
+        assert_eq!(seed_from_infotext("A cat"), None);
+    }
+
+    #[test]
+    fn test_request_body_seed_and_batches() -> Result<()> {
+        let params = ImageParams {
+            seed: Some(42),
+            batch_size: Some(4),
+            n_iter: Some(2),
+            ..Default::default()
+        };
+        let body = serde_json::to_value(RequestBody::from_params(&params)?)?;
+        assert_eq!(body["seed"], 42);
+        assert_eq!(body["batch_size"], 4);
+        assert_eq!(body["n_iter"], 2);
+        assert!(body.get("subseed").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_body_settings() -> Result<()> {
+        let params = ImageParams {
+            cfg_scale: 6.5,
+            scheduler: Some("Karras".to_string()),
+            clip_skip: Some(2),
+            vae: Some("sdxl_vae.safetensors".to_string()),
+            tiling: true,
+            override_settings: [("eta_noise_seed_delta".to_string(), Value::from(31337))].into(),
+            ..Default::default()
+        };
+        let body = serde_json::to_value(RequestBody::from_params(&params)?)?;
+        assert_eq!(body["cfg_scale"], 6.5);
+        assert_eq!(body["scheduler"], "Karras");
+        assert_eq!(body["tiling"], true);
+        assert!(body.get("restore_faces").is_none());
+        assert_eq!(body["vae"], "sdxl_vae.safetensors");
+        assert_eq!(
+            body["override_settings"],
+            serde_json::json!({
+                "CLIP_stop_at_last_layers": 2,
+                "sd_vae": "sdxl_vae.safetensors",
+                "eta_noise_seed_delta": 31337,
+            })
+        );
+
+        let body = serde_json::to_value(RequestBody::from_params(&ImageParams::default())?)?;
+        assert!(body.get("override_settings").is_none());
         Ok(())
     }
 }
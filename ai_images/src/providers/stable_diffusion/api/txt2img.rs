@@ -1,10 +1,10 @@
 use crate::images::Base64Image;
 
-use super::StableDiffusionXLProvider;
+use super::{controlnet::AlwaysOnScripts, ImageParams, StableDiffusionXLProvider};
 use anyhow::Result;
 use clap::Args;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 #[derive(Debug, Serialize, Args, Deserialize)]
 pub struct Txt2ImgRequestBody {
@@ -15,7 +15,103 @@ pub struct Txt2ImgRequestBody {
     pub width: u32,
     pub height: u32,
     pub sampler_name: String,
-    pub cfg_scale: u32,
+    pub cfg_scale: f32,
+    /// The noise schedule, e.g. `Karras`. Only understood by Automatic1111 1.9 and later.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<String>,
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiling: Option<bool>,
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore_faces: Option<bool>,
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subseed: Option<i64>,
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subseed_strength: Option<f32>,
+    /// How many batches of `batch_size` images to generate.
+    #[clap(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_iter: Option<u32>,
+    /// Upscale the images and add detail in a second pass. Not used by img2img.
+    #[clap(skip)]
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub hires_fix: Option<HiresFix>,
+    /// Extension scripts to run with the request, e.g. ControlNet.
+    #[clap(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alwayson_scripts: Option<AlwaysOnScripts>,
+    /// Settings to use for this request only. The instance restores its own settings afterwards.
+    #[clap(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_settings: Option<OverrideSettings>,
+}
+
+/// The hires fix options of a txt2img request.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct HiresFix {
+    pub enable_hr: bool,
+    pub hr_scale: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hr_upscaler: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoising_strength: Option<f32>,
+}
+
+impl HiresFix {
+    /// The hires fix options requested by the parameters, or None if the hires fix is disabled.
+    pub fn from_params(params: &ImageParams) -> Option<Self> {
+        params.enable_hr.then(|| Self {
+            enable_hr: true,
+            hr_scale: params.hires_scale(),
+            hr_upscaler: params.hr_upscaler.clone(),
+            denoising_strength: params.denoising_strength,
+        })
+    }
+}
+
+/// Settings that only apply to one request, by their `/sdapi/v1/options` names.
+/// The settings with a parameter of their own are typed, and any others are passed through as they are.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct OverrideSettings {
+    /// The number of the text encoder's last layers to skip, i.e. the clip skip.
+    #[serde(
+        rename = "CLIP_stop_at_last_layers",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub clip_skip: Option<u32>,
+    /// The VAE to decode the image with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sd_vae: Option<String>,
+    /// Every other setting.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl OverrideSettings {
+    /// The settings requested by the parameters, or None if there aren't any.
+    /// `clip_skip` and `vae` take precedence over the same settings in `override_settings`.
+    pub fn from_params(params: &ImageParams) -> Option<Self> {
+        let mut other: Map<String, Value> = params.override_settings.clone().into_iter().collect();
+        if params.clip_skip.is_some() {
+            other.remove("CLIP_stop_at_last_layers");
+        }
+        if params.vae.is_some() {
+            other.remove("sd_vae");
+        }
+        let settings = Self {
+            clip_skip: params.clip_skip,
+            sd_vae: params.vae.clone(),
+            other,
+        };
+        (settings != Self::default()).then_some(settings)
+    }
 }
 
 impl Default for Txt2ImgRequestBody {
@@ -28,7 +124,17 @@ impl Default for Txt2ImgRequestBody {
             width: 1024,
             height: 1024,
             sampler_name: "Default".to_string(),
-            cfg_scale: 2,
+            cfg_scale: 2.0,
+            scheduler: None,
+            tiling: None,
+            restore_faces: None,
+            seed: None,
+            subseed: None,
+            subseed_strength: None,
+            n_iter: None,
+            hires_fix: None,
+            alwayson_scripts: None,
+            override_settings: None,
         }
     }
 }
@@ -43,20 +149,55 @@ impl StableDiffusionXLProvider {
         let client = reqwest::Client::new();
         let response = client.post(url).body(body).send().await?;
         let response: Value = serde_json::from_str(&response.text().await?)?;
-        let images: Vec<Base64Image> = response["images"]
-            .as_array()
-            .ok_or(anyhow::anyhow!("Unable to get images."))?
-            .iter()
-            .map(|image| {
-                image
-                    .as_str()
-                    .ok_or(anyhow::anyhow!("Unable to get image."))
-                    .map(|image| Base64Image {
-                        image: image.to_string(),
-                    })
-            })
-            .collect::<Result<Vec<Base64Image>>>()?;
-        Ok(images)
+        images_from_response(&response)
+    }
+}
+
+/// Get the base64-encoded images from a `/sdapi/v1/txt2img` or `/sdapi/v1/img2img` response.
+/// The seed of each image is read from `all_seeds` in the `info` JSON string, which also gives the number of images
+/// generated. Any other images returned, like a grid of the batch or ControlNet's detected maps, are dropped.
+pub fn images_from_response(response: &Value) -> Result<Vec<Base64Image>> {
+    let images = response["images"]
+        .as_array()
+        .ok_or(anyhow::anyhow!("Unable to get images."))?;
+    let seeds: Vec<i64> = response["info"]
+        .as_str()
+        .and_then(|info| serde_json::from_str::<Value>(info).ok())
+        .and_then(|info| {
+            info["all_seeds"]
+                .as_array()
+                .map(|seeds| seeds.iter().filter_map(Value::as_i64).collect())
+        })
+        .unwrap_or_default();
+    let count = if seeds.is_empty() {
+        images.len()
+    } else {
+        seeds.len()
+    };
+    images
+        .iter()
+        .skip(leading_grids(images.len(), count))
+        .take(count)
+        .enumerate()
+        .map(|(index, image)| {
+            image
+                .as_str()
+                .ok_or(anyhow::anyhow!("Unable to get image."))
+                .map(|image| Base64Image {
+                    image: image.to_string(),
+                    seed: seeds.get(index).copied(),
+                })
+        })
+        .collect::<Result<Vec<Base64Image>>>()
+}
+
+/// The number of images returned before the `count` generated images. A grid of the whole batch comes first, but
+/// only for batches of more than one image. Extensions like ControlNet add their images after the generated ones.
+pub fn leading_grids(returned: usize, count: usize) -> usize {
+    if count > 1 && returned > count {
+        1
+    } else {
+        0
     }
 }
 
@@ -79,7 +220,8 @@ mod tests {
             width: 1024,
             height: 1024,
             sampler_name: "DPM++ 2M".to_string(),
-            cfg_scale: 2,
+            cfg_scale: 2.0,
+            ..Default::default()
         };
         let received_images = provider.post_txt2img(&request).await.unwrap();
         // Assert that we get one image.
@@ -91,4 +233,58 @@ mod tests {
         assert_eq!(image.width(), 1024);
         assert_eq!(image.height(), 1024);
     }
+
+    #[test]
+    fn test_override_settings_from_params() -> Result<()> {
+        assert_eq!(OverrideSettings::from_params(&ImageParams::default()), None);
+
+        // The typed parameters replace the same settings given by name.
+        let params = ImageParams {
+            clip_skip: Some(2),
+            override_settings: [
+                ("CLIP_stop_at_last_layers".to_string(), Value::from(1)),
+                ("sd_vae".to_string(), Value::from("other.safetensors")),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let settings = serde_json::to_value(OverrideSettings::from_params(&params))?;
+        assert_eq!(
+            settings,
+            serde_json::json!({ "CLIP_stop_at_last_layers": 2, "sd_vae": "other.safetensors" })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_images_from_response_seeds() -> Result<()> {
+        let info = serde_json::json!({ "seed": 10, "all_seeds": [10, 11] }).to_string();
+        let response = serde_json::json!({
+            "images": ["grid", "first", "second"],
+            "info": info,
+        });
+        let images = images_from_response(&response)?;
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].image, "first");
+        assert_eq!(images[0].seed, Some(10));
+        assert_eq!(images[1].seed, Some(11));
+
+        // Images added after the generated ones, like ControlNet's detected maps, are dropped.
+        let info = serde_json::json!({ "seed": 10, "all_seeds": [10] }).to_string();
+        let response = serde_json::json!({
+            "images": ["first", "detected map"],
+            "info": info,
+        });
+        let images = images_from_response(&response)?;
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].image, "first");
+        assert_eq!(images[0].seed, Some(10));
+
+        // Without any seeds, every image is kept.
+        let response = serde_json::json!({ "images": ["first", "second"] });
+        let images = images_from_response(&response)?;
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].seed, None);
+        Ok(())
+    }
 }
@@ -1,7 +1,11 @@
 //! Generate images with a local Stable Diffusion instance.
 
 mod api;
+mod pending;
 mod provider;
 
-use super::{Base64Image, ImageParams, ImageProvider};
-pub use provider::StableDiffusionXLProvider;
+use super::{Base64Image, ImageParams, ImageProvider, InitImage, InpaintParams, Upscale};
+pub use api::extra_networks::{StableDiffusionEmbeddings, StableDiffusionLora};
+pub use api::model::StableDiffusionModel;
+pub use pending::PendingTask;
+pub use provider::{StableDiffusionMode, StableDiffusionXLProvider};
@@ -0,0 +1,126 @@
+//! Remember queued tasks until their images are collected, so they can be resumed after the process exits.
+use super::ImageParams;
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The file queued tasks are saved to in the output directory, unless `state_file` is set.
+pub const DEFAULT_STATE_FILE: &str = ".ai_images-tasks.json";
+
+/// A task in the agent-scheduler queue whose images haven't been collected yet.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PendingTask {
+    /// The agent-scheduler's ID for the task.
+    pub task_id: String,
+    /// The URL of the instance the task was queued on.
+    pub url: String,
+    /// `txt2img` or `img2img`.
+    pub request_type: String,
+    /// When the task was queued, as a Unix timestamp.
+    pub queued_at: i64,
+    /// The number of images requested. Any extra results are grids of the batch and are dropped.
+    pub count: usize,
+    /// The parameters the task was queued with, used to save its images.
+    pub params: ImageParams,
+    /// The `task_context` of the parameters, which isn't saved with them.
+    #[serde(default)]
+    pub context: Value,
+    /// The width and height that were asked for, which the images are fitted to. The `params` have the size sent to
+    /// the instance, rounded up to a multiple of 8. Tasks saved without it use that size instead.
+    #[serde(default)]
+    pub requested_size: Option<(u32, u32)>,
+}
+
+/// Stops two tasks of the same process from changing a state file at the same time.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Read the tasks saved in a state file. A file that doesn't exist has no tasks.
+pub fn read_tasks(path: &Path) -> Result<Vec<PendingTask>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| {
+            Error::msg(format!(
+                "Unable to read the queued tasks in {:?}: {}",
+                path, e
+            ))
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save a task that was just queued.
+pub fn add_task(path: &Path, task: &PendingTask) -> Result<()> {
+    update_tasks(path, |tasks| tasks.push(task.clone()))
+}
+
+/// Forget the task queued on the instance at `url` once it is finished, and its images, if any, are saved.
+pub fn remove_task(path: &Path, url: &str, task_id: &str) -> Result<()> {
+    update_tasks(path, |tasks| {
+        tasks.retain(|saved| saved.task_id != task_id || saved.url != url)
+    })
+}
+
+/// Change the tasks saved in a state file. The file is replaced in one step, so it is never left half-written,
+/// and it is removed once there are no tasks left in it.
+fn update_tasks(path: &Path, update: impl FnOnce(&mut Vec<PendingTask>)) -> Result<()> {
+    let _lock = STATE_LOCK
+        .lock()
+        .map_err(|_| Error::msg("The lock on the queued tasks was poisoned."))?;
+    let mut tasks = read_tasks(path)?;
+    update(&mut tasks);
+    if tasks.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    if let Some(directory) = path.parent()
+        && !directory.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(directory)?;
+    }
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_string_pretty(&tasks)?)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn task(task_id: &str) -> PendingTask {
+        PendingTask {
+            task_id: task_id.to_string(),
+            url: "http://localhost:7860".to_string(),
+            request_type: "txt2img".to_string(),
+            queued_at: 1735689600,
+            count: 1,
+            params: ImageParams::default(),
+            context: serde_json::json!({ "name": "Goblin" }),
+            requested_size: Some((500, 750)),
+        }
+    }
+
+    #[test]
+    fn test_add_and_remove_tasks() -> Result<()> {
+        let directory = tempdir()?;
+        let path = directory.path().join("state").join(DEFAULT_STATE_FILE);
+        assert!(read_tasks(&path)?.is_empty());
+
+        add_task(&path, &task("t1"))?;
+        add_task(&path, &task("t2"))?;
+        assert_eq!(read_tasks(&path)?, vec![task("t1"), task("t2")]);
+
+        remove_task(&path, "http://localhost:7860", "t1")?;
+        assert_eq!(read_tasks(&path)?, vec![task("t2")]);
+        // The file is removed with the last task.
+        remove_task(&path, "http://localhost:7860", "t2")?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
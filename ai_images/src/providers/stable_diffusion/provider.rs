@@ -1,75 +1,276 @@
-use super::{api, Base64Image, ImageParams, ImageProvider};
+use super::pending::{self, PendingTask};
+use super::{api, Base64Image, ImageParams, ImageProvider, InitImage, InpaintParams, Upscale};
+use crate::cancel::CancellationToken;
+use crate::images::{decode_images, ImageData};
+use crate::progress::Progress;
+use crate::providers::seconds;
 use anyhow::Result;
 use async_trait::async_trait;
-use chrono::Utc;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// A provider for generating images with a local Stable Diffusion instance.
 #[derive(Args, Deserialize, Debug, Serialize)]
 pub struct StableDiffusionXLProvider {
     /// The URL for the local Stable Diffusion instance.
     pub url: String,
+
+    /// How requests are sent to the instance. Defaults to `auto`.
+    #[clap(long = "sd-mode", value_enum)]
+    pub mode: Option<StableDiffusionMode>,
+
+    /// How often a queued task is checked on, in seconds. Defaults to 1.
+    #[clap(long = "sd-poll-interval")]
+    pub poll_interval: Option<f32>,
+
+    /// How long to wait for a queued task before giving up on it, in seconds. Defaults to 300.
+    /// The task is kept in the state file, so its images can still be collected with `resume`.
+    #[clap(long = "sd-task-timeout")]
+    pub task_timeout: Option<f32>,
+
+    /// Where queued tasks are saved until their images are collected.
+    /// Defaults to `.ai_images-tasks.json` in the output directory.
+    #[clap(long = "sd-state-file")]
+    pub state_file: Option<PathBuf>,
 }
 
 impl Default for StableDiffusionXLProvider {
     fn default() -> Self {
         Self {
             url: "http://localhost:7860".to_string(),
+            mode: None,
+            poll_interval: None,
+            task_timeout: None,
+            state_file: None,
         }
     }
 }
 
+/// How requests are sent to the Stable Diffusion instance. Both modes save the same images with the same seeds.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StableDiffusionMode {
+    /// Send requests to `/sdapi/v1/txt2img` and `/sdapi/v1/img2img` and wait for the response.
+    Direct,
+    /// Add requests to the queue of the agent-scheduler extension and poll them until they finish.
+    Queue,
+    /// Use the queue if the agent-scheduler extension is installed, and send requests directly otherwise.
+    #[default]
+    Auto,
+}
+
 #[async_trait]
 impl ImageProvider for StableDiffusionXLProvider {
     /// Generate an image using the local Stable Diffusion instance.
-    async fn text_to_image(&self, params: ImageParams) -> Result<PathBuf> {
-        // Check if the local Stable Diffusion instance is available.
-        let is_up: bool = self.is_up().await?;
-        if !is_up {
+    async fn text_to_image(
+        &self,
+        params: ImageParams,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let (mode, params) = cancel.run_until_cancelled(self.prepare(&params)).await?;
+        let params = params.with_extra_networks();
+        if mode == StableDiffusionMode::Queue {
+            return self.queue_txt2img(&params, progress, cancel).await;
+        }
+
+        let request_body = txt2img_request_body(&params)?;
+        let request = self.with_progress(progress, self.post_txt2img(&request_body));
+        let images: Vec<Base64Image> = self.interruptible(cancel, request).await?;
+        decode_images(&images)
+    }
+
+    /// Generate an image from an init image using the local Stable Diffusion instance.
+    async fn image_to_image(
+        &self,
+        params: ImageParams,
+        init_image: InitImage,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let (mode, params) = cancel.run_until_cancelled(self.prepare(&params)).await?;
+        let params = params.with_extra_networks();
+        if mode == StableDiffusionMode::Queue {
+            return self
+                .queue_img2img(&params, &init_image, progress, cancel)
+                .await;
+        }
+
+        let request_body = api::img2img::Img2ImgRequestBody {
+            init_images: vec![init_image.to_base64()?],
+            denoising_strength: init_image.denoising_strength,
+            resize_mode: init_image.resize_mode.api_value(),
+            txt2img: img2img_txt2img_body(&params)?,
+            ..Default::default()
+        };
+
+        // Send the request.
+        let request = self.with_progress(progress, self.post_img2img(&request_body));
+        let images: Vec<Base64Image> = self.interruptible(cancel, request).await?;
+        decode_images(&images)
+    }
+
+    /// Repaint the masked part of an image using the local Stable Diffusion instance.
+    async fn inpaint(
+        &self,
+        params: ImageParams,
+        inpaint: InpaintParams,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ImageData>> {
+        let (mode, params) = cancel.run_until_cancelled(self.prepare(&params)).await?;
+        let params = params.with_extra_networks();
+        if mode == StableDiffusionMode::Queue {
+            return self
+                .queue_inpaint(&params, &inpaint, progress, cancel)
+                .await;
+        }
+
+        let (image, mask) = api::queue::inpaint_images(&inpaint)?;
+        let request_body = api::img2img::Img2ImgRequestBody {
+            init_images: vec![image],
+            denoising_strength: inpaint.denoising_strength,
+            resize_mode: 0,
+            mask: Some(mask),
+            inpainting_fill: Some(inpaint.inpainting_fill.api_value()),
+            mask_blur: Some(inpaint.mask_blur),
+            txt2img: img2img_txt2img_body(&params)?,
+        };
+
+        // Send the request.
+        let request = self.with_progress(progress, self.post_img2img(&request_body));
+        let images: Vec<Base64Image> = self.interruptible(cancel, request).await?;
+        decode_images(&images)
+    }
+
+    /// Upscale an image with one of the instance's upscalers.
+    async fn upscale(&self, image: &ImageData, upscale: &Upscale) -> Result<ImageData> {
+        let request = api::extras::ExtraSingleImageRequestBody::new(image.to_base64(), upscale);
+        self.post_extra_single_image(&request).await?.decode()
+    }
+}
+
+/// Build a txt2img request. Fails if a ControlNet image can't be read.
+fn txt2img_request_body(params: &ImageParams) -> Result<api::txt2img::Txt2ImgRequestBody> {
+    Ok(api::txt2img::Txt2ImgRequestBody {
+        prompt: params.prompt.to_string(),
+        negative_prompt: params.prompt.negative.clone().unwrap_or_default(),
+        steps: params.steps,
+        batch_size: params.batch_size.unwrap_or(1),
+        width: params.width,
+        height: params.height,
+        sampler_name: params.sampler_name.clone(),
+        cfg_scale: params.cfg_scale,
+        scheduler: params.scheduler.clone(),
+        tiling: params.tiling.then_some(true),
+        restore_faces: params.restore_faces.then_some(true),
+        seed: params.seed,
+        subseed: params.subseed,
+        subseed_strength: params.subseed_strength,
+        n_iter: params.n_iter,
+        hires_fix: api::txt2img::HiresFix::from_params(params),
+        alwayson_scripts: api::controlnet::AlwaysOnScripts::from_params(params)?,
+        override_settings: api::txt2img::OverrideSettings::from_params(params),
+    })
+}
+
+/// The txt2img part of an img2img request. The hires fix only applies to txt2img, so it is left out.
+fn img2img_txt2img_body(params: &ImageParams) -> Result<api::txt2img::Txt2ImgRequestBody> {
+    Ok(api::txt2img::Txt2ImgRequestBody {
+        hires_fix: None,
+        ..txt2img_request_body(params)?
+    })
+}
+
+impl StableDiffusionXLProvider {
+    /// Pick the mode to send requests with. `auto` uses the queue if the agent-scheduler extension responds.
+    pub async fn resolve_mode(&self) -> Result<StableDiffusionMode> {
+        match self.mode.unwrap_or_default() {
+            StableDiffusionMode::Direct => Ok(StableDiffusionMode::Direct),
+            StableDiffusionMode::Queue => match self.has_agent_scheduler().await {
+                true => Ok(StableDiffusionMode::Queue),
+                false => Err(anyhow::anyhow!(
+                    "The agent-scheduler extension is not installed on the Stable Diffusion instance. Install it or set `mode = \"direct\"`."
+                )),
+            },
+            StableDiffusionMode::Auto => match self.has_agent_scheduler().await {
+                true => Ok(StableDiffusionMode::Queue),
+                false => Ok(StableDiffusionMode::Direct),
+            },
+        }
+    }
+
+    /// Check that the local Stable Diffusion instance is available and pick the mode to send requests with.
+    /// The requested model is resolved to the title the instance lists it by, which both modes send.
+    /// In direct mode, the model is loaded first. The queue loads it with each task instead.
+    /// The requested LoRAs, embeddings, and ControlNet units are then checked in both modes.
+    async fn prepare(&self, params: &ImageParams) -> Result<(StableDiffusionMode, ImageParams)> {
+        let mode = self.resolve_mode().await?;
+        if mode == StableDiffusionMode::Direct && !self.is_up().await? {
             return Err(anyhow::anyhow!(
                 "Local Stable Diffusion instance is not available."
             ));
         }
+        let mut params = params.clone();
+        if let Some(model) = &params.model {
+            params.model = Some(self.resolve_model(model).await?.title);
+        }
+        if mode == StableDiffusionMode::Direct {
+            self.load_model(&params).await?;
+        }
+        self.validate_extra_networks(&params).await?;
+        self.validate_controlnet(&params).await?;
+        Ok((mode, params))
+    }
 
+    /// Load the requested model if it isn't loaded already.
+    async fn load_model(&self, params: &ImageParams) -> Result<()> {
         // Check what model is currently loaded.
         let model_name: String = self.get_model_name().await?;
 
         // Check if the currently loaded model matches the requested model.
-        if let Some(model) = &params.model {
-            if model_name != model.as_str() {
-                self.set_model(model).await?;
-            }
+        if let Some(model) = &params.model
+            && model_name != model.as_str()
+        {
+            self.set_model(model).await?;
         }
+        Ok(())
+    }
 
-        // Select the appropriate request body based on the model.
-        // If a Flux model is loaded, return an error.
-        let request_body = api::txt2img::Txt2ImgRequestBody {
-            prompt: params.prompt.to_string(),
-            negative_prompt: params.prompt.negative.unwrap_or_default(),
-            steps: params.steps,
-            batch_size: 1,
-            width: params.width,
-            height: params.height,
-            sampler_name: params.sampler_name,
-            cfg_scale: params.cfg_scale,
-        };
+    /// The tasks queued on this instance whose images haven't been collected yet, oldest first.
+    /// Without a `state_file`, they are read from the default state file in `output_directory`.
+    pub fn pending_tasks(&self, output_directory: &Path) -> Result<Vec<PendingTask>> {
+        let url = self.get_url();
+        Ok(pending::read_tasks(&self.state_file(output_directory))?
+            .into_iter()
+            .filter(|task| task.url == url)
+            .collect())
+    }
 
-        // Send the request.
-        let images: Vec<Base64Image> = self.post_txt2img(&request_body).await?;
+    /// Remove a task queued on this instance from the state file, once its images are saved.
+    pub(crate) fn forget_task(&self, output_directory: &Path, task_id: &str) -> Result<()> {
+        pending::remove_task(&self.state_file(output_directory), &self.get_url(), task_id)
+    }
 
-        // Build the output path for the image.
-        let timestamp = Utc::now().timestamp().to_string();
-        let output_path = params.output_directory.join(format!("{}.png", timestamp));
+    /// The file queued tasks are saved to until their images are collected.
+    pub fn state_file(&self, output_directory: &Path) -> PathBuf {
+        self.state_file
+            .clone()
+            .unwrap_or_else(|| output_directory.join(pending::DEFAULT_STATE_FILE))
+    }
 
-        // Convert the base64-encoded image to a PNG file.
-        images[0].to_file(&output_path)?;
-        Ok(output_path)
+    /// How often a queued task is checked on.
+    pub(crate) fn poll_interval(&self) -> Result<Duration> {
+        seconds("poll_interval", self.poll_interval.unwrap_or(1.0))
+    }
+
+    /// How long to wait for a queued task before giving up on it.
+    pub(crate) fn task_timeout(&self) -> Result<Duration> {
+        seconds("task_timeout", self.task_timeout.unwrap_or(300.0))
     }
-}
 
-impl StableDiffusionXLProvider {
     /// Get the sanitized URL for the local Stable Diffusion instance.
     /// The URL should not have a trailing slash.
     pub fn get_url(&self) -> String {
@@ -83,15 +284,51 @@ mod tests {
     use super::*;
     use anyhow::Error;
 
+    #[test]
+    fn test_poll_settings() -> Result<()> {
+        let provider = StableDiffusionXLProvider {
+            poll_interval: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(provider.poll_interval()?, Duration::from_millis(500));
+        assert_eq!(provider.task_timeout()?, Duration::from_secs(300));
+        let provider = StableDiffusionXLProvider {
+            task_timeout: Some(0.0),
+            ..Default::default()
+        };
+        assert!(provider.task_timeout().is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_generate_image() -> Result<()> {
         let params = ImageParams::default();
         let provider = StableDiffusionXLProvider::default();
-        let image_path = provider.text_to_image(params).await?;
+        let images = provider
+            .text_to_image(params, &Progress::default(), &CancellationToken::default())
+            .await?;
         // Check if the image was generated.
-        assert!(image_path.exists());
-        // Clean up the test output.
-        std::fs::remove_file(image_path)?;
+        assert_eq!(images.len(), 1);
+        assert!(!images[0].bytes.is_empty());
+        assert!(images[0].seed.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generate_variants() -> Result<()> {
+        let params = ImageParams {
+            seed: Some(1234),
+            batch_size: Some(2),
+            n_iter: Some(2),
+            ..Default::default()
+        };
+        let provider = StableDiffusionXLProvider::default();
+        let images = provider
+            .text_to_image(params, &Progress::default(), &CancellationToken::default())
+            .await?;
+        // Every variant is returned, starting from the requested seed.
+        assert_eq!(images.len(), 4);
+        assert_eq!(images[0].seed, Some(1234));
         Ok(())
     }
 
@@ -102,12 +339,11 @@ mod tests {
         params.prompt.base = "a cat".to_string();
         let provider = StableDiffusionXLProvider::default();
         // Generate the image.
-        let image_path = provider.text_to_image(params).await?;
-        // Check if the image was generated.
-        assert!(image_path.exists());
+        let images = provider
+            .text_to_image(params, &Progress::default(), &CancellationToken::default())
+            .await?;
         // Check the EXIF data for the image to see if the prompt was added.
-        let file = std::fs::File::open(&image_path)?;
-        let mut bufreader = std::io::BufReader::new(file);
+        let mut bufreader = std::io::Cursor::new(&images[0].bytes);
         let exif_data = exif::Reader::new()
             .continue_on_error(true)
             .read_from_container(&mut bufreader)
@@ -144,8 +380,6 @@ mod tests {
                 )));
             }
         }
-        // Clean up the test output.
-        std::fs::remove_file(image_path)?;
         Ok(())
     }
 }
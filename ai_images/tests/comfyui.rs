@@ -0,0 +1,187 @@
+use ai_images::providers::{ComfyUiInputs, ComfyUiProvider};
+use ai_images::{ImageMetadata, ImageParams, ImageProviders, Prompt};
+use anyhow::Result;
+use serde_json::{from_str, json, Value};
+use std::path::PathBuf;
+use test_support::StandInServer;
+
+const PROMPT: &str = r#"{"prompt_id": "f3a1", "number": 0, "node_errors": {}}"#;
+const HISTORY: &str = r#"{"f3a1": {
+    "outputs": {"9": {"images": [{"filename": "ComfyUI_00001_.png", "subfolder": "", "type": "output"}]}},
+    "status": {"status_str": "success", "completed": true, "messages": []}
+}}"#;
+
+/// A text-to-image workflow like ComfyUI's default one.
+fn write_workflow(name: &str) -> Result<PathBuf> {
+    let workflow = json!({
+        "3": {
+            "class_type": "KSampler",
+            "inputs": {
+                "seed": 0, "steps": 20, "cfg": 8, "sampler_name": "euler", "scheduler": "normal", "denoise": 1,
+                "model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]
+            }
+        },
+        "4": { "class_type": "CheckpointLoaderSimple", "inputs": { "ckpt_name": "sd_xl_base_1.0.safetensors" } },
+        "5": { "class_type": "EmptyLatentImage", "inputs": { "width": 1024, "height": 1024, "batch_size": 1 } },
+        "6": { "class_type": "CLIPTextEncode", "inputs": { "text": "", "clip": ["4", 1] } },
+        "7": { "class_type": "CLIPTextEncode", "inputs": { "text": "", "clip": ["4", 1] } },
+        "8": { "class_type": "VAEDecode", "inputs": { "samples": ["3", 0], "vae": ["4", 2] } },
+        "9": { "class_type": "SaveImage", "inputs": { "filename_prefix": "ComfyUI", "images": ["8", 0] } }
+    });
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, workflow.to_string())?;
+    Ok(path)
+}
+
+fn png(width: u32, height: u32) -> Result<Vec<u8>> {
+    let image = image::RgbaImage::from_pixel(width, height, image::Rgba([0, 128, 255, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+fn output_directory(name: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+#[tokio::test]
+async fn test_comfyui_text_to_image() -> Result<()> {
+    let server = StandInServer::start(vec![
+        ("/prompt", 200, PROMPT.as_bytes().to_vec()),
+        ("/history/f3a1", 200, HISTORY.as_bytes().to_vec()),
+        ("/view", 200, png(64, 64)?),
+    ])?;
+    let provider = ImageProviders::ComfyUi(ComfyUiProvider {
+        url: server.url.clone(),
+        workflow: write_workflow("test_comfyui_text_to_image.json")?,
+        inputs: ComfyUiInputs::default(),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        prompt: Prompt {
+            base: "A castle on a hill".to_string(),
+            negative: Some("blurry".to_string()),
+            ..Default::default()
+        },
+        output_directory: output_directory("test_comfyui_text_to_image")?,
+        width: 64,
+        height: 64,
+        seed: Some(42),
+        ..Default::default()
+    };
+
+    let images = provider.generate_image(params).await?;
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].seed, Some(42));
+    assert_eq!(image::image_dimensions(&images[0].path)?, (64, 64));
+
+    // Check that the parameters were saved in the image.
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(metadata.provider, Some("ComfyUi".to_string()));
+    assert_eq!(metadata.sampler_name, None);
+    let saved = ImageParams::from_image(&images[0].path)?;
+    assert_eq!(saved.prompt.base, "A castle on a hill");
+    assert_eq!(saved.prompt.negative, Some("blurry".to_string()));
+    assert_eq!(saved.seed, Some(42));
+
+    // Check that the parameters were written into the workflow.
+    let requests = server.requests.lock().unwrap().clone();
+    let workflow: Value = from_str(&requests[0].body)?;
+    let workflow = &workflow["prompt"];
+    assert_eq!(workflow["6"]["inputs"]["text"], "A castle on a hill");
+    assert_eq!(workflow["7"]["inputs"]["text"], "blurry");
+    assert_eq!(workflow["5"]["inputs"]["width"], 64);
+    assert_eq!(workflow["3"]["inputs"]["seed"], 42);
+    assert_eq!(workflow["3"]["inputs"]["steps"], 15);
+    let download = requests.last().unwrap();
+    assert!(download
+        .path
+        .starts_with("/view?filename=ComfyUI_00001_.png"));
+    assert!(download.path.contains("type=output"));
+
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_comfyui_rejected_workflow() -> Result<()> {
+    let error = r#"{"error": {"type": "prompt_outputs_failed_validation", "message": "Prompt outputs failed validation"},
+        "node_errors": {"4": {"errors": [{"message": "Value not in list", "details": "ckpt_name: 'missing.safetensors' not in []"}]}}}"#;
+    let server = StandInServer::start(vec![("/prompt", 400, error.as_bytes().to_vec())])?;
+    let provider = ImageProviders::ComfyUi(ComfyUiProvider {
+        url: server.url.clone(),
+        workflow: write_workflow("test_comfyui_rejected_workflow.json")?,
+        inputs: ComfyUiInputs::default(),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        model: Some("missing.safetensors".to_string()),
+        output_directory: output_directory("test_comfyui_rejected_workflow")?,
+        ..Default::default()
+    };
+
+    let error = provider.generate_image(params).await.unwrap_err();
+    assert!(error.to_string().contains("missing.safetensors"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_comfyui_failed_workflow() -> Result<()> {
+    let history = r#"{"f3a1": {"outputs": {}, "status": {"status_str": "error", "completed": false,
+        "messages": [["execution_error", {"exception_message": "CUDA out of memory"}]]}}}"#;
+    let server = StandInServer::start(vec![
+        ("/prompt", 200, PROMPT.as_bytes().to_vec()),
+        ("/history/f3a1", 200, history.as_bytes().to_vec()),
+    ])?;
+    let provider = ImageProviders::ComfyUi(ComfyUiProvider {
+        url: server.url.clone(),
+        workflow: write_workflow("test_comfyui_failed_workflow.json")?,
+        inputs: ComfyUiInputs::default(),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_comfyui_failed_workflow")?,
+        ..Default::default()
+    };
+
+    let error = provider.generate_image(params).await.unwrap_err();
+    assert!(error.to_string().contains("CUDA out of memory"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_comfyui_timeout() -> Result<()> {
+    // The workflow is still running, so the history is empty.
+    let server = StandInServer::start(vec![
+        ("/prompt", 200, PROMPT.as_bytes().to_vec()),
+        ("/history/f3a1", 200, b"{}".to_vec()),
+    ])?;
+    let provider = ImageProviders::ComfyUi(ComfyUiProvider {
+        url: server.url.clone(),
+        workflow: write_workflow("test_comfyui_timeout.json")?,
+        poll_interval: Some(0.05),
+        task_timeout: Some(0.5),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_comfyui_timeout")?,
+        ..Default::default()
+    };
+
+    let error = provider.generate_image(params).await.unwrap_err();
+    assert!(
+        error.to_string().contains("timed out after 0.5 seconds"),
+        "{}",
+        error
+    );
+    // The history was checked again at the configured interval, rather than only once a second.
+    let requests = server.requests.lock().unwrap().clone();
+    let polls = requests
+        .iter()
+        .filter(|request| request.path == "/history/f3a1")
+        .count();
+    assert!(polls >= 2, "{} polls", polls);
+    Ok(())
+}
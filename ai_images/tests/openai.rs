@@ -0,0 +1,231 @@
+use ai_images::providers::{OpenAiImageModel, OpenAiImageOptions, OpenAiProvider};
+use ai_images::{ImageMetadata, ImageParams, ImageProviders, Prompt};
+use anyhow::Result;
+use api_keys::ApiKeySource;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use test_support::StandInServer;
+
+const GENERATIONS: &str = "/v1/images/generations";
+
+fn generated() -> Result<Vec<u8>> {
+    let image = image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 128, 255, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    let response = json!({
+        "created": 1735689600,
+        "data": [{
+            "b64_json": base64::prelude::BASE64_STANDARD.encode(bytes.into_inner()),
+            "revised_prompt": "A stone castle on a green hill"
+        }]
+    });
+    Ok(response.to_string().into_bytes())
+}
+
+fn output_directory(name: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+fn params(name: &str) -> Result<ImageParams> {
+    Ok(ImageParams {
+        prompt: Prompt {
+            base: "A castle on a hill".to_string(),
+            ..Default::default()
+        },
+        output_directory: output_directory(name)?,
+        width: 64,
+        height: 64,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_openai_base_url_and_headers() -> Result<()> {
+    let server = StandInServer::start(vec![(GENERATIONS, 200, generated()?)])?;
+    let api_key_file = output_directory("test_openai_base_url_and_headers")?.join("key.txt");
+    std::fs::write(&api_key_file, "sk-openai-test")?;
+    let provider = ImageProviders::OpenAi(OpenAiProvider {
+        api_key: ApiKeySource {
+            api_key_file: Some(api_key_file),
+            ..Default::default()
+        },
+        base_url: Some(format!("{}/v1/", server.url)),
+        organization: Some("org-test".to_string()),
+        project: Some("proj-test".to_string()),
+        options: OpenAiImageOptions {
+            model: Some(OpenAiImageModel::DallE3),
+            model_name: Some("dalle3-deployment".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let images = provider
+        .generate_image(params("test_openai_base_url_and_headers")?)
+        .await?;
+    assert_eq!(images.len(), 1);
+    assert_eq!(
+        images[0].revised_prompt.as_deref(),
+        Some("A stone castle on a green hill")
+    );
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(metadata.provider, Some("OpenAi".to_string()));
+    assert_eq!(metadata.model, Some("dalle3-deployment".to_string()));
+
+    // Check that the request went to the base URL with the headers and the model name.
+    let requests = server.requests.lock().unwrap().clone();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, GENERATIONS);
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some("Bearer sk-openai-test")
+    );
+    assert_eq!(requests[0].header("openai-organization"), Some("org-test"));
+    assert_eq!(requests[0].header("openai-project"), Some("proj-test"));
+    let body: Value = serde_json::from_str(&requests[0].body)?;
+    assert_eq!(body["model"], "dalle3-deployment");
+    assert_eq!(body["prompt"], "A castle on a hill");
+    assert_eq!(body["size"], "1024x1024");
+
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_openai_base_url_without_api_key() -> Result<()> {
+    let server = StandInServer::start(vec![(GENERATIONS, 200, generated()?)])?;
+    let api_key = ApiKeySource {
+        api_key_env: Some("AI_IMAGES_TEST_UNSET_OPENAI_KEY".to_string()),
+        ..Default::default()
+    };
+
+    // A local server doesn't need an API key.
+    let local = ImageProviders::OpenAi(OpenAiProvider {
+        api_key: api_key.clone(),
+        base_url: Some(format!("{}/v1", server.url)),
+        ..Default::default()
+    });
+    let images = local
+        .generate_image(params("test_openai_base_url_without_api_key")?)
+        .await?;
+    std::fs::remove_file(&images[0].path)?;
+
+    // OpenAI itself does.
+    let openai = ImageProviders::OpenAi(OpenAiProvider {
+        api_key,
+        ..Default::default()
+    });
+    assert!(openai
+        .generate_image(params("test_openai_base_url_without_api_key")?)
+        .await
+        .is_err());
+
+    // Header values that can't be sent are rejected before the request.
+    let invalid = ImageProviders::OpenAi(OpenAiProvider {
+        base_url: Some(format!("{}/v1", server.url)),
+        organization: Some("org\ntest".to_string()),
+        ..Default::default()
+    });
+    assert!(invalid
+        .generate_image(params("test_openai_base_url_without_api_key")?)
+        .await
+        .is_err());
+    assert_eq!(server.requests.lock().unwrap().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_openai_azure_deployment() -> Result<()> {
+    const AZURE_GENERATIONS: &str = "/openai/deployments/dalle3/images/generations";
+    let server = StandInServer::start(vec![(AZURE_GENERATIONS, 200, generated()?)])?;
+    let api_key_file = output_directory("test_openai_azure_deployment")?.join("key.txt");
+    std::fs::write(&api_key_file, "azure-test-key")?;
+    let provider = ImageProviders::OpenAi(OpenAiProvider {
+        api_key: ApiKeySource {
+            api_key_file: Some(api_key_file),
+            ..Default::default()
+        },
+        base_url: Some(server.url.clone()),
+        deployment: Some("dalle3".to_string()),
+        ..Default::default()
+    });
+
+    let images = provider
+        .generate_image(params("test_openai_azure_deployment")?)
+        .await?;
+    assert_eq!(images.len(), 1);
+
+    // Azure addresses the deployment in the path, and takes the key in its own header.
+    let requests = server.requests.lock().unwrap().clone();
+    assert_eq!(
+        requests[0].path,
+        format!("{}?api-version=2024-10-21", AZURE_GENERATIONS)
+    );
+    assert_eq!(requests[0].header("api-key"), Some("azure-test-key"));
+    assert_eq!(requests[0].header("authorization"), None);
+
+    // A deployment needs the resource's endpoint.
+    let missing_url = ImageProviders::OpenAi(OpenAiProvider {
+        deployment: Some("dalle3".to_string()),
+        ..Default::default()
+    });
+    assert!(missing_url
+        .generate_image(params("test_openai_azure_deployment")?)
+        .await
+        .is_err());
+
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[test]
+fn test_openai_limiter_key() {
+    // Instances behind different URLs don't share rate limits.
+    let openai = ImageProviders::OpenAi(OpenAiProvider::default());
+    let local = ImageProviders::OpenAi(OpenAiProvider {
+        base_url: Some("http://localhost:8080/v1/".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(
+        openai.limiter_key(),
+        "images/OpenAi/https://api.openai.com/v1"
+    );
+    assert_eq!(
+        local.limiter_key(),
+        "images/OpenAi/http://localhost:8080/v1"
+    );
+}
+
+#[test]
+fn test_openai_config() -> Result<()> {
+    let config: ai_images::cli::Provider = toml::from_str(
+        r#"
+        name = "OpenAi"
+        [config]
+        url = "http://localhost:8080/v1"
+        organization = "org-test"
+        project = "proj-test"
+        model = "gpt-image-1"
+        model_name = "local-image-model"
+        deployment = "gpt-image"
+        api_version = "2025-04-01-preview"
+        "#,
+    )?;
+    let ImageProviders::OpenAi(provider) = config.to_image_provider()? else {
+        unreachable!("the provider is OpenAI");
+    };
+    assert_eq!(
+        provider.base_url.as_deref(),
+        Some("http://localhost:8080/v1")
+    );
+    assert_eq!(provider.organization.as_deref(), Some("org-test"));
+    assert_eq!(provider.project.as_deref(), Some("proj-test"));
+    assert_eq!(provider.options.model(), OpenAiImageModel::GptImage1);
+    assert_eq!(provider.options.model_name(), "local-image-model");
+    assert_eq!(provider.deployment.as_deref(), Some("gpt-image"));
+    assert_eq!(provider.api_version.as_deref(), Some("2025-04-01-preview"));
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use ai_images::providers::{
+    StabilityAiEndpoint, StabilityAiFormat, StabilityAiOptions, StabilityAiProvider,
+};
+use ai_images::{ImageMetadata, ImageParams, ImageProviders, Prompt};
+use anyhow::Result;
+use api_keys::ApiKeySource;
+use base64::Engine;
+use serde_json::json;
+use std::path::PathBuf;
+use test_support::StandInServer;
+
+const GENERATE_CORE: &str = "/v2beta/stable-image/generate/core";
+const GENERATE_SD3: &str = "/v2beta/stable-image/generate/sd3";
+
+fn png_base64() -> Result<String> {
+    let image = image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 128, 255, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    Ok(base64::prelude::BASE64_STANDARD.encode(bytes.into_inner()))
+}
+
+fn generated(finish_reason: &str) -> Result<Vec<u8>> {
+    let response = json!({ "image": png_base64()?, "finish_reason": finish_reason, "seed": 42 });
+    Ok(response.to_string().into_bytes())
+}
+
+fn output_directory(name: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+/// A provider for the stand-in server, with the API key read from a file.
+fn provider(url: &str, name: &str, options: StabilityAiOptions) -> Result<ImageProviders> {
+    let api_key_file = output_directory(name)?.join("key.txt");
+    std::fs::write(&api_key_file, "sk-stability-test")?;
+    Ok(ImageProviders::StabilityAi(StabilityAiProvider {
+        url: url.to_string(),
+        api_key: ApiKeySource {
+            api_key_file: Some(api_key_file),
+            ..Default::default()
+        },
+        options,
+    }))
+}
+
+#[tokio::test]
+async fn test_stability_ai_text_to_image() -> Result<()> {
+    let server = StandInServer::start(vec![(GENERATE_CORE, 200, generated("SUCCESS")?)])?;
+    let provider = provider(
+        &server.url,
+        "test_stability_ai_text_to_image",
+        StabilityAiOptions::default(),
+    )?;
+    let params = ImageParams {
+        prompt: Prompt {
+            base: "A castle on a hill".to_string(),
+            negative: Some("blurry".to_string()),
+            ..Default::default()
+        },
+        output_directory: output_directory("test_stability_ai_text_to_image")?,
+        width: 64,
+        height: 36,
+        seed: Some(7),
+        ..Default::default()
+    };
+
+    let images = provider.generate_image(params).await?;
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].seed, Some(42));
+    // The square image is cropped to the requested size.
+    assert_eq!(image::image_dimensions(&images[0].path)?, (64, 36));
+
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(metadata.provider, Some("StabilityAi".to_string()));
+    assert_eq!(metadata.model, Some("stable-image-core".to_string()));
+    assert_eq!(metadata.negative_prompt, Some("blurry".to_string()));
+
+    // Check that the request was sent as a form, with the key and the parameters.
+    let requests = server.requests.lock().unwrap().clone();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some("Bearer sk-stability-test")
+    );
+    assert_eq!(requests[0].header("accept"), Some("application/json"));
+    assert!(requests[0]
+        .header("content-type")
+        .unwrap()
+        .starts_with("multipart/form-data"));
+    let body = &requests[0].body;
+    for (name, value) in [
+        ("prompt", "A castle on a hill"),
+        ("negative_prompt", "blurry"),
+        ("aspect_ratio", "16:9"),
+        ("seed", "7"),
+        ("output_format", "png"),
+    ] {
+        let field = format!("name=\"{}\"\r\n\r\n{}\r\n", name, value);
+        assert!(body.contains(&field), "{:?} is missing {}", body, field);
+    }
+    assert!(!body.contains("name=\"model\""));
+
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stability_ai_sd3_model() -> Result<()> {
+    let server = StandInServer::start(vec![(GENERATE_SD3, 200, generated("SUCCESS")?)])?;
+    let provider = provider(
+        &server.url,
+        "test_stability_ai_sd3_model",
+        StabilityAiOptions {
+            endpoint: Some(StabilityAiEndpoint::Sd3),
+            output_format: Some(StabilityAiFormat::Jpeg),
+        },
+    )?;
+    let params = ImageParams {
+        model: Some("sd3.5-large-turbo".to_string()),
+        output_directory: output_directory("test_stability_ai_sd3_model")?,
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+
+    let images = provider.generate_image(params).await?;
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(metadata.model, Some("sd3.5-large-turbo".to_string()));
+
+    let requests = server.requests.lock().unwrap().clone();
+    let body = &requests[0].body;
+    assert!(body.contains("name=\"model\"\r\n\r\nsd3.5-large-turbo\r\n"));
+    assert!(body.contains("name=\"output_format\"\r\n\r\njpeg\r\n"));
+    // A random seed isn't sent, so Stability AI picks one.
+    assert!(!body.contains("name=\"seed\""));
+
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stability_ai_errors() -> Result<()> {
+    let rejected = json!({
+        "id": "a1b2", "name": "unauthorized", "errors": ["Invalid API key: sk-stability-test"]
+    });
+    let server = StandInServer::start(vec![
+        (GENERATE_CORE, 401, rejected.to_string().into_bytes()),
+        (GENERATE_SD3, 200, generated("CONTENT_FILTERED")?),
+    ])?;
+    let params = ImageParams {
+        output_directory: output_directory("test_stability_ai_errors")?,
+        ..Default::default()
+    };
+
+    // The API key is kept out of the error.
+    let core = provider(
+        &server.url,
+        "test_stability_ai_errors",
+        StabilityAiOptions::default(),
+    )?;
+    let error = core.generate_image(params.clone()).await.unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("401"));
+    assert!(message.contains("unauthorized: Invalid API key: [REDACTED]"));
+    assert!(!message.contains("sk-stability-test"));
+
+    let sd3 = provider(
+        &server.url,
+        "test_stability_ai_errors",
+        StabilityAiOptions {
+            endpoint: Some(StabilityAiEndpoint::Sd3),
+            ..Default::default()
+        },
+    )?;
+    let error = sd3.generate_image(params).await.unwrap_err();
+    assert!(error.to_string().contains("content filter"));
+    Ok(())
+}
+
+#[test]
+fn test_stability_ai_config() -> Result<()> {
+    let config: ai_images::cli::Provider = toml::from_str(
+        r#"
+        name = "StabilityAi"
+        [config]
+        endpoint = "ultra"
+        stability_output_format = "webp"
+        api_key_env = "MY_STABILITY_KEY"
+        "#,
+    )?;
+    let ImageProviders::StabilityAi(provider) = config.to_image_provider()? else {
+        unreachable!("the provider is Stability AI");
+    };
+    assert_eq!(provider.url, "https://api.stability.ai");
+    assert_eq!(provider.options.endpoint(), StabilityAiEndpoint::Ultra);
+    assert_eq!(provider.options.output_format(), StabilityAiFormat::Webp);
+    assert_eq!(
+        provider.api_key.api_key_env.as_deref(),
+        Some("MY_STABILITY_KEY")
+    );
+
+    // The sd3 endpoint can't return WebP images, which is caught when the configuration is loaded.
+    let config: ai_images::cli::Provider = toml::from_str(
+        r#"
+        name = "StabilityAi"
+        [config]
+        endpoint = "sd3"
+        stability_output_format = "webp"
+        "#,
+    )?;
+    assert!(config.to_image_provider().is_err());
+    Ok(())
+}
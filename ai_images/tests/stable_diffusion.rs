@@ -0,0 +1,1025 @@
+use ai_images::providers::{StableDiffusionMode, StableDiffusionXLProvider};
+use ai_images::{
+    CancellationToken, Cancelled, ControlNetUnit, GeneratedImage, ImageMetadata, ImageOperation,
+    ImageParams, ImageProviders, OutputFormat, Progress, ProgressEvent, Upscale, UpscaleParams,
+};
+use anyhow::Result;
+use base64::Engine;
+use serde_json::json;
+use std::path::PathBuf;
+use test_support::StandInServer;
+
+fn png_base64() -> Result<String> {
+    let image = image::RgbaImage::from_pixel(64, 64, image::Rgba([255, 128, 0, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    Ok(base64::prelude::BASE64_STANDARD.encode(bytes.into_inner()))
+}
+
+/// Routes for an instance without the agent-scheduler extension.
+fn direct_routes() -> Result<Vec<(&'static str, u16, Vec<u8>)>> {
+    let txt2img = json!({
+        "images": [png_base64()?],
+        "info": json!({ "seed": 42, "all_seeds": [42] }).to_string(),
+    });
+    Ok(vec![
+        (
+            "/agent-scheduler",
+            404,
+            b"{\"detail\": \"Not Found\"}".to_vec(),
+        ),
+        ("/sdapi/v1/txt2img", 200, txt2img.to_string().into_bytes()),
+        (
+            "/sdapi/v1/options",
+            200,
+            br#"{"sd_model_checkpoint": "sd_xl_base_1.0.safetensors"}"#.to_vec(),
+        ),
+        ("/", 200, b"{}".to_vec()),
+    ])
+}
+
+/// Routes for an instance with the agent-scheduler extension.
+fn queue_routes() -> Result<Vec<(&'static str, u16, Vec<u8>)>> {
+    let status = json!({
+        "success": true,
+        "data": {
+            "id": "t1", "type": "txt2img", "status": "done", "params": {}, "priority": 0,
+            "created_at": "2025-01-01T00:00:00", "updated_at": "2025-01-01T00:00:10"
+        }
+    });
+    let results = json!({
+        "success": true,
+        "data": [{
+            "image": format!("data:image/png;base64,{}", png_base64()?),
+            "infotext": "A castle\nSteps: 15, Sampler: UniPC, CFG scale: 2, Seed: 42, Size: 64x64"
+        }]
+    });
+    Ok(vec![
+        (
+            "/agent-scheduler/v1/queue/txt2img",
+            200,
+            br#"{"task_id": "t1"}"#.to_vec(),
+        ),
+        (
+            "/agent-scheduler/v1/queue",
+            200,
+            br#"{"current_task_id": null, "pending_tasks": [], "total_pending_tasks": 0}"#.to_vec(),
+        ),
+        (
+            "/agent-scheduler/v1/task/t1/results",
+            200,
+            results.to_string().into_bytes(),
+        ),
+        (
+            "/agent-scheduler/v1/task/t1",
+            200,
+            status.to_string().into_bytes(),
+        ),
+    ])
+}
+
+fn output_directory(name: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+async fn generate(
+    server: &StandInServer,
+    mode: Option<StableDiffusionMode>,
+    name: &str,
+) -> Result<Vec<GeneratedImage>> {
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode,
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory(name)?,
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+    provider.generate_image(params).await
+}
+
+fn paths(server: &StandInServer) -> Vec<String> {
+    server
+        .requests
+        .lock()
+        .map(|requests| {
+            requests
+                .iter()
+                .map(|request| request.path.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_auto_mode_matches_both_paths() -> Result<()> {
+    let direct = StandInServer::start(direct_routes()?)?;
+    let direct_images = generate(&direct, None, "test_auto_mode_direct").await?;
+    assert!(paths(&direct).contains(&"/sdapi/v1/txt2img".to_string()));
+
+    let queue = StandInServer::start(queue_routes()?)?;
+    let queue_images = generate(&queue, None, "test_auto_mode_queue").await?;
+    assert!(paths(&queue).contains(&"/agent-scheduler/v1/queue/txt2img".to_string()));
+
+    // Both paths save the same image with the same seed.
+    assert_eq!(direct_images.len(), 1);
+    assert_eq!(queue_images.len(), 1);
+    assert_eq!(direct_images[0].seed, Some(42));
+    assert_eq!(queue_images[0].seed, Some(42));
+    assert_eq!(
+        std::fs::read(&direct_images[0].path)?,
+        std::fs::read(&queue_images[0].path)?
+    );
+    let metadata = ImageMetadata::read(&direct_images[0].path)?.unwrap();
+    assert_eq!(metadata.provider, Some("StableDiffusion".to_string()));
+    assert_eq!(metadata.seed, Some(42));
+    assert_eq!(metadata.steps, Some(15));
+    std::fs::remove_file(&direct_images[0].path)?;
+    std::fs::remove_file(&queue_images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_queue_mode_requires_agent_scheduler() -> Result<()> {
+    let server = StandInServer::start(direct_routes()?)?;
+    let error = generate(
+        &server,
+        Some(StableDiffusionMode::Queue),
+        "test_queue_mode_requires_agent_scheduler",
+    )
+    .await
+    .unwrap_err();
+    assert!(error.to_string().contains("agent-scheduler"));
+    assert!(!paths(&server).contains(&"/sdapi/v1/txt2img".to_string()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_direct_mode_skips_queue() -> Result<()> {
+    let server = StandInServer::start(
+        queue_routes()?
+            .into_iter()
+            .chain(direct_routes()?)
+            .collect(),
+    )?;
+    let images = generate(
+        &server,
+        Some(StableDiffusionMode::Direct),
+        "test_direct_mode_skips_queue",
+    )
+    .await?;
+    assert!(paths(&server).contains(&"/sdapi/v1/txt2img".to_string()));
+    assert!(!paths(&server).contains(&"/agent-scheduler/v1/queue/txt2img".to_string()));
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_format_and_thumbnails() -> Result<()> {
+    let server = StandInServer::start(direct_routes()?)?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_format_and_thumbnails")?,
+        width: 64,
+        height: 64,
+        format: Some(OutputFormat::Jpeg),
+        quality: Some(50),
+        thumbnail_sizes: vec![16, 32],
+        ..Default::default()
+    };
+    let images = provider.generate_image(params).await?;
+    let image = &images[0];
+    assert_eq!(
+        image
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str()),
+        Some("jpg")
+    );
+    assert_eq!(
+        image::ImageFormat::from_path(&image.path)?,
+        image::guess_format(&std::fs::read(&image.path)?)?
+    );
+    // Only the converted image is kept.
+    assert!(!image.path.with_extension("png").exists());
+
+    assert_eq!(image.thumbnails.len(), 2);
+    assert_eq!(image::image_dimensions(&image.thumbnails[0])?, (16, 16));
+    assert_eq!(image::image_dimensions(&image.thumbnails[1])?, (32, 32));
+    for path in image.thumbnails.iter().chain([&image.path]) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Routes for an instance with one LoRA and one embedding installed.
+fn extra_network_routes() -> Result<Vec<(&'static str, u16, Vec<u8>)>> {
+    let loras = json!([{ "name": "add_detail", "alias": "detail_tweaker", "path": "models/Lora/add_detail.safetensors" }]);
+    let embeddings =
+        json!({ "loaded": { "easynegative": { "shape": 768, "vectors": 8 } }, "skipped": {} });
+    Ok(vec![
+        ("/sdapi/v1/loras", 200, loras.to_string().into_bytes()),
+        (
+            "/sdapi/v1/embeddings",
+            200,
+            embeddings.to_string().into_bytes(),
+        ),
+    ]
+    .into_iter()
+    .chain(direct_routes()?)
+    .collect())
+}
+
+#[tokio::test]
+async fn test_extra_networks_in_prompt() -> Result<()> {
+    let server = StandInServer::start(extra_network_routes()?)?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: Some(StableDiffusionMode::Direct),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_extra_networks_in_prompt")?,
+        width: 64,
+        height: 64,
+        loras: vec!["detail_tweaker:0.5".parse()?],
+        negative_embeddings: vec!["easynegative".parse()?],
+        ..Default::default()
+    };
+    let images = provider.generate_image(params).await?;
+
+    let requests = server.requests.lock().unwrap().clone();
+    let txt2img = requests
+        .iter()
+        .find(|request| request.path == "/sdapi/v1/txt2img")
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&txt2img.body)?;
+    assert!(body["prompt"]
+        .as_str()
+        .unwrap()
+        .ends_with("<lora:detail_tweaker:0.5>"));
+    assert_eq!(body["negative_prompt"], "easynegative");
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert!(metadata.prompt.ends_with("<lora:detail_tweaker:0.5>"));
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_extra_networks_are_rejected() -> Result<()> {
+    let server = StandInServer::start(extra_network_routes()?)?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: Some(StableDiffusionMode::Direct),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_unknown_extra_networks_are_rejected")?,
+        loras: vec!["add_detial".parse()?],
+        ..Default::default()
+    };
+    let error = provider.generate_image(params.clone()).await.unwrap_err();
+    assert!(error.to_string().contains("add_detial"));
+    assert!(error.to_string().contains("add_detail"));
+
+    let params = ImageParams {
+        loras: Vec::new(),
+        embeddings: vec!["easynegativ".parse()?],
+        ..params
+    };
+    let error = provider.generate_image(params).await.unwrap_err();
+    assert!(error.to_string().contains("easynegativ"));
+    assert!(!paths(&server).contains(&"/sdapi/v1/txt2img".to_string()));
+    Ok(())
+}
+
+fn png_base64_of_size(width: u32, height: u32) -> Result<String> {
+    let image = image::RgbaImage::from_pixel(width, height, image::Rgba([0, 128, 255, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    Ok(base64::prelude::BASE64_STANDARD.encode(bytes.into_inner()))
+}
+
+fn request_body(server: &StandInServer, path: &str) -> Result<serde_json::Value> {
+    let requests = server
+        .requests
+        .lock()
+        .map_err(|_| anyhow::Error::msg("The requests lock is poisoned"))?
+        .clone();
+    let request = requests
+        .iter()
+        .find(|request| request.path == path)
+        .ok_or(anyhow::Error::msg(format!("{} was not requested", path)))?;
+    Ok(serde_json::from_str(&request.body)?)
+}
+
+#[tokio::test]
+async fn test_hires_fix_in_both_modes() -> Result<()> {
+    let params = |name| -> Result<ImageParams> {
+        Ok(ImageParams {
+            output_directory: output_directory(name)?,
+            width: 64,
+            height: 64,
+            enable_hr: true,
+            hr_upscaler: Some("Latent".to_string()),
+            denoising_strength: Some(0.5),
+            ..Default::default()
+        })
+    };
+    let direct = StandInServer::start(direct_routes()?)?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: direct.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    let direct_images = provider
+        .generate_image(params("test_hires_fix_direct")?)
+        .await?;
+    let body = request_body(&direct, "/sdapi/v1/txt2img")?;
+    assert_eq!(body["enable_hr"], true);
+    assert_eq!(body["hr_scale"], 2.0);
+    assert_eq!(body["hr_upscaler"], "Latent");
+    assert_eq!(body["denoising_strength"], 0.5);
+    // The hires fix doubles the size of the image.
+    assert_eq!(image::image_dimensions(&direct_images[0].path)?, (128, 128));
+
+    let queue = StandInServer::start(queue_routes()?)?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: queue.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    let queue_images = provider
+        .generate_image(params("test_hires_fix_queue")?)
+        .await?;
+    let body = request_body(&queue, "/agent-scheduler/v1/queue/txt2img")?;
+    assert_eq!(body["enable_hr"], true);
+    assert_eq!(body["hr_scale"], 2.0);
+    assert_eq!(body["denoising_strength"], 0.5);
+    assert_eq!(image::image_dimensions(&queue_images[0].path)?, (128, 128));
+
+    std::fs::remove_file(&direct_images[0].path)?;
+    std::fs::remove_file(&queue_images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upscale_after_generating() -> Result<()> {
+    let upscaled = json!({ "image": png_base64_of_size(256, 256)?, "html_info": "" });
+    let server = StandInServer::start(
+        vec![(
+            "/sdapi/v1/extra-single-image",
+            200,
+            upscaled.to_string().into_bytes(),
+        )]
+        .into_iter()
+        .chain(direct_routes()?)
+        .collect(),
+    )?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("test_upscale_after_generating")?,
+        width: 64,
+        height: 64,
+        upscale: Some(Upscale {
+            upscaler: "R-ESRGAN 4x+".to_string(),
+            scale: 4.0,
+        }),
+        ..Default::default()
+    };
+    let images = provider.generate_image(params).await?;
+    let body = request_body(&server, "/sdapi/v1/extra-single-image")?;
+    assert_eq!(body["upscaler_1"], "R-ESRGAN 4x+");
+    assert_eq!(body["upscaling_resize"], 4.0);
+    assert_eq!(image::image_dimensions(&images[0].path)?, (256, 256));
+    // The parameters are saved in the upscaled image.
+    let metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(metadata.seed, Some(42));
+    assert_eq!((metadata.width, metadata.height), (256, 256));
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upscale_existing_image() -> Result<()> {
+    let upscaled = json!({ "image": png_base64_of_size(128, 128)?, "html_info": "" });
+    let server = StandInServer::start(vec![(
+        "/sdapi/v1/extra-single-image",
+        200,
+        upscaled.to_string().into_bytes(),
+    )])?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    // An image with saved parameters, as if it had been generated earlier.
+    let directory = output_directory("test_upscale_existing_image")?;
+    let source = directory.join("source.png");
+    image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 0, 0, 255])).save(&source)?;
+    let metadata = ImageMetadata {
+        prompt: "A castle".to_string(),
+        seed: Some(7),
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+    metadata.write(&source)?;
+
+    let params = ImageParams {
+        output_directory: directory,
+        ..Default::default()
+    };
+    let images = provider
+        .upscale(params, UpscaleParams::new(source.clone()))
+        .await?;
+    assert_eq!(image::image_dimensions(&images[0].path)?, (128, 128));
+    assert_eq!(images[0].seed, Some(7));
+    let upscaled_metadata = ImageMetadata::read(&images[0].path)?.unwrap();
+    assert_eq!(upscaled_metadata.prompt, "A castle");
+    assert_eq!(
+        (upscaled_metadata.width, upscaled_metadata.height),
+        (128, 128)
+    );
+    // The original image is left as it was.
+    assert_eq!(image::image_dimensions(&source)?, (64, 64));
+    std::fs::remove_file(&images[0].path)?;
+    std::fs::remove_file(source)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_progress_while_rendering() -> Result<()> {
+    let progress_response = json!({
+        "progress": 0.25, "eta_relative": 12.0,
+        "state": {"sampling_step": 5, "sampling_steps": 20},
+        "current_image": png_base64()?,
+    });
+    // The render takes long enough for progress to be checked while it runs.
+    let server = StandInServer::start_with_delay(
+        std::iter::once((
+            "/sdapi/v1/progress",
+            200,
+            progress_response.to_string().into_bytes(),
+        ))
+        .chain(direct_routes()?)
+        .collect(),
+        Some(("/sdapi/v1/txt2img", std::time::Duration::from_millis(500))),
+    )?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: Some(StableDiffusionMode::Direct),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("ai_images-progress")?,
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+    let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = events.clone();
+    let progress = Progress::new(move |event| {
+        if let Ok(mut events) = received.lock() {
+            events.push(event);
+        }
+    });
+
+    let images = provider
+        .run(
+            params,
+            ImageOperation::TextToImage,
+            &progress,
+            &CancellationToken::default(),
+        )
+        .await?;
+    let events = events.lock().unwrap().clone();
+    assert!(
+        matches!(
+            events.first(),
+            Some(ProgressEvent::Rendering { step: 5, steps: 20, eta: Some(eta), preview: Some(preview), .. })
+                if eta.as_secs() == 12 && preview.exists()
+        ),
+        "Expected a rendering event, got {:?}",
+        events
+    );
+    for image in images {
+        std::fs::remove_file(image.path)?;
+    }
+    Ok(())
+}
+
+/// Generate with a token that is cancelled shortly after the request is sent.
+async fn generate_and_cancel(
+    server: &StandInServer,
+    mode: StableDiffusionMode,
+) -> Result<anyhow::Error> {
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: Some(mode),
+        ..Default::default()
+    });
+    let params = ImageParams {
+        output_directory: output_directory("ai_images-cancel")?,
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+    let cancel = CancellationToken::new();
+    let trigger = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        trigger.cancel();
+    });
+    let started = std::time::Instant::now();
+    let result = provider
+        .run(
+            params,
+            ImageOperation::TextToImage,
+            &Progress::default(),
+            &cancel,
+        )
+        .await;
+    // The run stops as soon as it is cancelled instead of waiting for the render.
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    result
+        .err()
+        .ok_or(anyhow::Error::msg("The generation wasn't cancelled."))
+}
+
+#[tokio::test]
+async fn test_cancel_direct_request() -> Result<()> {
+    let server = StandInServer::start_with_delay(
+        std::iter::once(("/sdapi/v1/interrupt", 200, b"{}".to_vec()))
+            .chain(direct_routes()?)
+            .collect(),
+        Some(("/sdapi/v1/txt2img", std::time::Duration::from_secs(10))),
+    )?;
+    let error = generate_and_cancel(&server, StableDiffusionMode::Direct).await?;
+    assert!(error.is::<Cancelled>());
+    assert!(paths(&server).contains(&"/sdapi/v1/interrupt".to_string()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_queued_task() -> Result<()> {
+    // The task never finishes, so it has to be deleted from the queue.
+    let pending = json!({
+        "success": true,
+        "data": {
+            "id": "t1", "type": "txt2img", "status": "pending", "params": {}, "priority": 0,
+            "created_at": "2025-01-01T00:00:00", "updated_at": "2025-01-01T00:00:00"
+        }
+    });
+    let server = StandInServer::start(
+        std::iter::once((
+            "/agent-scheduler/v1/task/t1",
+            200,
+            pending.to_string().into_bytes(),
+        ))
+        .chain(queue_routes()?)
+        .collect(),
+    )?;
+    let error = generate_and_cancel(&server, StableDiffusionMode::Queue).await?;
+    assert!(error.is::<Cancelled>());
+    let requests = server.requests.lock().unwrap().clone();
+    assert!(
+        requests
+            .iter()
+            .any(|request| request.method == "DELETE"
+                && request.path == "/agent-scheduler/v1/task/t1")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resume_timed_out_task() -> Result<()> {
+    // Reading the task's status takes longer than the first run is willing to wait.
+    let server = StandInServer::start_with_delay(
+        queue_routes()?,
+        Some((
+            "/agent-scheduler/v1/task/t1",
+            std::time::Duration::from_millis(800),
+        )),
+    )?;
+    let provider = |task_timeout| {
+        ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+            url: server.url.clone(),
+            mode: Some(StableDiffusionMode::Queue),
+            task_timeout,
+            ..Default::default()
+        })
+    };
+    let directory = output_directory("ai_images-resume")?;
+    let state_file = directory.join(".ai_images-tasks.json");
+    let _ = std::fs::remove_file(&state_file);
+    let params = ImageParams {
+        output_directory: directory.clone(),
+        width: 64,
+        height: 64,
+        thumbnail_sizes: vec![32],
+        task_context: json!({ "name": "Goblin" }),
+        ..Default::default()
+    };
+    let error = provider(Some(0.3))
+        .generate_image(params)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("timed out"), "{}", error);
+    assert!(state_file.exists());
+
+    let provider = provider(None);
+    let (progress, cancel) = (Progress::default(), CancellationToken::default());
+    let resumed = provider.resume(&directory, &progress, &cancel).await?;
+    assert_eq!(resumed.len(), 1);
+    assert_eq!(resumed[0].task.task_id, "t1");
+    assert_eq!(resumed[0].task.context, json!({ "name": "Goblin" }));
+    let images = resumed[0].images.as_ref().unwrap();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].seed, Some(42));
+    assert_eq!(images[0].thumbnails.len(), 1);
+    assert!(ImageMetadata::read(&images[0].path)?.is_some());
+    // The task is forgotten once its images are collected.
+    assert!(!state_file.exists());
+    assert!(provider
+        .resume(&directory, &progress, &cancel)
+        .await?
+        .is_empty());
+    for image in images {
+        std::fs::remove_file(&image.path)?;
+        for thumbnail in &image.thumbnails {
+            std::fs::remove_file(thumbnail)?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resumed_images_have_the_requested_size() -> Result<()> {
+    let server = StandInServer::start_with_delay(
+        queue_routes()?,
+        Some((
+            "/agent-scheduler/v1/task/t1",
+            std::time::Duration::from_millis(800),
+        )),
+    )?;
+    let provider = |task_timeout| {
+        ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+            url: server.url.clone(),
+            mode: Some(StableDiffusionMode::Queue),
+            task_timeout,
+            ..Default::default()
+        })
+    };
+    let directory = output_directory("ai_images-resume-size")?;
+    let state_file = directory.join(".ai_images-tasks.json");
+    let _ = std::fs::remove_file(&state_file);
+    // Neither side is a multiple of 8, so the instance is asked for 64x48 and the images are cropped.
+    let params = ImageParams {
+        output_directory: directory.clone(),
+        width: 60,
+        height: 44,
+        ..Default::default()
+    };
+    let generated = provider(None).generate_image(params.clone()).await?;
+    assert!(provider(Some(0.3)).generate_image(params).await.is_err());
+    let body = request_body(&server, "/agent-scheduler/v1/queue/txt2img")?;
+    assert_eq!(
+        (body["width"].clone(), body["height"].clone()),
+        (json!(64), json!(48))
+    );
+
+    let (progress, cancel) = (Progress::default(), CancellationToken::default());
+    let resumed = provider(None)
+        .resume(&directory, &progress, &cancel)
+        .await?;
+    let images = resumed[0].images.as_ref().unwrap();
+    assert_eq!(image::image_dimensions(&generated[0].path)?, (60, 44));
+    assert_eq!(image::image_dimensions(&images[0].path)?, (60, 44));
+    for image in generated.iter().chain(images) {
+        std::fs::remove_file(&image.path)?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_task_kept_until_images_are_saved() -> Result<()> {
+    let server = StandInServer::start(queue_routes()?)?;
+    let directory = output_directory("ai_images-unsaved")?;
+    let state_file = directory.join("tasks.json");
+    let _ = std::fs::remove_file(&state_file);
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: Some(StableDiffusionMode::Queue),
+        state_file: Some(state_file.clone()),
+        ..Default::default()
+    });
+    // The output directory is a file, so the finished task's images can't be saved.
+    let blocked = directory.join("images");
+    let _ = std::fs::remove_dir_all(&blocked);
+    std::fs::write(&blocked, "")?;
+    let params = ImageParams {
+        output_directory: blocked.clone(),
+        width: 64,
+        height: 64,
+        ..Default::default()
+    };
+    assert!(provider.generate_image(params).await.is_err());
+    assert!(state_file.exists());
+
+    // Once the images can be saved, the task is resumed and forgotten.
+    std::fs::remove_file(&blocked)?;
+    std::fs::create_dir_all(&blocked)?;
+    let (progress, cancel) = (Progress::default(), CancellationToken::default());
+    let resumed = provider.resume(&blocked, &progress, &cancel).await?;
+    let images = resumed[0].images.as_ref().unwrap();
+    assert_eq!(images[0].seed, Some(42));
+    assert!(!state_file.exists());
+    std::fs::remove_file(&images[0].path)?;
+    Ok(())
+}
+
+/// Routes for the ControlNet extension's lists of modules and models.
+fn controlnet_routes() -> Vec<(&'static str, u16, Vec<u8>)> {
+    vec![
+        (
+            "/controlnet/module_list",
+            200,
+            br#"{"module_list": ["none", "openpose", "canny"]}"#.to_vec(),
+        ),
+        (
+            "/controlnet/model_list",
+            200,
+            br#"{"model_list": ["control_v11p_sd15_openpose [cab727d4]"]}"#.to_vec(),
+        ),
+    ]
+}
+
+/// A different image than `png_base64`, standing in for the detected map ControlNet adds after the generated images.
+fn detected_map_base64() -> Result<String> {
+    let image = image::RgbaImage::from_pixel(32, 32, image::Rgba([0, 0, 0, 255]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image).write_to(&mut bytes, image::ImageFormat::Png)?;
+    Ok(base64::prelude::BASE64_STANDARD.encode(bytes.into_inner()))
+}
+
+#[tokio::test]
+async fn test_controlnet_detected_map_is_dropped() -> Result<()> {
+    let directory = output_directory("ai_images-controlnet-map")?;
+    let pose = directory.join("pose.png");
+    std::fs::write(
+        &pose,
+        base64::prelude::BASE64_STANDARD.decode(png_base64()?)?,
+    )?;
+    let params = ImageParams {
+        output_directory: directory.clone(),
+        width: 64,
+        height: 64,
+        controlnet: vec![ControlNetUnit {
+            module: "openpose".to_string(),
+            ..ControlNetUnit::new(pose.clone(), "control_v11p_sd15_openpose")
+        }],
+        ..Default::default()
+    };
+    let txt2img = json!({
+        "images": [png_base64()?, detected_map_base64()?],
+        "info": json!({ "seed": 42, "all_seeds": [42] }).to_string(),
+    });
+    let results = json!({
+        "success": true,
+        "data": [
+            {
+                "image": format!("data:image/png;base64,{}", png_base64()?),
+                "infotext": "A castle\nSteps: 15, Sampler: UniPC, CFG scale: 2, Seed: 42, Size: 64x64"
+            },
+            {
+                "image": format!("data:image/png;base64,{}", detected_map_base64()?),
+                "infotext": "A castle\nSteps: 15, Sampler: UniPC, CFG scale: 2, Seed: 42, Size: 32x32"
+            }
+        ]
+    });
+    // The routes with the detected map are first, so they're matched before the ones without it.
+    let direct = ("/sdapi/v1/txt2img", 200, txt2img.to_string().into_bytes());
+    let queue = (
+        "/agent-scheduler/v1/task/t1/results",
+        200,
+        results.to_string().into_bytes(),
+    );
+
+    for (routes, path) in [
+        (
+            [direct]
+                .into_iter()
+                .chain(direct_routes()?)
+                .collect::<Vec<_>>(),
+            "/sdapi/v1/txt2img",
+        ),
+        (
+            [queue].into_iter().chain(queue_routes()?).collect(),
+            "/agent-scheduler/v1/queue/txt2img",
+        ),
+    ] {
+        let server = StandInServer::start(controlnet_routes().into_iter().chain(routes).collect())?;
+        let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+            url: server.url.clone(),
+            mode: None,
+            ..Default::default()
+        });
+        let images = provider.generate_image(params.clone()).await?;
+        let body = request_body(&server, path)?;
+        assert_eq!(
+            body["alwayson_scripts"]["controlnet"]["args"][0]["save_detected_map"],
+            false
+        );
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].seed, Some(42));
+        assert_eq!(image::image_dimensions(&images[0].path)?, (64, 64));
+        for image in images {
+            std::fs::remove_file(image.path)?;
+        }
+    }
+    std::fs::remove_file(pose)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_controlnet_in_both_modes() -> Result<()> {
+    let directory = output_directory("ai_images-controlnet")?;
+    let pose = directory.join("pose.png");
+    std::fs::write(
+        &pose,
+        base64::prelude::BASE64_STANDARD.decode(png_base64()?)?,
+    )?;
+    let params = ImageParams {
+        output_directory: directory.clone(),
+        width: 64,
+        height: 64,
+        controlnet: vec![ControlNetUnit {
+            module: "openpose".to_string(),
+            weight: 0.8,
+            guidance_end: 0.6,
+            ..ControlNetUnit::new(pose.clone(), "control_v11p_sd15_openpose")
+        }],
+        ..Default::default()
+    };
+
+    for (routes, path) in [
+        (direct_routes()?, "/sdapi/v1/txt2img"),
+        (queue_routes()?, "/agent-scheduler/v1/queue/txt2img"),
+    ] {
+        let server = StandInServer::start(controlnet_routes().into_iter().chain(routes).collect())?;
+        let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+            url: server.url.clone(),
+            mode: None,
+            ..Default::default()
+        });
+        let images = provider.generate_image(params.clone()).await?;
+        let body = request_body(&server, path)?;
+        let unit = &body["alwayson_scripts"]["controlnet"]["args"][0];
+        assert_eq!(unit["enabled"], true);
+        assert_eq!(unit["module"], "openpose");
+        assert_eq!(unit["model"], "control_v11p_sd15_openpose");
+        assert_eq!(unit["weight"], 0.8);
+        assert_eq!(unit["guidance_start"], 0.0);
+        assert_eq!(unit["guidance_end"], 0.6);
+        assert_eq!(unit["image"], png_base64()?);
+        for image in images {
+            std::fs::remove_file(image.path)?;
+        }
+    }
+
+    // Units the extension can't load are rejected before anything is generated.
+    let server = StandInServer::start(
+        controlnet_routes()
+            .into_iter()
+            .chain(direct_routes()?)
+            .collect(),
+    )?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: server.url.clone(),
+        mode: None,
+        ..Default::default()
+    });
+    let unknown_model = ImageParams {
+        controlnet: vec![ControlNetUnit::new(pose.clone(), "control_v11p_sd15_depth")],
+        ..params.clone()
+    };
+    let error = provider.generate_image(unknown_model).await.unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("control_v11p_sd15_openpose [cab727d4]"));
+    assert!(!paths(&server).contains(&"/sdapi/v1/txt2img".to_string()));
+    std::fs::remove_file(pose)?;
+    Ok(())
+}
+
+/// A route that lists the installed models. The loaded model in `direct_routes` is the first one.
+fn models_route() -> (&'static str, u16, Vec<u8>) {
+    let models = json!([
+        {
+            "title": "sd_xl_base_1.0.safetensors", "model_name": "sd_xl_base_1.0",
+            "filename": "/models/Stable-diffusion/sd_xl_base_1.0.safetensors",
+            "hash": "31e35c80fc", "sha256": null, "config": null
+        },
+        {
+            "title": "juggernautXL_v9.safetensors [c9e3e68f89]", "model_name": "juggernautXL_v9",
+            "filename": "/models/Stable-diffusion/juggernautXL_v9.safetensors",
+            "hash": "c9e3e68f89", "sha256": null, "config": null
+        }
+    ]);
+    ("/sdapi/v1/sd-models", 200, models.to_string().into_bytes())
+}
+
+#[tokio::test]
+async fn test_model_resolution() -> Result<()> {
+    let params = |name: &str, model: &str| -> Result<ImageParams> {
+        Ok(ImageParams {
+            output_directory: output_directory(name)?,
+            width: 64,
+            height: 64,
+            model: Some(model.to_string()),
+            ..Default::default()
+        })
+    };
+
+    // The model name resolves to the loaded model's title, so it isn't loaded again.
+    let direct = StandInServer::start(
+        std::iter::once(models_route())
+            .chain(direct_routes()?)
+            .collect(),
+    )?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: direct.url.clone(),
+        mode: Some(StableDiffusionMode::Direct),
+        ..Default::default()
+    });
+    let mut images = provider
+        .generate_image(params("ai_images-model-direct", "sd_xl_base_1.0")?)
+        .await?;
+    images.extend(
+        provider
+            .generate_image(params("ai_images-model-hash", "31e35c")?)
+            .await?,
+    );
+    let requests = direct.requests.lock().unwrap().clone();
+    assert!(!requests
+        .iter()
+        .any(|request| request.method == "POST" && request.path == "/sdapi/v1/options"));
+    // The model list is cached after the first generation.
+    let listed = requests
+        .iter()
+        .filter(|request| request.path == "/sdapi/v1/sd-models")
+        .count();
+    assert_eq!(listed, 1);
+
+    // The queue is sent the model's title.
+    let queue = StandInServer::start(
+        std::iter::once(models_route())
+            .chain(queue_routes()?)
+            .collect(),
+    )?;
+    let provider = ImageProviders::StableDiffusion(StableDiffusionXLProvider {
+        url: queue.url.clone(),
+        mode: Some(StableDiffusionMode::Queue),
+        ..Default::default()
+    });
+    images.extend(
+        provider
+            .generate_image(params("ai_images-model-queue", "juggernautxl_v9")?)
+            .await?,
+    );
+    let body = request_body(&queue, "/agent-scheduler/v1/queue/txt2img")?;
+    assert_eq!(
+        body["checkpoint"],
+        "juggernautXL_v9.safetensors [c9e3e68f89]"
+    );
+
+    // Typos fail with the closest names before anything is queued.
+    let error = provider
+        .generate_image(params("ai_images-model-queue", "juggernaut_v9")?)
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Did you mean: juggernautXL_v9"),
+        "{}",
+        error
+    );
+
+    for image in images {
+        std::fs::remove_file(image.path)?;
+    }
+    Ok(())
+}
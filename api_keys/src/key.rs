@@ -0,0 +1,75 @@
+use anyhow::Error;
+use std::fmt;
+
+/// The text that replaces an API key wherever it would be shown.
+const REDACTED: &str = "[REDACTED]";
+
+/// An API key. Its value is only available through `expose`, and is redacted from `Debug` and `Display` output.
+#[derive(Clone, PartialEq)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    /// Wrap an API key, trimming any surrounding whitespace.
+    pub fn new(key: &str) -> Self {
+        Self(key.trim().to_string())
+    }
+
+    /// Get the value of the key, to pass to the provider's client.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replace every occurrence of the key in a piece of text.
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+        text.replace(&self.0, REDACTED)
+    }
+
+    /// Replace every occurrence of the key in an error message, including the messages of the errors that caused it.
+    pub fn redact_error(&self, error: impl Into<Error>) -> Error {
+        let error: Error = error.into();
+        let message = format!("{:#}", error);
+        if self.0.is_empty() || !message.contains(&self.0) {
+            return error;
+        }
+        Error::msg(self.redact(&message))
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ApiKey({})", REDACTED)
+    }
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_and_display_are_redacted() {
+        let key = ApiKey::new("sk-secret");
+        assert_eq!(format!("{:?}", key), "ApiKey([REDACTED])");
+        assert_eq!(key.to_string(), "[REDACTED]");
+        assert_eq!(key.expose(), "sk-secret");
+    }
+
+    #[test]
+    fn test_redact_error() {
+        let key = ApiKey::new("sk-secret");
+        let error = Error::msg("Incorrect API key provided: sk-secret").context("Request failed");
+        let redacted = key.redact_error(error);
+        assert_eq!(
+            format!("{:#}", redacted),
+            "Request failed: Incorrect API key provided: [REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! Loads API keys for AI providers from configurable sources, and keeps them out of error messages and debug output.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use api_keys::ApiKeySource;
+//!
+//! let source = ApiKeySource {
+//!     api_key_env: Some("MY_OPENAI_KEY".to_string()),
+//!     api_key_file: None,
+//!     dotenv_path: Some("config/.env".into()),
+//! };
+//! // Falls back to `OPENAI_API_KEY` if `api_key_env` isn't set.
+//! let key = source.load("OPENAI_API_KEY").unwrap();
+//! // The key itself is never printed.
+//! println!("{:?}", key);
+//! ```
+
+#![deny(unused_crate_dependencies)]
+
+mod key;
+mod source;
+
+pub use key::ApiKey;
+pub use source::ApiKeySource;
@@ -0,0 +1,147 @@
+use crate::ApiKey;
+use anyhow::{Error, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Where to load a provider's API key from.
+/// If nothing is set, the key is read from the provider's default environment variable, after loading a `.env` file from the current directory or the directory of the binary.
+#[derive(Args, Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ApiKeySource {
+    /// The environment variable containing the API key.
+    #[arg(long)]
+    pub api_key_env: Option<String>,
+
+    /// A file containing the API key. Takes precedence over environment variables.
+    #[arg(long)]
+    pub api_key_file: Option<PathBuf>,
+
+    /// A `.env` file to load environment variables from.
+    #[arg(long)]
+    pub dotenv_path: Option<PathBuf>,
+}
+
+impl ApiKeySource {
+    /// Load the API key, reading `default_env` if no other environment variable is configured.
+    pub fn load(&self, default_env: &str) -> Result<ApiKey> {
+        self.load_optional(default_env)?.ok_or({
+            let env = self.api_key_env.as_deref().unwrap_or(default_env);
+            Error::msg(format!(
+                "API key not set. Set the {} environment variable, or configure `api_key_env` or `api_key_file`.",
+                env
+            ))
+        })
+    }
+
+    /// Load the API key if one is available. Used by providers that don't always need a key.
+    pub fn load_optional(&self, default_env: &str) -> Result<Option<ApiKey>> {
+        if let Some(path) = &self.api_key_file {
+            let key = std::fs::read_to_string(path).map_err(|e| {
+                Error::msg(format!("Unable to read the API key file {:?}: {}", path, e))
+            })?;
+            let key = ApiKey::new(&key);
+            if key.expose().is_empty() {
+                return Err(Error::msg(format!("The API key file {:?} is empty.", path)));
+            }
+            return Ok(Some(key));
+        }
+
+        self.load_dotenv()?;
+        let env = self.api_key_env.as_deref().unwrap_or(default_env);
+        match std::env::var(env) {
+            Ok(key) if !key.trim().is_empty() => Ok(Some(ApiKey::new(&key))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Populate the environment variables from the configured `.env` file.
+    /// Without one, look in the current directory and then next to the binary, ignoring missing files.
+    fn load_dotenv(&self) -> Result<()> {
+        if let Some(path) = &self.dotenv_path {
+            dotenvy::from_path(path).map_err(|e| {
+                Error::msg(format!("Unable to load the .env file {:?}: {}", path, e))
+            })?;
+            return Ok(());
+        }
+        if dotenvy::dotenv().is_err() {
+            let beside_binary = std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(|dir| dir.join(".env")));
+            if let Some(path) = beside_binary {
+                dotenvy::from_path(path).ok();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_from_file() -> Result<()> {
+        let dir = tempdir()?;
+        let key_file = dir.path().join("key.txt");
+        std::fs::write(&key_file, "sk-from-file\n")?;
+        let source = ApiKeySource {
+            api_key_file: Some(key_file),
+            ..Default::default()
+        };
+        let key = source.load("API_KEYS_TEST_UNUSED")?;
+        assert_eq!(key.expose(), "sk-from-file");
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_from_dotenv_path() -> Result<()> {
+        let dir = tempdir()?;
+        let dotenv_file = dir.path().join("keys.env");
+        std::fs::write(&dotenv_file, "API_KEYS_TEST_DOTENV=sk-from-dotenv\n")?;
+        let source = ApiKeySource {
+            api_key_env: Some("API_KEYS_TEST_DOTENV".to_string()),
+            dotenv_path: Some(dotenv_file),
+            ..Default::default()
+        };
+        let key = source.load("API_KEYS_TEST_UNUSED")?;
+        assert_eq!(key.expose(), "sk-from-dotenv");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let source = ApiKeySource::default();
+        let error = source.load("API_KEYS_TEST_MISSING");
+        assert!(error.is_err());
+        assert!(
+            source
+                .load_optional("API_KEYS_TEST_MISSING")
+                .is_ok_and(|key| key.is_none())
+        );
+    }
+
+    #[test]
+    fn test_missing_dotenv_path() {
+        let source = ApiKeySource {
+            dotenv_path: Some(PathBuf::from("does-not-exist.env")),
+            ..Default::default()
+        };
+        assert!(source.load("API_KEYS_TEST_MISSING").is_err());
+    }
+
+    /// The source is flattened into provider configurations, so check how it looks in TOML.
+    #[test]
+    fn test_toml() -> Result<()> {
+        let toml = r#"
+api_key_env = "MY_OPENAI_KEY"
+dotenv_path = "config/.env"
+        "#;
+        let source: ApiKeySource = toml::from_str(toml)?;
+        assert_eq!(source.api_key_env, Some("MY_OPENAI_KEY".to_string()));
+        assert_eq!(source.api_key_file, None);
+        assert_eq!(source.dotenv_path, Some(PathBuf::from("config/.env")));
+        Ok(())
+    }
+}
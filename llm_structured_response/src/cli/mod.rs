@@ -53,11 +53,9 @@ impl Default for CliConfigArgs {
     fn default() -> Self {
         CliConfigArgs {
             provider: LlmProviders::default(),
-            provider_config: Some(LlmProviderConfig {
-                model: "gpt-4o".to_string(),
-                url: None,
-                port: None,
-            }),
+            provider_config: Some(LlmProviderConfig::default_for_provider(
+                &LlmProviders::default(),
+            )),
             json_schema_file: std::path::PathBuf::default(),
             initial_prompt: String::default(),
             system_prompt: String::default(),
@@ -82,23 +80,9 @@ impl<'de> Deserialize<'de> for CliConfigArgs {
         }
 
         let helper = CliConfigArgsHelper::deserialize(deserializer)?;
-        let provider_config = helper.provider_config.or_else(|| match helper.provider {
-            LlmProviders::OpenAi => Some(LlmProviderConfig {
-                model: "gpt-4o".to_string(),
-                url: None,
-                port: None,
-            }),
-            LlmProviders::Ollama => Some(LlmProviderConfig {
-                model: "llama3.1:latest".to_string(),
-                url: Some("http://127.0.0.1".to_string()),
-                port: Some(11434),
-            }),
-            LlmProviders::XAI => Some(LlmProviderConfig {
-                model: "grok-2-latest".to_string(),
-                url: None,
-                port: None,
-            }),
-        });
+        let provider_config = helper
+            .provider_config
+            .or_else(|| Some(LlmProviderConfig::default_for_provider(&helper.provider)));
 
         Ok(CliConfigArgs {
             provider: helper.provider,
@@ -145,6 +129,7 @@ system_prompt = "You are an AI assistant that generates random students."
                 model: "gpt-4o".to_string(),
                 url: None,
                 port: None,
+                ..Default::default()
             })
         );
     }
@@ -180,6 +165,7 @@ system_prompt = "You are an AI assistant that generates random students."
                 model: "llama3.1:latest".to_string(),
                 url: Some("http://127.0.0.1".to_string()),
                 port: Some(11434),
+                ..Default::default()
             })
         );
     }
@@ -56,11 +56,17 @@
 mod cli;
 mod providers;
 mod request;
+mod response;
+
+// The stand-in server is only used by the integration tests.
+#[cfg(test)]
+use test_support as _;
 
 pub use cli::CliConfigArgs;
 pub use llm::chat::StructuredOutputFormat;
 pub use providers::{LlmProviderConfig, LlmProviders};
 pub use request::Prompt;
+pub use response::{JsonFix, RepairedJson, repair_json};
 
 #[cfg(test)]
 mod tests {
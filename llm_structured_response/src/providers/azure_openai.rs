@@ -0,0 +1,136 @@
+//! Send structured requests to GPT models deployed through Azure OpenAI.
+//! The llm crate doesn't support Azure's URL shape or `api-key` header, so requests are sent directly.
+
+use crate::{providers::LlmProviderConfig, request::Prompt};
+use anyhow::{Error, Result};
+use api_keys::ApiKey;
+use llm::chat::StructuredOutputFormat;
+use serde_json::{Value, json};
+
+/// The API version used if none is configured.
+/// The first generally available version that supports `json_schema` response formats.
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// A chat completion request for an Azure OpenAI deployment.
+#[derive(Debug)]
+pub struct AzureOpenAiRequest {
+    url: String,
+    body: Value,
+}
+
+impl AzureOpenAiRequest {
+    /// Build the request from the provider configuration. The endpoint and deployment must be configured.
+    pub fn new(
+        config: &LlmProviderConfig,
+        schema: StructuredOutputFormat,
+        prompt: &Prompt,
+    ) -> Result<Self> {
+        let endpoint = config.endpoint.as_deref().ok_or(Error::msg(
+            "Azure OpenAI requires an `endpoint` in the provider config.",
+        ))?;
+        let deployment = config.deployment.as_deref().ok_or(Error::msg(
+            "Azure OpenAI requires a `deployment` in the provider config.",
+        ))?;
+        let api_version = config.api_version.as_deref().unwrap_or(DEFAULT_API_VERSION);
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            endpoint.trim_end_matches('/'),
+            deployment,
+            api_version
+        );
+
+        // Azure rejects explicit nulls for the optional schema fields.
+        let mut json_schema = serde_json::to_value(schema)?;
+        if let Value::Object(fields) = &mut json_schema {
+            fields.retain(|_, value| !value.is_null());
+        }
+        let body = json!({
+            "messages": [
+                { "role": "system", "content": prompt.system },
+                { "role": "user", "content": prompt.initial },
+            ],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": json_schema,
+            },
+        });
+        Ok(Self { url, body })
+    }
+
+    /// Send the request and return the content of the first choice.
+    pub async fn send(&self, api_key: &ApiKey) -> Result<String> {
+        let response = reqwest::Client::new()
+            .post(&self.url)
+            .header("api-key", api_key.expose())
+            .json(&self.body)
+            .send()
+            .await?;
+        let status = response.status();
+        let response_text = response.text().await?;
+        if !status.is_success() {
+            return Err(Error::msg(format!(
+                "Azure OpenAI request failed with status {}. Response: {:?}",
+                status, response_text
+            )));
+        }
+        let response: Value = serde_json::from_str(&response_text)?;
+        response["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(Error::msg(format!(
+                "Azure OpenAI response did not contain any content. Response: {:?}",
+                response_text
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> StructuredOutputFormat {
+        serde_json::from_value(json!({
+            "name": "Student",
+            "schema": { "type": "object" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_request_url_and_body() -> Result<()> {
+        let config = LlmProviderConfig {
+            endpoint: Some("https://my-resource.openai.azure.com/".to_string()),
+            deployment: Some("gpt-4o-deployment".to_string()),
+            ..LlmProviderConfig::default_for_provider(&crate::LlmProviders::AzureOpenAi)
+        };
+        let prompt = Prompt {
+            system: "System".to_string(),
+            initial: "Initial".to_string(),
+        };
+        let request = AzureOpenAiRequest::new(&config, schema(), &prompt)?;
+        assert_eq!(
+            request.url,
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o-deployment/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(request.body["messages"][1]["content"], "Initial");
+        assert_eq!(request.body["response_format"]["type"], "json_schema");
+        assert_eq!(
+            request.body["response_format"]["json_schema"],
+            json!({ "name": "Student", "schema": { "type": "object" } })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_deployment() {
+        let config = LlmProviderConfig {
+            endpoint: Some("https://my-resource.openai.azure.com".to_string()),
+            ..LlmProviderConfig::default_for_provider(&crate::LlmProviders::AzureOpenAi)
+        };
+        let prompt = Prompt {
+            system: "System".to_string(),
+            initial: "Initial".to_string(),
+        };
+        assert!(AzureOpenAiRequest::new(&config, schema(), &prompt).is_err());
+    }
+}
@@ -1,13 +1,19 @@
-use crate::{providers::provider_config::LlmProviderConfig, request::Prompt};
+use super::{azure_openai::AzureOpenAiRequest, ollama::OllamaClient};
+use crate::{
+    providers::provider_config::LlmProviderConfig,
+    request::Prompt,
+    response::{RepairedJson, repair_json},
+};
 use anyhow::{Error, Result};
 use clap::ValueEnum;
-use dotenvy::dotenv;
 use llm::{
     builder::{LLMBackend, LLMBuilder},
     chat::{ChatMessage, StructuredOutputFormat},
     error::LLMError,
 };
+use rate_limiter::{Limiter, RateLimits, estimate_tokens};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio::runtime::Runtime;
 
 #[derive(Debug, Clone, Deserialize, ValueEnum, PartialEq, Default, Serialize)]
@@ -16,6 +22,7 @@ pub enum LlmProviders {
     OpenAi,
     Ollama,
     XAI,
+    AzureOpenAi,
 }
 
 impl LlmProviders {
@@ -25,18 +32,33 @@ impl LlmProviders {
         schema: StructuredOutputFormat,
         prompt: &Prompt,
     ) -> Result<String> {
-        // Populate the environment variables.
-        dotenv().ok();
+        Ok(self.request_repaired_response(config, schema, prompt)?.json)
+    }
 
-        // Map the LlmProviders enum to the LLMBackend enum.
-        let (backend, api_key) = match self {
-            LlmProviders::OpenAi => (LLMBackend::OpenAI, std::env::var("OPENAI_API_KEY")?),
-            LlmProviders::Ollama => (
-                LLMBackend::Ollama,
-                std::env::var("OLLAMA_API_KEY").unwrap_or("".to_string()),
-            ),
-            LlmProviders::XAI => (LLMBackend::XAI, std::env::var("XAI_API_KEY")?),
+    /// Like `request_structured_response`, but also returns the fixes that were needed to make the response valid
+    /// JSON, so the caller can report them. There are none if the response isn't repaired.
+    pub fn request_repaired_response(
+        &self,
+        config: &LlmProviderConfig,
+        schema: StructuredOutputFormat,
+        prompt: &Prompt,
+    ) -> Result<RepairedJson> {
+        // Load the API key. Ollama instances don't always need one.
+        let api_key = match self {
+            LlmProviders::OpenAi => Some(config.api_key.load("OPENAI_API_KEY")?),
+            LlmProviders::Ollama => config.api_key.load_optional("OLLAMA_API_KEY")?,
+            LlmProviders::XAI => Some(config.api_key.load("XAI_API_KEY")?),
+            LlmProviders::AzureOpenAi => Some(config.api_key.load("AZURE_OPENAI_API_KEY")?),
+        };
+        // Keep the API key out of any error messages.
+        let redact = |e: Error| match &api_key {
+            Some(api_key) => api_key.redact_error(e),
+            None => e,
         };
+        let key: String = api_key
+            .as_ref()
+            .map(|api_key| api_key.expose().to_string())
+            .unwrap_or_default();
 
         // Build the base URL based on the URL and port, if provided.
         let base_url = config.url.clone().map(|url| {
@@ -47,50 +69,116 @@ impl LlmProviders {
             }
         });
 
-        // Build the LLM instance.
-        let llm = match backend {
-            LLMBackend::OpenAI => LLMBuilder::new()
-                .backend(backend)
-                .model(config.model.clone())
-                .api_key(api_key)
-                .stream(false)
-                .system(prompt.system.clone())
-                .schema(schema)
-                .build()?,
-            LLMBackend::Ollama => LLMBuilder::new()
-                .backend(backend)
-                .model(config.model.clone())
-                .base_url(base_url.ok_or(Error::msg("Missing base URL"))?)
-                .stream(false)
-                .system(prompt.system.clone())
-                .schema(schema)
-                .build()?,
-            LLMBackend::XAI => LLMBuilder::new()
-                .backend(backend)
-                .model(config.model.clone())
-                .api_key(api_key)
-                .stream(false)
-                .system(prompt.system.clone())
-                .schema(schema)
-                .build()?,
-            _ => return Err(Error::msg("Backend not supported")),
+        // Requests to the same provider share a limiter. Local instances are told apart by their URL.
+        let limiter_key = match &base_url {
+            Some(base_url) => format!("llm/{:?}/{}", self, base_url),
+            None => format!("llm/{:?}", self),
         };
+        if let Some(limits) = &config.rate_limits {
+            rate_limiter::configure(&limiter_key, limits.clone())?;
+        }
+        let limiter = rate_limiter::limiter(&limiter_key, RateLimits::default())?;
+        let tokens = estimate_tokens(&prompt.system) + estimate_tokens(&prompt.initial);
 
         // Send the request to the LLM provider.
+        // Providers that need features the llm crate doesn't support are sent directly, without a backend.
         let rt = Runtime::new()?;
-        let response = rt.block_on(async {
-            let initial = prompt.initial.clone();
-            tokio::spawn(async move {
-                let messages = vec![ChatMessage::user().content(initial).build()];
-                llm.chat(&messages)
-                    .await?
-                    .text()
-                    .ok_or(Error::new(LLMError::ProviderError(
-                        "Failed to get text response".to_string(),
-                    )))
-            })
-            .await?
-        })?;
-        Ok(response)
+        let response = match self {
+            LlmProviders::OpenAi => rt.block_on(chat_with_backend(
+                LLMBackend::OpenAI,
+                config,
+                key,
+                schema,
+                prompt,
+                limiter,
+                tokens,
+            )),
+            LlmProviders::XAI => rt.block_on(chat_with_backend(
+                LLMBackend::XAI,
+                config,
+                key,
+                schema,
+                prompt,
+                limiter,
+                tokens,
+            )),
+            LlmProviders::Ollama => {
+                let base_url = base_url.ok_or(Error::msg("Missing base URL"))?;
+                let client = OllamaClient::new(&base_url, api_key.clone());
+                rt.block_on(async {
+                    // Check for the model before waiting on the rate limits, since pulling can take a while.
+                    client
+                        .ensure_model(&config.model, config.pull_model.unwrap_or(true))
+                        .await?;
+                    let _permit = limiter.acquire(tokens).await?;
+                    client.chat(config, schema, prompt).await
+                })
+            }
+            LlmProviders::AzureOpenAi => {
+                let request = AzureOpenAiRequest::new(config, schema, prompt)?;
+                let api_key = api_key
+                    .as_ref()
+                    .ok_or(Error::msg("Azure OpenAI requires an API key."))?;
+                rt.block_on(async {
+                    // Wait for the provider's rate limits before sending the request.
+                    let _permit = limiter.acquire(tokens).await?;
+                    request.send(api_key).await
+                })
+            }
+        }
+        .map_err(redact)?;
+
+        // Local models don't always follow the schema strictly, so extract and repair the JSON if needed.
+        if config.repair_json.unwrap_or(self.repairs_json_by_default()) {
+            return repair_json(&response);
+        }
+        Ok(RepairedJson {
+            json: response,
+            fixes: Vec::new(),
+        })
+    }
+
+    /// Whether responses from this provider are repaired when the configuration doesn't say otherwise.
+    /// Providers that enforce the JSON schema server-side don't need it.
+    pub fn repairs_json_by_default(&self) -> bool {
+        match self {
+            LlmProviders::OpenAi | LlmProviders::XAI | LlmProviders::AzureOpenAi => false,
+            LlmProviders::Ollama => true,
+        }
     }
 }
+
+/// Send the request through one of the llm crate's backends, waiting for the provider's rate limits first.
+async fn chat_with_backend(
+    backend: LLMBackend,
+    config: &LlmProviderConfig,
+    api_key: String,
+    schema: StructuredOutputFormat,
+    prompt: &Prompt,
+    limiter: Arc<Limiter>,
+    tokens: u32,
+) -> Result<String> {
+    // Build the LLM instance.
+    let llm = LLMBuilder::new()
+        .backend(backend)
+        .model(config.model.clone())
+        .api_key(api_key)
+        .stream(false)
+        .system(prompt.system.clone())
+        .schema(schema)
+        .build()?;
+
+    let initial = prompt.initial.clone();
+    tokio::spawn(async move {
+        // Wait for the provider's rate limits before sending the request.
+        let _permit = limiter.acquire(tokens).await?;
+        let messages = vec![ChatMessage::user().content(initial).build()];
+        llm.chat(&messages)
+            .await?
+            .text()
+            .ok_or(Error::new(LLMError::ProviderError(
+                "Failed to get text response".to_string(),
+            )))
+    })
+    .await?
+}
@@ -1,4 +1,6 @@
+mod azure_openai;
 mod llm_providers;
+mod ollama;
 pub mod provider_config;
 
 pub use llm_providers::LlmProviders;
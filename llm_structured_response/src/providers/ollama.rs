@@ -0,0 +1,266 @@
+//! Send structured requests to a local Ollama instance.
+//! Requests are sent directly so that a missing model can be detected and pulled before the chat request,
+//! and so that Ollama-specific options like `num_ctx` and `keep_alive` can be passed through.
+
+use crate::{providers::LlmProviderConfig, request::Prompt};
+use anyhow::{Error, Result};
+use api_keys::ApiKey;
+use llm::chat::StructuredOutputFormat;
+use serde_json::{Map, Value, json};
+use std::io::Write;
+
+/// A client for a single Ollama instance.
+#[derive(Debug)]
+pub struct OllamaClient {
+    base_url: String,
+    api_key: Option<ApiKey>,
+    client: reqwest::Client,
+}
+
+impl OllamaClient {
+    pub fn new(base_url: &str, api_key: Option<ApiKey>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.post(format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key.expose()),
+            None => request,
+        }
+    }
+
+    /// List the names of the models that have been pulled, e.g. `llama3.1:latest`.
+    pub async fn installed_models(&self) -> Result<Vec<String>> {
+        let mut request = self.client.get(format!("{}/api/tags", self.base_url));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key.expose());
+        }
+        let response = request.send().await.map_err(|e| {
+            Error::msg(format!(
+                "Could not reach Ollama at {}. Is it running? {}",
+                self.base_url, e
+            ))
+        })?;
+        let response: Value = check_status(response).await?.json().await?;
+        Ok(response["models"]
+            .as_array()
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|model| model["name"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Make sure the model has been pulled, pulling it if allowed.
+    pub async fn ensure_model(&self, model: &str, pull: bool) -> Result<()> {
+        let installed = self.installed_models().await?;
+        if installed.iter().any(|name| same_model(name, model)) {
+            return Ok(());
+        }
+        if !pull {
+            return Err(Error::msg(format!(
+                "The Ollama model {:?} has not been pulled. Installed models: {:?}. Run `ollama pull {}` or set `pull_model = true`.",
+                model, installed, model
+            )));
+        }
+        self.pull(model).await
+    }
+
+    /// Pull a model, printing progress to stderr as it downloads.
+    pub async fn pull(&self, model: &str) -> Result<()> {
+        eprintln!("Pulling the Ollama model {:?}...", model);
+        let mut response = check_status(
+            self.post("/api/pull")
+                .json(&json!({ "model": model, "stream": true }))
+                .send()
+                .await?,
+        )
+        .await?;
+
+        // Progress is streamed as one JSON object per line.
+        let mut buffer = String::new();
+        let mut progress = PullProgress::default();
+        while let Some(chunk) = response.chunk().await? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            while let Some(newline) = buffer.find('\n') {
+                let line: String = buffer.drain(..=newline).collect();
+                progress.update(&line)?;
+            }
+        }
+        progress.update(&buffer)?;
+        eprintln!();
+        if !progress.succeeded {
+            return Err(Error::msg(format!(
+                "Ollama stopped pulling {:?} before it finished. Last status: {:?}",
+                model, progress.status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Send a chat request and return the content of the response message.
+    pub async fn chat(
+        &self,
+        config: &LlmProviderConfig,
+        schema: StructuredOutputFormat,
+        prompt: &Prompt,
+    ) -> Result<String> {
+        let response = self
+            .post("/api/chat")
+            .json(&chat_body(config, schema, prompt))
+            .send()
+            .await?;
+        let response_text = check_status(response).await?.text().await?;
+        let response: Value = serde_json::from_str(&response_text)?;
+        response["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(Error::msg(format!(
+                "Ollama response did not contain any content. Response: {:?}",
+                response_text
+            )))
+    }
+}
+
+/// Build the body of a chat request, including any Ollama-specific options.
+fn chat_body(config: &LlmProviderConfig, schema: StructuredOutputFormat, prompt: &Prompt) -> Value {
+    let mut body = json!({
+        "model": config.model,
+        "messages": [
+            { "role": "system", "content": prompt.system },
+            { "role": "user", "content": prompt.initial },
+        ],
+        "stream": false,
+        "format": schema.schema.unwrap_or(json!("json")),
+    });
+    let mut options = Map::new();
+    if let Some(num_ctx) = config.num_ctx {
+        options.insert("num_ctx".to_string(), json!(num_ctx));
+    }
+    if let Some(num_predict) = config.num_predict {
+        options.insert("num_predict".to_string(), json!(num_predict));
+    }
+    if !options.is_empty() {
+        body["options"] = Value::Object(options);
+    }
+    if let Some(keep_alive) = &config.keep_alive {
+        // Ollama reads plain numbers as seconds and everything else as a duration like "10m".
+        body["keep_alive"] = match keep_alive.parse::<i64>() {
+            Ok(seconds) => json!(seconds),
+            Err(_) => json!(keep_alive),
+        };
+    }
+    body
+}
+
+/// Whether an installed model name refers to the requested model. Ollama adds `:latest` to untagged names.
+fn same_model(installed: &str, requested: &str) -> bool {
+    installed == requested
+        || (!requested.contains(':') && installed == format!("{}:latest", requested))
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let response_text = response.text().await.unwrap_or_default();
+    Err(Error::msg(format!(
+        "Ollama request failed with status {}. Response: {:?}",
+        status, response_text
+    )))
+}
+
+/// Tracks the progress lines streamed by `/api/pull`.
+#[derive(Debug, Default)]
+struct PullProgress {
+    status: String,
+    succeeded: bool,
+}
+
+impl PullProgress {
+    fn update(&mut self, line: &str) -> Result<()> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let update: Value = serde_json::from_str(line)?;
+        if let Some(error) = update["error"].as_str() {
+            return Err(Error::msg(format!(
+                "Ollama failed to pull the model: {}",
+                error
+            )));
+        }
+        let status = update["status"].as_str().unwrap_or_default();
+        match (update["completed"].as_u64(), update["total"].as_u64()) {
+            (Some(completed), Some(total)) if total > 0 => {
+                eprint!("\r{}: {}%", status, completed * 100 / total);
+            }
+            _ if status != self.status => eprint!("\n{}", status),
+            _ => {}
+        }
+        std::io::stderr().flush().ok();
+        self.succeeded = status == "success";
+        self.status = status.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_model() {
+        assert!(same_model("llama3.1:latest", "llama3.1"));
+        assert!(same_model("llama3.1:latest", "llama3.1:latest"));
+        assert!(!same_model("llama3.1:8b", "llama3.1"));
+        assert!(!same_model("llama3.1:latest", "llama3.1:8b"));
+    }
+
+    #[test]
+    fn test_chat_body_options() {
+        let config = LlmProviderConfig {
+            num_ctx: Some(8192),
+            num_predict: Some(512),
+            keep_alive: Some("10m".to_string()),
+            ..LlmProviderConfig::default_for_provider(&crate::LlmProviders::Ollama)
+        };
+        let schema: StructuredOutputFormat = serde_json::from_value(json!({
+            "name": "Student",
+            "schema": { "type": "object" }
+        }))
+        .unwrap();
+        let prompt = Prompt {
+            system: "System".to_string(),
+            initial: "Initial".to_string(),
+        };
+        let body = chat_body(&config, schema, &prompt);
+        assert_eq!(body["model"], "llama3.1:latest");
+        assert_eq!(body["format"], json!({ "type": "object" }));
+        assert_eq!(
+            body["options"],
+            json!({ "num_ctx": 8192, "num_predict": 512 })
+        );
+        assert_eq!(body["keep_alive"], "10m");
+    }
+
+    #[test]
+    fn test_pull_progress_error() {
+        let mut progress = PullProgress::default();
+        assert!(progress.update(r#"{"status": "pulling manifest"}"#).is_ok());
+        assert!(
+            progress
+                .update(r#"{"error": "pull model manifest: file does not exist"}"#)
+                .is_err()
+        );
+        assert!(!progress.succeeded);
+    }
+}
@@ -1,5 +1,8 @@
+use super::azure_openai;
 use crate::LlmProviders;
+use api_keys::ApiKeySource;
 use clap::Args;
+use rate_limiter::RateLimits;
 use serde::{Deserialize, Serialize};
 
 /// Configuration for the LLM provider.
@@ -15,6 +18,59 @@ pub struct LlmProviderConfig {
     /// The port of the API.
     #[arg(long)]
     pub port: Option<u16>,
+    /// The Azure OpenAI resource endpoint, e.g. `https://my-resource.openai.azure.com`. Only used by Azure OpenAI.
+    #[arg(long)]
+    pub endpoint: Option<String>,
+    /// The name of the Azure OpenAI model deployment. Only used by Azure OpenAI.
+    #[arg(long)]
+    pub deployment: Option<String>,
+    /// The Azure OpenAI API version. Must support `json_schema` response formats. Only used by Azure OpenAI.
+    #[arg(long)]
+    pub api_version: Option<String>,
+    /// Whether to pull the Ollama model if it hasn't been pulled yet. Defaults to enabled. Only used by Ollama.
+    #[arg(long)]
+    pub pull_model: Option<bool>,
+    /// The size of the context window in tokens. Only used by Ollama.
+    #[arg(long)]
+    pub num_ctx: Option<u32>,
+    /// The maximum number of tokens to generate. `-1` generates until the model stops. Only used by Ollama.
+    #[arg(long, allow_hyphen_values = true)]
+    pub num_predict: Option<i32>,
+    /// How long Ollama keeps the model loaded after the request, e.g. `10m`, or `-1` to keep it loaded. Only used by Ollama.
+    #[arg(long, allow_hyphen_values = true)]
+    pub keep_alive: Option<String>,
+    /// Where to load the API key from. Defaults to the provider's usual environment variable.
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub api_key: ApiKeySource,
+    /// Whether to extract and repair malformed JSON in the response.
+    /// Defaults to enabled for local providers like Ollama, which don't always follow the schema strictly.
+    #[arg(long)]
+    pub repair_json: Option<bool>,
+    /// Limits on how many requests are sent to the provider at once and per minute.
+    /// Only configurable from a TOML file.
+    #[arg(skip)]
+    pub rate_limits: Option<RateLimits>,
+}
+
+impl Default for LlmProviderConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4o".to_string(),
+            url: None,
+            port: None,
+            endpoint: None,
+            deployment: None,
+            api_version: None,
+            pull_model: None,
+            num_ctx: None,
+            num_predict: None,
+            keep_alive: None,
+            api_key: ApiKeySource::default(),
+            repair_json: None,
+            rate_limits: None,
+        }
+    }
 }
 
 impl LlmProviderConfig {
@@ -23,18 +79,22 @@ impl LlmProviderConfig {
         match provider {
             LlmProviders::OpenAi => Self {
                 model: "gpt-4o".to_string(),
-                url: None,
-                port: None,
+                ..Self::default()
             },
             LlmProviders::Ollama => Self {
                 model: "llama3.1:latest".to_string(),
                 url: Some("http://127.0.0.1".to_string()),
                 port: Some(11434),
+                ..Self::default()
             },
             LlmProviders::XAI => Self {
                 model: "grok-2-latest".to_string(),
-                url: None,
-                port: None,
+                ..Self::default()
+            },
+            LlmProviders::AzureOpenAi => Self {
+                model: "gpt-4o".to_string(),
+                api_version: Some(azure_openai::DEFAULT_API_VERSION.to_string()),
+                ..Self::default()
             },
         }
     }
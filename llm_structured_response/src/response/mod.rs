@@ -0,0 +1,3 @@
+mod repair;
+
+pub use repair::{JsonFix, RepairedJson, repair_json};
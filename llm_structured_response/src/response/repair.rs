@@ -0,0 +1,418 @@
+//! Extract and repair JSON objects from LLM responses that don't strictly follow the requested format.
+//! Local models in particular like to wrap their JSON in markdown code fences, add a chatty preamble, or leave trailing commas behind.
+
+use anyhow::{Error, Result};
+use serde_json::Value;
+use std::fmt;
+
+/// A defect that was fixed while repairing a JSON response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonFix {
+    /// The object was wrapped in a markdown code fence.
+    StrippedCodeFence,
+    /// There was text before or after the object.
+    StrippedSurroundingText,
+    /// The object contained `//` or `/* */` comments.
+    RemovedComments,
+    /// A comma was left before a closing brace or bracket.
+    RemovedTrailingCommas,
+    /// Strings were delimited with single quotes.
+    ConvertedSingleQuotes,
+    /// Object keys were not quoted.
+    QuotedKeys,
+    /// Python or JavaScript literals like `True` or `None` were used.
+    ReplacedNonJsonLiterals,
+    /// Strings contained raw newlines or other control characters.
+    EscapedControlCharacters,
+    /// Commas were missing between values.
+    InsertedMissingCommas,
+    /// The response ended before the object was closed.
+    ClosedTruncatedObject,
+}
+
+impl fmt::Display for JsonFix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            JsonFix::StrippedCodeFence => "stripped a markdown code fence",
+            JsonFix::StrippedSurroundingText => "stripped text surrounding the JSON object",
+            JsonFix::RemovedComments => "removed comments",
+            JsonFix::RemovedTrailingCommas => "removed trailing commas",
+            JsonFix::ConvertedSingleQuotes => "converted single-quoted strings",
+            JsonFix::QuotedKeys => "quoted unquoted object keys",
+            JsonFix::ReplacedNonJsonLiterals => "replaced non-JSON literals",
+            JsonFix::EscapedControlCharacters => "escaped control characters in strings",
+            JsonFix::InsertedMissingCommas => "inserted missing commas",
+            JsonFix::ClosedTruncatedObject => "closed a truncated JSON object",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// A JSON object extracted from an LLM response, along with the fixes that were needed to make it valid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairedJson {
+    /// The valid JSON object.
+    pub json: String,
+    /// The fixes that were applied, in the order they were found. Empty if the response was already valid.
+    pub fixes: Vec<JsonFix>,
+}
+
+/// Extract the first JSON object from a response and repair common syntax defects.
+/// Only returns an error if no valid object can be recovered.
+pub fn repair_json(response: &str) -> Result<RepairedJson> {
+    // Don't touch responses that are already valid.
+    if let Ok(Value::Object(_)) = serde_json::from_str::<Value>(response.trim()) {
+        return Ok(RepairedJson {
+            json: response.trim().to_string(),
+            fixes: Vec::new(),
+        });
+    }
+
+    let mut fixes: Vec<JsonFix> = Vec::new();
+    let extracted = extract_object(response, &mut fixes)?;
+    let json = repair_syntax(&extracted, &mut fixes);
+
+    match serde_json::from_str::<Value>(&json) {
+        Ok(Value::Object(_)) => Ok(RepairedJson { json, fixes }),
+        Ok(_) => Err(Error::msg(format!(
+            "The response did not contain a JSON object. Response: {:?}",
+            response
+        ))),
+        Err(e) => Err(Error::msg(format!(
+            "Unable to repair the JSON response: {}. Response: {:?}",
+            e, response
+        ))),
+    }
+}
+
+/// Record a fix, ignoring duplicates.
+fn record(fixes: &mut Vec<JsonFix>, fix: JsonFix) {
+    if !fixes.contains(&fix) {
+        fixes.push(fix);
+    }
+}
+
+/// Find the first balanced JSON object in the response.
+/// If the response ends before the object is closed, the missing delimiters are appended.
+fn extract_object(response: &str, fixes: &mut Vec<JsonFix>) -> Result<String> {
+    let start = response.find('{').ok_or(Error::msg(format!(
+        "No JSON object found in the response. Response: {:?}",
+        response
+    )))?;
+
+    let mut closers: Vec<char> = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut end: Option<usize> = None;
+    for (i, c) in response[start..].char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+                if closers.is_empty() {
+                    end = Some(start + i + c.len_utf8());
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut object = match end {
+        Some(end) => response[start..end].to_string(),
+        None => response[start..].trim_end().to_string(),
+    };
+    let surrounding = format!(
+        "{}\n{}",
+        &response[..start],
+        end.map(|end| &response[end..]).unwrap_or_default()
+    );
+    if surrounding.contains("```") {
+        record(fixes, JsonFix::StrippedCodeFence);
+    }
+    let other_text = surrounding
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .any(|line| !line.trim().is_empty());
+    if other_text {
+        record(fixes, JsonFix::StrippedSurroundingText);
+    }
+
+    if end.is_none() {
+        record(fixes, JsonFix::ClosedTruncatedObject);
+        if let Some(q) = quote {
+            object.push(q);
+        }
+        while let Some(closer) = closers.pop() {
+            object.push(closer);
+        }
+    }
+    Ok(object)
+}
+
+/// Rewrite an extracted object so that it is valid JSON.
+fn repair_syntax(object: &str, fixes: &mut Vec<JsonFix>) -> String {
+    let chars: Vec<char> = object.chars().collect();
+    let mut out = String::with_capacity(object.len());
+    // Whether the last token written was a complete value, so a comma is needed before the next one.
+    let mut after_value = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' => {
+                if after_value {
+                    out.push(',');
+                    record(fixes, JsonFix::InsertedMissingCommas);
+                }
+                if c == '\'' {
+                    record(fixes, JsonFix::ConvertedSingleQuotes);
+                }
+                i = copy_string(&chars, i, &mut out, fixes);
+                after_value = true;
+                continue;
+            }
+            '/' if matches!(chars.get(i + 1), Some('/') | Some('*')) => {
+                record(fixes, JsonFix::RemovedComments);
+                i = skip_comment(&chars, i);
+                continue;
+            }
+            ',' => {
+                if next_significant(&chars, i + 1).is_some_and(|c| c == '}' || c == ']') {
+                    record(fixes, JsonFix::RemovedTrailingCommas);
+                } else {
+                    out.push(',');
+                }
+                after_value = false;
+            }
+            '{' | '[' => {
+                if after_value {
+                    out.push(',');
+                    record(fixes, JsonFix::InsertedMissingCommas);
+                }
+                out.push(c);
+                after_value = false;
+            }
+            '}' | ']' => {
+                out.push(c);
+                after_value = true;
+            }
+            ':' => {
+                out.push(c);
+                after_value = false;
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                if after_value {
+                    out.push(',');
+                    record(fixes, JsonFix::InsertedMissingCommas);
+                }
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || "+-.".contains(chars[i]))
+                {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                after_value = true;
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' || c == '$' => {
+                if after_value {
+                    out.push(',');
+                    record(fixes, JsonFix::InsertedMissingCommas);
+                }
+                let word_start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || "_$-".contains(chars[i])) {
+                    i += 1;
+                }
+                let word: String = chars[word_start..i].iter().collect();
+                if next_significant(&chars, i) == Some(':') {
+                    record(fixes, JsonFix::QuotedKeys);
+                    out.push_str(&format!("\"{}\"", word));
+                } else {
+                    let literal = match word.as_str() {
+                        "True" => "true",
+                        "False" => "false",
+                        "None" | "undefined" => "null",
+                        word => word,
+                    };
+                    if literal != word {
+                        record(fixes, JsonFix::ReplacedNonJsonLiterals);
+                    }
+                    out.push_str(literal);
+                }
+                after_value = true;
+                continue;
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Copy a string starting at the opening quote at `start`, rewriting it as a valid double-quoted JSON string.
+/// Returns the index after the closing quote.
+fn copy_string(chars: &[char], start: usize, out: &mut String, fixes: &mut Vec<JsonFix>) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    out.push('"');
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                match chars.get(i + 1) {
+                    // `\'` is not a valid JSON escape.
+                    Some('\'') => out.push('\''),
+                    Some(escaped) => {
+                        out.push('\\');
+                        out.push(*escaped);
+                    }
+                    None => {}
+                }
+                i += 2;
+                continue;
+            }
+            c if c == quote => {
+                out.push('"');
+                return i + 1;
+            }
+            '"' => out.push_str("\\\""),
+            '\n' => {
+                record(fixes, JsonFix::EscapedControlCharacters);
+                out.push_str("\\n");
+            }
+            '\r' => {
+                record(fixes, JsonFix::EscapedControlCharacters);
+                out.push_str("\\r");
+            }
+            '\t' => {
+                record(fixes, JsonFix::EscapedControlCharacters);
+                out.push_str("\\t");
+            }
+            c if c.is_control() => {
+                record(fixes, JsonFix::EscapedControlCharacters);
+                out.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    // Unterminated strings are closed when the object is extracted, so this is only reached for malformed input.
+    out.push('"');
+    i
+}
+
+/// Skip a `//` or `/* */` comment starting at `start`. Returns the index after the comment.
+fn skip_comment(chars: &[char], start: usize) -> usize {
+    let mut i = start + 2;
+    if chars.get(start + 1) == Some(&'/') {
+        while i < chars.len() && chars[i] != '\n' {
+            i += 1;
+        }
+        i
+    } else {
+        while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+            i += 1;
+        }
+        (i + 2).min(chars.len())
+    }
+}
+
+/// Find the next character that isn't whitespace, starting at `start`.
+fn next_significant(chars: &[char], start: usize) -> Option<char> {
+    chars[start.min(chars.len())..]
+        .iter()
+        .find(|c| !c.is_whitespace())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_json_is_untouched() -> Result<()> {
+        let response = r#"{"name": "Dog", "activity": "jumping"}"#;
+        let repaired = repair_json(response)?;
+        assert_eq!(repaired.json, response);
+        assert!(repaired.fixes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_fence_and_preamble() -> Result<()> {
+        let response = "Sure! Here is your animal:\n```json\n{\"name\": \"Dog\", \"activity\": \"jumping\"}\n```\n";
+        let repaired = repair_json(response)?;
+        assert_eq!(repaired.json, r#"{"name": "Dog", "activity": "jumping"}"#);
+        assert_eq!(
+            repaired.fixes,
+            vec![JsonFix::StrippedCodeFence, JsonFix::StrippedSurroundingText]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_commas() -> Result<()> {
+        let response = r#"{"name": "Dog", "tags": ["a", "b",],}"#;
+        let repaired = repair_json(response)?;
+        let value: Value = serde_json::from_str(&repaired.json)?;
+        assert_eq!(value["tags"][1], "b");
+        assert_eq!(repaired.fixes, vec![JsonFix::RemovedTrailingCommas]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_javascript_style_object() -> Result<()> {
+        let response = "{name: 'Dog', // the animal\n good: True, owner: None}";
+        let repaired = repair_json(response)?;
+        let value: Value = serde_json::from_str(&repaired.json)?;
+        assert_eq!(value["name"], "Dog");
+        assert_eq!(value["good"], true);
+        assert_eq!(value["owner"], Value::Null);
+        assert!(repaired.fixes.contains(&JsonFix::QuotedKeys));
+        assert!(repaired.fixes.contains(&JsonFix::ConvertedSingleQuotes));
+        assert!(repaired.fixes.contains(&JsonFix::RemovedComments));
+        assert!(repaired.fixes.contains(&JsonFix::ReplacedNonJsonLiterals));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_commas_and_newlines() -> Result<()> {
+        let response = "{\"name\": \"Dog\"\n\"description\": \"A good\ndog\"}";
+        let repaired = repair_json(response)?;
+        let value: Value = serde_json::from_str(&repaired.json)?;
+        assert_eq!(value["description"], "A good\ndog");
+        assert!(repaired.fixes.contains(&JsonFix::InsertedMissingCommas));
+        assert!(repaired.fixes.contains(&JsonFix::EscapedControlCharacters));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_object() -> Result<()> {
+        let response = r#"{"name": "Dog", "tags": ["a", "b"#;
+        let repaired = repair_json(response)?;
+        let value: Value = serde_json::from_str(&repaired.json)?;
+        assert_eq!(value["tags"][1], "b");
+        assert!(repaired.fixes.contains(&JsonFix::ClosedTruncatedObject));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unrecoverable_response() {
+        assert!(repair_json("I'm sorry, I can't help with that.").is_err());
+        assert!(repair_json(r#"{"name": "Dog" "activity" jumping}"#).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use api_keys::ApiKeySource;
+use llm_structured_response::{LlmProviderConfig, LlmProviders, Prompt, StructuredOutputFormat};
+use serde_json::{Value, from_str};
+use std::path::{Path, PathBuf};
+use test_support::StandInServer;
+
+fn schema() -> Result<StructuredOutputFormat> {
+    Ok(from_str(
+        r#"
+{
+    "name": "Student",
+    "schema": {
+        "type": "object",
+        "properties": {
+            "name": {
+                "type": "string"
+            }
+        },
+        "required": ["name"]
+    }
+}
+"#,
+    )?)
+}
+
+fn prompt() -> Prompt {
+    Prompt {
+        system: "You are an AI assistant that generates random students.".to_string(),
+        initial: "Generate a random student using the provided JSON schema.".to_string(),
+    }
+}
+
+/// Write the API key to a file, so the tests don't depend on the environment.
+fn write_key_file() -> Result<PathBuf> {
+    let key_file =
+        std::env::temp_dir().join(format!("azure-openai-test-key-{}", std::process::id()));
+    std::fs::write(&key_file, "test-azure-key")?;
+    Ok(key_file)
+}
+
+fn config(server: &StandInServer, key_file: &Path) -> LlmProviderConfig {
+    LlmProviderConfig {
+        endpoint: Some(server.url.clone()),
+        deployment: Some("test-deployment".to_string()),
+        api_key: ApiKeySource {
+            api_key_file: Some(key_file.to_path_buf()),
+            ..Default::default()
+        },
+        ..LlmProviderConfig::default_for_provider(&LlmProviders::AzureOpenAi)
+    }
+}
+
+#[test]
+fn test_azure_openai() -> Result<()> {
+    let server = StandInServer::start(vec![(
+        "/openai/deployments/test-deployment/chat/completions",
+        200,
+        r#"{"choices": [{"index": 0, "message": {"role": "assistant", "content": "{\"name\": \"Alice\"}"}}]}"#.to_string(),
+    )])?;
+    let key_file = write_key_file()?;
+
+    let response = LlmProviders::AzureOpenAi.request_structured_response(
+        &config(&server, &key_file),
+        schema()?,
+        &prompt(),
+    )?;
+    let response: Value = from_str(&response)?;
+    assert_eq!(response["name"], "Alice");
+
+    // Check that the request used Azure's URL shape, header, and response format.
+    let requests = server.requests.lock().unwrap();
+    let request = requests.first().unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(
+        request.path,
+        "/openai/deployments/test-deployment/chat/completions?api-version=2024-10-21"
+    );
+    assert_eq!(request.header("api-key"), Some("test-azure-key"));
+    let body: Value = from_str(&request.body)?;
+    assert_eq!(body["response_format"]["type"], "json_schema");
+    assert_eq!(body["response_format"]["json_schema"]["name"], "Student");
+    Ok(())
+}
+
+#[test]
+fn test_azure_openai_error_is_redacted() -> Result<()> {
+    let server = StandInServer::start(vec![(
+        "/openai/deployments/test-deployment/chat/completions",
+        401,
+        r#"{"error": {"message": "Access denied for key test-azure-key."}}"#.to_string(),
+    )])?;
+    let key_file = write_key_file()?;
+
+    let error = LlmProviders::AzureOpenAi
+        .request_structured_response(&config(&server, &key_file), schema()?, &prompt())
+        .unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("401"));
+    assert!(!message.contains("test-azure-key"));
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use llm_structured_response::{
+    JsonFix, LlmProviderConfig, LlmProviders, Prompt, StructuredOutputFormat,
+};
+use serde_json::{Value, from_str};
+use test_support::StandInServer;
+
+const TAGS: &str = r#"{"models": [{"name": "llama3.1:latest"}]}"#;
+const CHAT: &str = r#"{"model": "llama3.1:latest", "message": {"role": "assistant", "content": "{\"name\": \"Alice\"}"}, "done": true}"#;
+
+fn schema() -> Result<StructuredOutputFormat> {
+    Ok(from_str(
+        r#"
+{
+    "name": "Student",
+    "schema": {
+        "type": "object",
+        "properties": {
+            "name": {
+                "type": "string"
+            }
+        },
+        "required": ["name"]
+    }
+}
+"#,
+    )?)
+}
+
+fn prompt() -> Prompt {
+    Prompt {
+        system: "You are an AI assistant that generates random students.".to_string(),
+        initial: "Generate a random student using the provided JSON schema.".to_string(),
+    }
+}
+
+fn config(server: &StandInServer, model: &str) -> LlmProviderConfig {
+    LlmProviderConfig {
+        model: model.to_string(),
+        url: Some(server.url.clone()),
+        port: None,
+        ..LlmProviderConfig::default_for_provider(&LlmProviders::Ollama)
+    }
+}
+
+fn paths(server: &StandInServer) -> Vec<String> {
+    server
+        .requests
+        .lock()
+        .map(|requests| {
+            requests
+                .iter()
+                .map(|request| request.path.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_ollama_installed_model() -> Result<()> {
+    let server = StandInServer::start(vec![
+        ("/api/tags", 200, TAGS.to_string()),
+        ("/api/chat", 200, CHAT.to_string()),
+    ])?;
+    let config = LlmProviderConfig {
+        num_ctx: Some(8192),
+        keep_alive: Some("-1".to_string()),
+        ..config(&server, "llama3.1")
+    };
+
+    let response =
+        LlmProviders::Ollama.request_structured_response(&config, schema()?, &prompt())?;
+    let response: Value = from_str(&response)?;
+    assert_eq!(response["name"], "Alice");
+    assert_eq!(paths(&server), vec!["/api/tags", "/api/chat"]);
+
+    // Check that the Ollama-specific options were sent.
+    let requests = server.requests.lock().unwrap();
+    let body: Value = from_str(&requests[1].body)?;
+    assert_eq!(body["options"]["num_ctx"], 8192);
+    assert_eq!(body["keep_alive"], -1);
+    assert_eq!(body["format"]["required"][0], "name");
+    Ok(())
+}
+
+#[test]
+fn test_ollama_repaired_response() -> Result<()> {
+    let fenced = r#"{"model": "llama3.1:latest", "message": {"role": "assistant", "content": "```json\n{\"name\": \"Alice\",}\n```"}, "done": true}"#;
+    let server = StandInServer::start(vec![
+        ("/api/tags", 200, TAGS.to_string()),
+        ("/api/chat", 200, fenced.to_string()),
+    ])?;
+
+    // The fixes are returned to the caller instead of being printed.
+    let repaired = LlmProviders::Ollama.request_repaired_response(
+        &config(&server, "llama3.1"),
+        schema()?,
+        &prompt(),
+    )?;
+    let response: Value = from_str(&repaired.json)?;
+    assert_eq!(response["name"], "Alice");
+    assert_eq!(
+        repaired.fixes,
+        vec![JsonFix::StrippedCodeFence, JsonFix::RemovedTrailingCommas]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ollama_pulls_missing_model() -> Result<()> {
+    let pull = [
+        r#"{"status": "pulling manifest"}"#,
+        r#"{"status": "pulling 8eeb52dfb3bb", "digest": "sha256:8eeb52dfb3bb", "total": 100, "completed": 50}"#,
+        r#"{"status": "pulling 8eeb52dfb3bb", "digest": "sha256:8eeb52dfb3bb", "total": 100, "completed": 100}"#,
+        r#"{"status": "success"}"#,
+    ]
+    .join("\n");
+    let server = StandInServer::start(vec![
+        ("/api/tags", 200, TAGS.to_string()),
+        ("/api/pull", 200, pull),
+        ("/api/chat", 200, CHAT.to_string()),
+    ])?;
+
+    LlmProviders::Ollama.request_structured_response(
+        &config(&server, "qwen2.5:7b"),
+        schema()?,
+        &prompt(),
+    )?;
+    assert_eq!(paths(&server), vec!["/api/tags", "/api/pull", "/api/chat"]);
+    let requests = server.requests.lock().unwrap();
+    let body: Value = from_str(&requests[1].body)?;
+    assert_eq!(body["model"], "qwen2.5:7b");
+    Ok(())
+}
+
+#[test]
+fn test_ollama_missing_model_without_pull() -> Result<()> {
+    let server = StandInServer::start(vec![("/api/tags", 200, TAGS.to_string())])?;
+    let config = LlmProviderConfig {
+        pull_model: Some(false),
+        ..config(&server, "qwen2.5:7b")
+    };
+
+    let error = LlmProviders::Ollama
+        .request_structured_response(&config, schema()?, &prompt())
+        .unwrap_err();
+    assert!(error.to_string().contains("ollama pull qwen2.5:7b"));
+    assert_eq!(paths(&server), vec!["/api/tags"]);
+    Ok(())
+}
+
+#[test]
+fn test_ollama_pull_error() -> Result<()> {
+    let server = StandInServer::start(vec![
+        ("/api/tags", 200, TAGS.to_string()),
+        (
+            "/api/pull",
+            200,
+            r#"{"error": "pull model manifest: file does not exist"}"#.to_string(),
+        ),
+    ])?;
+
+    let error = LlmProviders::Ollama
+        .request_structured_response(&config(&server, "not-a-model"), schema()?, &prompt())
+        .unwrap_err();
+    assert!(error.to_string().contains("file does not exist"));
+    Ok(())
+}
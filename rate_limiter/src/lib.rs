@@ -0,0 +1,28 @@
+//! Limits how many requests are sent to each AI provider, both at once and per minute.
+//!
+//! Limiters are shared across the whole process and keyed by provider, so that every request to the same provider waits its turn no matter where it comes from.
+//!
+//! # Example
+//!
+//! ```rust
+//! use rate_limiter::RateLimits;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let limits = RateLimits {
+//!     max_in_flight: Some(1),
+//!     requests_per_minute: Some(5),
+//!     tokens_per_minute: None,
+//! };
+//! rate_limiter::configure("images/StableDiffusion", limits).unwrap();
+//! let limiter = rate_limiter::limiter("images/StableDiffusion", RateLimits::default()).unwrap();
+//! let permit = limiter.acquire(0).await.unwrap();
+//! // Send the request while holding the permit.
+//! drop(permit);
+//! # });
+//! ```
+
+#![deny(unused_crate_dependencies)]
+
+mod limiter;
+
+pub use limiter::{Limiter, Permit, RateLimits, configure, estimate_tokens, limiter};
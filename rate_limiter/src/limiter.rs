@@ -0,0 +1,251 @@
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+/// The length of the window that per-minute limits are measured over.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits for requests sent to a single provider. Limits that aren't set are not enforced.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct RateLimits {
+    /// The maximum number of requests that can be running at once.
+    pub max_in_flight: Option<usize>,
+    /// The maximum number of requests that can be started per minute.
+    pub requests_per_minute: Option<u32>,
+    /// The maximum number of tokens that can be sent per minute.
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    /// Check that every limit that is set lets requests through. A limit of 0 would block every request,
+    /// so it's rejected rather than quietly treated as 1.
+    pub fn validate(&self) -> Result<()> {
+        let zero = [
+            ("max_in_flight", self.max_in_flight == Some(0)),
+            ("requests_per_minute", self.requests_per_minute == Some(0)),
+            ("tokens_per_minute", self.tokens_per_minute == Some(0)),
+        ];
+        match zero.iter().find(|(_, is_zero)| *is_zero) {
+            Some((name, _)) => Err(Error::msg(format!(
+                "The rate limit {} must be at least 1. Leave it unset to not limit requests.",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Limits requests to a single provider.
+#[derive(Debug)]
+pub struct Limiter {
+    limits: RateLimits,
+    in_flight: Option<Arc<Semaphore>>,
+    /// The start time and token count of each request sent in the last minute.
+    history: Mutex<VecDeque<(Instant, u32)>>,
+}
+
+/// Permission to send a request. The request counts as in flight until the permit is dropped.
+#[derive(Debug)]
+pub struct Permit {
+    _in_flight: Option<OwnedSemaphorePermit>,
+}
+
+impl Limiter {
+    /// Create a limiter that enforces the given limits. Returns an error if a limit is 0.
+    pub fn new(limits: RateLimits) -> Result<Self> {
+        limits.validate()?;
+        Ok(Self {
+            in_flight: limits
+                .max_in_flight
+                .map(|max| Arc::new(Semaphore::new(max))),
+            limits,
+            history: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// The limits enforced by this limiter.
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Wait until a request using the given number of tokens can be sent.
+    pub async fn acquire(&self, tokens: u32) -> Result<Permit> {
+        let in_flight = match &self.in_flight {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await?),
+            None => None,
+        };
+        while let Some(wait) = self.reserve(tokens)? {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(Permit {
+            _in_flight: in_flight,
+        })
+    }
+
+    /// Record the request if the per-minute limits allow it, otherwise return how long to wait before trying again.
+    fn reserve(&self, tokens: u32) -> Result<Option<Duration>> {
+        let mut history = self
+            .history
+            .lock()
+            .map_err(|_| Error::msg("The rate limiter history is poisoned."))?;
+        let now = Instant::now();
+        while history
+            .front()
+            .is_some_and(|(start, _)| now.duration_since(*start) >= WINDOW)
+        {
+            history.pop_front();
+        }
+
+        let over_requests = self
+            .limits
+            .requests_per_minute
+            .is_some_and(|max| history.len() >= max as usize);
+        // A single request larger than the token limit is allowed through on its own, since it could never be sent otherwise.
+        let used_tokens: u32 = history.iter().map(|(_, tokens)| tokens).sum();
+        let over_tokens = self
+            .limits
+            .tokens_per_minute
+            .is_some_and(|max| !history.is_empty() && used_tokens.saturating_add(tokens) > max);
+
+        match history.front() {
+            Some((oldest, _)) if over_requests || over_tokens => {
+                Ok(Some(WINDOW.saturating_sub(now.duration_since(*oldest))))
+            }
+            _ => {
+                history.push_back((now, tokens));
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The limiters for every provider, keyed by provider.
+fn registry() -> &'static Mutex<HashMap<String, Arc<Limiter>>> {
+    static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<Limiter>>>> = OnceLock::new();
+    LIMITERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Set the limits for a provider. Returns an error if a limit is 0.
+/// The existing limiter is kept if the limits haven't changed, so requests already in flight are still counted.
+pub fn configure(key: &str, limits: RateLimits) -> Result<()> {
+    // A poisoned registry only means another thread panicked while holding the lock; the map itself is still usable.
+    let mut limiters = registry().lock().unwrap_or_else(|e| e.into_inner());
+    if limiters
+        .get(key)
+        .is_some_and(|limiter| limiter.limits == limits)
+    {
+        return Ok(());
+    }
+    limiters.insert(key.to_string(), Arc::new(Limiter::new(limits)?));
+    Ok(())
+}
+
+/// Get the limiter for a provider, creating it with the default limits if the provider hasn't been configured.
+pub fn limiter(key: &str, default: RateLimits) -> Result<Arc<Limiter>> {
+    let mut limiters = registry().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(limiter) = limiters.get(key) {
+        return Ok(limiter.clone());
+    }
+    let limiter = Arc::new(Limiter::new(default)?);
+    limiters.insert(key.to_string(), limiter.clone());
+    Ok(limiter)
+}
+
+/// Roughly estimate the number of tokens in a piece of text, for use with `tokens_per_minute`.
+/// Uses the common rule of thumb of four characters per token.
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_in_flight() -> Result<()> {
+        let limiter = Limiter::new(RateLimits {
+            max_in_flight: Some(1),
+            ..Default::default()
+        })?;
+        let first = limiter.acquire(0).await?;
+        // The second request has to wait until the first one is finished.
+        let second = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(0)).await;
+        assert!(second.is_err());
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(0)).await;
+        assert!(second.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute() -> Result<()> {
+        let limiter = Limiter::new(RateLimits {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        })?;
+        let start = Instant::now();
+        limiter.acquire(0).await?;
+        limiter.acquire(0).await?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The third request has to wait for the window to move past the first one.
+        limiter.acquire(0).await?;
+        assert!(start.elapsed() >= WINDOW);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokens_per_minute() -> Result<()> {
+        let limiter = Limiter::new(RateLimits {
+            tokens_per_minute: Some(100),
+            ..Default::default()
+        })?;
+        let start = Instant::now();
+        // A request over the limit on its own is still allowed through.
+        limiter.acquire(150).await?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        limiter.acquire(10).await?;
+        assert!(start.elapsed() >= WINDOW);
+        Ok(())
+    }
+
+    #[test]
+    fn test_configure_keeps_unchanged_limiter() -> Result<()> {
+        let limits = RateLimits {
+            max_in_flight: Some(1),
+            ..Default::default()
+        };
+        configure("test/configure", limits.clone())?;
+        let first = limiter("test/configure", RateLimits::default())?;
+        configure("test/configure", limits)?;
+        let second = limiter("test/configure", RateLimits::default())?;
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.limits().max_in_flight, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_limits_are_rejected() {
+        let zero_in_flight = RateLimits {
+            max_in_flight: Some(0),
+            ..Default::default()
+        };
+        let error = configure("test/zero", zero_in_flight).unwrap_err();
+        assert!(error.to_string().contains("max_in_flight"));
+        let zero_requests = RateLimits {
+            requests_per_minute: Some(0),
+            ..Default::default()
+        };
+        assert!(Limiter::new(zero_requests).is_err());
+        assert!(RateLimits::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+}
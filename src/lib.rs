@@ -1,4 +1,5 @@
 use ai_images::cli::GenerationParameters;
+use ai_images::{CancellationToken, Cancelled, GeneratedImage, ImageOperation, Progress};
 use anyhow::{Error, Result};
 use ex::fs;
 pub use llm_structured_response::{
@@ -70,16 +71,25 @@ impl AssetConfig {
         };
 
         // Send the initial prompt to the LLM API to get a structured response
-        let llm_structured_response = self
-            .llm_structured_response
-            .provider
-            .request_structured_response(&config, schema, &prompt)?;
+        let provider = &self.llm_structured_response.provider;
+        let llm_structured_response =
+            provider.request_repaired_response(&config, schema, &prompt)?;
+        for fix in &llm_structured_response.fixes {
+            eprintln!("Repaired the {:?} response: {}", provider, fix);
+        }
 
-        Ok(llm_structured_response)
+        Ok(llm_structured_response.json)
     }
 
-    /// Generate an image based on the structured response
-    fn generate_image(&self, prompt_from_response: &str) -> Result<PathBuf> {
+    /// Generate images based on the structured response. Returns one image per generated variant.
+    /// The structured response is saved with queued Stable Diffusion tasks, so the asset can be finished if they are resumed.
+    fn generate_images(
+        &self,
+        prompt_from_response: &str,
+        structured_response: &Value,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<GeneratedImage>> {
         // Initialize the provider.
         let provider = self.ai_images.provider.to_image_provider()?;
         // Set up the prompt.
@@ -93,13 +103,26 @@ impl AssetConfig {
         // We need to do this since the configuration TOML file can define prefixes, suffixes, etc. which cannot be passed from the command line, and which are not part of the structured response.
         let mut image_params: ai_images::ImageParams = self.ai_images.params.clone();
         image_params.prompt = image_prompt;
-        // Generate the image.
+        image_params.task_context = structured_response.clone();
+        // Generate the images.
         let rt = Runtime::new()?;
-        let image = rt.block_on(async {
+        let images = rt.block_on(async {
             let image_params = image_params.clone();
-            tokio::spawn(async move { provider.generate_image(image_params).await }).await?
+            let progress = progress.clone();
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                provider
+                    .run(
+                        image_params,
+                        ImageOperation::TextToImage,
+                        &progress,
+                        &cancel,
+                    )
+                    .await
+            })
+            .await?
         })?;
-        Ok(image)
+        Ok(images)
     }
 
     /// Fill the markdown template with the image and the structured response
@@ -118,6 +141,10 @@ impl AssetConfig {
 pub struct Asset {
     pub markdown: PathBuf,
     pub image: Option<PathBuf>,
+    /// Every image variant that was generated, including `image`.
+    pub variants: Vec<PathBuf>,
+    /// The thumbnails of `image`, in the order of the configured thumbnail sizes.
+    pub thumbnails: Vec<PathBuf>,
 }
 
 impl Asset {
@@ -128,6 +155,22 @@ impl Asset {
     }
 
     pub fn from_config(config: &AssetConfig, user_prompt: Option<&str>) -> Result<Asset> {
+        Asset::from_config_with_progress_and_cancel(
+            config,
+            user_prompt,
+            &Progress::default(),
+            &CancellationToken::default(),
+        )
+    }
+
+    /// Generate an asset, sending the image provider's progress events to `progress` while the image renders.
+    /// Triggering `cancel` stops the run with a `Cancelled` error and stops the image job on the provider.
+    pub fn from_config_with_progress_and_cancel(
+        config: &AssetConfig,
+        user_prompt: Option<&str>,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Asset> {
         let initial_prompt = match user_prompt {
             Some(prompt) => prompt.to_string(),
             _ => config.generate_random_phrase()?,
@@ -135,34 +178,102 @@ impl Asset {
 
         let llm_structured_response = config.generate_structured_response(&initial_prompt)?;
         let llm_structured_response: Value = serde_json::from_str(&llm_structured_response)?;
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
 
         // Generate an image based on the structured response and save it
         let image_prompt: &Value = llm_structured_response
             .get("image_prompt")
             .unwrap_or(&Value::Null);
-        let image_path: Option<PathBuf> = match image_prompt {
-            Value::String(prompt) => Some(config.generate_image(prompt)?),
-            _ => None,
-        };
-        // Strip the image path to the filename
-        let image_filename: Option<String> = match &image_path {
-            Some(image_path) => image_path
-                .file_name()
-                .ok_or(Error::msg("Unable to get the image filename."))?
-                .to_string_lossy()
-                .to_string()
-                .parse()
-                .ok(),
-            _ => None,
+        let images: Vec<GeneratedImage> = match image_prompt {
+            Value::String(prompt) => {
+                config.generate_images(prompt, &llm_structured_response, progress, cancel)?
+            }
+            _ => Vec::new(),
         };
+        Asset::from_response_and_images(config, &llm_structured_response, images)
+    }
+
+    /// Finish the assets whose images were queued on Stable Diffusion by an earlier run but never collected,
+    /// e.g. because it timed out or exited first. Tasks that are still running are waited for.
+    /// Returns one result per task, so one failed task doesn't stop the others from being finished.
+    pub fn resume(
+        config: &AssetConfig,
+        progress: &Progress,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Result<Asset>>> {
+        let provider = config.ai_images.provider.to_image_provider()?;
+        let output_directory = config.ai_images.params.output_directory.clone();
+        let rt = Runtime::new()?;
+        let resumed = rt.block_on(async {
+            let progress = progress.clone();
+            let cancel = cancel.clone();
+            tokio::spawn(
+                async move { provider.resume(&output_directory, &progress, &cancel).await },
+            )
+            .await?
+        })?;
+        Ok(resumed
+            .into_iter()
+            .map(|resumed| {
+                let task_id = resumed.task.task_id;
+                let images = resumed.images.map_err(|e| {
+                    Error::msg(format!("Task {} could not be resumed: {}", task_id, e))
+                })?;
+                // Tasks queued with ai_images directly have no structured response to finish an asset with.
+                if !resumed.task.context.is_object() {
+                    return Err(Error::msg(format!(
+                        "Task {} was not queued for an asset, so only its images were saved: {:?}",
+                        task_id,
+                        images.iter().map(|image| &image.path).collect::<Vec<_>>()
+                    )));
+                }
+                Asset::from_response_and_images(config, &resumed.task.context, images)
+            })
+            .collect())
+    }
+
+    /// Link the generated images from the structured response, then fill the markdown template with it and save it.
+    fn from_response_and_images(
+        config: &AssetConfig,
+        llm_structured_response: &Value,
+        images: Vec<GeneratedImage>,
+    ) -> Result<Asset> {
+        let variants: Vec<PathBuf> = images.iter().map(|image| image.path.clone()).collect();
+        // The first variant is the main image.
+        let image_path: Option<PathBuf> = variants.first().cloned();
+        let thumbnails: Vec<PathBuf> = images
+            .first()
+            .map(|image| image.thumbnails.clone())
+            .unwrap_or_default();
+        // Strip the image paths to the filenames
+        let image_filenames: Vec<String> = file_names(&variants)?;
+        let thumbnail_filenames: Vec<String> = file_names(&thumbnails)?;
         // Add the image name to the structured response
         let mut llm_structured_response: Map<String, Value> = llm_structured_response
             .as_object()
             .ok_or(Error::msg("Unable to convert response to an object."))?
             .clone();
-        if let Some(image_filename) = image_filename {
-            llm_structured_response
-                .insert("image_file_name".to_string(), Value::String(image_filename));
+        if let Some(image_filename) = image_filenames.first() {
+            llm_structured_response.insert(
+                "image_file_name".to_string(),
+                Value::String(image_filename.clone()),
+            );
+            llm_structured_response.insert(
+                "image_file_names".to_string(),
+                Value::from(image_filenames.clone()),
+            );
+        }
+        if let Some(thumbnail_filename) = thumbnail_filenames.first() {
+            llm_structured_response.insert(
+                "thumbnail_file_name".to_string(),
+                Value::String(thumbnail_filename.clone()),
+            );
+            llm_structured_response.insert(
+                "thumbnail_file_names".to_string(),
+                Value::from(thumbnail_filenames.clone()),
+            );
         }
 
         // Fill the markdown template with the image and the structured response
@@ -201,17 +312,41 @@ impl Asset {
         }
 
         // Save the markdown to a file. The filename is the current unix timestamp
-        let markdown_file_path = output_dir.join(format!("{}.md", chrono::Utc::now().timestamp()));
+        let markdown_file_path = markdown_path(&output_dir, chrono::Utc::now().timestamp());
         fs::write(&markdown_file_path, &markdown)?;
 
         // Return the markdown and the image path
         Ok(Asset {
             markdown: markdown_file_path,
             image: image_path,
+            variants,
+            thumbnails,
         })
     }
 }
 
+/// The path to save an asset's markdown to, named after the timestamp.
+/// If another asset was already saved in the same second, e.g. when resuming several at once, a number is appended.
+fn markdown_path(output_dir: &Path, timestamp: i64) -> PathBuf {
+    std::iter::once(format!("{}.md", timestamp))
+        .chain((1..).map(|number| format!("{}-{}.md", timestamp, number)))
+        .map(|name| output_dir.join(name))
+        .find(|path| !path.exists())
+        .unwrap_or_else(|| output_dir.join(format!("{}.md", timestamp)))
+}
+
+/// Strip the paths to their filenames, for linking from the markdown.
+fn file_names(paths: &[PathBuf]) -> Result<Vec<String>> {
+    paths
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .ok_or(Error::msg("Unable to get the image filename."))
+                .map(|filename| filename.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,6 +354,20 @@ mod tests {
     use serial_test::serial;
     use tempfile::{TempDir, tempdir};
 
+    #[test]
+    fn test_markdown_path() -> Result<()> {
+        let dir = tempdir()?;
+        let first = markdown_path(dir.path(), 1735689600);
+        assert_eq!(first, dir.path().join("1735689600.md"));
+        fs::write(&first, "")?;
+        assert_eq!(
+            markdown_path(dir.path(), 1735689600),
+            dir.path().join("1735689600-1.md")
+        );
+        dir.close()?;
+        Ok(())
+    }
+
     #[cfg(test)]
     mod default_config {
         use super::*;
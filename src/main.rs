@@ -1,11 +1,79 @@
-use ai_asset_generator::Asset;
-use anyhow::Result;
-use clap::Parser;
+use ai_asset_generator::{Asset, AssetConfig};
+use ai_images::{CancellationToken, Progress};
+use anyhow::{Error, Result};
+use clap::{Parser, Subcommand};
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 fn main() -> Result<()> {
     let args = AssetCli::parse();
-    let asset = Asset::from_config_file_and_prompt(&args.config_file, args.prompt.as_deref())?;
+    // Draw the image provider's progress on a single line of stderr, so stdout only has the JSON output.
+    let drawn = Arc::new(AtomicBool::new(false));
+    let progress = {
+        let drawn = drawn.clone();
+        Progress::new(move |event| {
+            drawn.store(true, Ordering::Relaxed);
+            eprint!("\r\x1b[2K{}", event);
+            let _ = std::io::stderr().flush();
+        })
+    };
+    // The first Ctrl-C stops the image job on the provider before exiting, and a second one exits right away.
+    let cancel = CancellationToken::new();
+    {
+        let cancel = cancel.clone();
+        ctrlc::set_handler(move || {
+            if cancel.is_cancelled() {
+                std::process::exit(130);
+            }
+            eprintln!("\nCancelling...");
+            cancel.cancel();
+        })?;
+    }
+
+    if let Some(Command::Resume { config_file }) = &args.command {
+        let config = AssetConfig::from_toml_file(config_file)?;
+        let assets = Asset::resume(&config, &progress, &cancel);
+        if drawn.load(Ordering::Relaxed) {
+            eprintln!();
+        }
+        // Print every finished asset as a line of JSON, and the tasks that failed to stderr.
+        let assets = assets?;
+        let total = assets.len();
+        let mut failed = 0;
+        for asset in assets {
+            match asset {
+                Ok(asset) => println!("{}", serde_json::to_string(&asset)?),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(Error::msg(format!(
+                "{} of {} queued tasks could not be resumed.",
+                failed, total
+            )));
+        }
+        return Ok(());
+    }
+
+    let config_file = args.config_file.ok_or(Error::msg(
+        "The path to the configuration file must be provided.",
+    ))?;
+    let config = AssetConfig::from_toml_file(&config_file)?;
+    let asset = Asset::from_config_with_progress_and_cancel(
+        &config,
+        args.prompt.as_deref(),
+        &progress,
+        &cancel,
+    );
+    if drawn.load(Ordering::Relaxed) {
+        eprintln!();
+    }
+    let asset = asset?;
     // Print the paths to the generated asset as a JSON string
     println!("{}", serde_json::to_string(&asset)?);
     Ok(())
@@ -13,11 +81,29 @@ fn main() -> Result<()> {
 
 /// Generate an asset based on the configuration file
 #[derive(Parser)]
-#[command(about)]
+#[command(
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct AssetCli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the configuration file
-    config_file: PathBuf,
+    #[arg(required = true)]
+    config_file: Option<PathBuf>,
 
     /// Optional prompt to generate the asset
     prompt: Option<String>,
 }
+
+#[derive(Subcommand)]
+enum Command {
+    /// Finish the assets whose images were queued on Stable Diffusion by an earlier run but never collected,
+    /// e.g. because it timed out or was stopped. Prints each finished asset as a line of JSON.
+    Resume {
+        /// Path to the configuration file the assets were generated with
+        config_file: PathBuf,
+    },
+}
@@ -0,0 +1,133 @@
+//! Helpers shared by the workspace's integration tests.
+//!
+//! [`StandInServer`] is a minimal HTTP server that stands in for a provider's API, so requests can be tested without
+//! a real account or a local instance.
+
+#![deny(unused_crate_dependencies)]
+
+use anyhow::Result;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A request received by the stand-in server.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ReceivedRequest {
+    /// Get the value of a header, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A stand-in server that answers each request with the first route whose path prefix matches.
+pub struct StandInServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl StandInServer {
+    /// Start the server on a random local port. Each route is a path prefix, a status code, and a response body.
+    /// Bodies can be strings or bytes, so that routes can serve images.
+    pub fn start(routes: Vec<(&'static str, u16, impl Into<Vec<u8>>)>) -> Result<Self> {
+        Self::start_with_delay(routes, None)
+    }
+
+    /// Like `start`, but waits before answering requests to one path prefix, like a provider that takes a while
+    /// to render. Other requests are still answered while it waits.
+    pub fn start_with_delay(
+        routes: Vec<(&'static str, u16, impl Into<Vec<u8>>)>,
+        delay: Option<(&'static str, Duration)>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let routes: Arc<Vec<(&str, u16, Vec<u8>)>> = Arc::new(
+            routes
+                .into_iter()
+                .map(|(prefix, status, body)| (prefix, status, body.into()))
+                .collect(),
+        );
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let routes = routes.clone();
+                let received = received.clone();
+                std::thread::spawn(move || {
+                    let Some(request) = read_request(&mut stream) else {
+                        return;
+                    };
+                    let (status, body) = routes
+                        .iter()
+                        .find(|(prefix, _, _)| request.path.starts_with(prefix))
+                        .map(|(_, status, body)| (*status, body.clone()))
+                        .unwrap_or((404, b"{\"error\": \"not found\"}".to_vec()));
+                    if let Some((prefix, delay)) = delay
+                        && request.path.starts_with(prefix)
+                    {
+                        std::thread::sleep(delay);
+                    }
+                    if let Ok(mut received) = received.lock() {
+                        received.push(request);
+                    }
+                    let head = format!(
+                        "HTTP/1.1 {} Stand-In\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    stream.write_all(head.as_bytes()).ok();
+                    stream.write_all(&body).ok();
+                });
+            }
+        });
+        Ok(Self { url, requests })
+    }
+}
+
+fn read_request(stream: &mut std::net::TcpStream) -> Option<ReceivedRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let content_length: usize = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(ReceivedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}